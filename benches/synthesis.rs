@@ -6,41 +6,79 @@ use bellperson::{Circuit, ConstraintSystem, SynthesisError};
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use ff::Field;
 use generic_array::typenum;
-use neptune::circuit::poseidon_hash;
+use neptune::circuit::{
+    poseidon_hash_elts, poseidon_hash_in_mode, CircuitTemplate, Elt, SynthesisMode,
+    WitnessGenerator,
+};
 use neptune::*;
 use rand::thread_rng;
 use std::marker::PhantomData;
 
+/// How `BenchCircuit` hashes.
+#[derive(Clone, Copy)]
+enum Variant {
+    /// With `poseidon_hash_elts`.
+    Synthesis,
+    /// With a `CircuitTemplate` built once. Its speed-up is the ratio of the `Poseidon Synthesis` time to this one.
+    Template,
+    /// With `poseidon_hash_in_mode` in `SynthesisMode::WitnessOnly`, which needs a `WitnessGenerator` rather than any
+    /// constraint system, so is not synthesized through `Circuit`.
+    Witness,
+}
+
 struct BenchCircuit<A: Arity<Fr>> {
     n: usize,
+    variant: Variant,
     _a: PhantomData<A>,
 }
 
-impl<A: Arity<Fr>> Circuit<Bls12> for BenchCircuit<A> {
-    fn synthesize<CS: ConstraintSystem<Bls12>>(
-        self,
-        mut cs: &mut CS,
-    ) -> Result<(), SynthesisError> {
+impl<A: Arity<Fr>> BenchCircuit<A> {
+    /// Allocate `n` random preimages and hash each with `hash`.
+    fn hash_all<CS, H>(&self, cs: &mut CS, mut hash: H) -> Result<(), SynthesisError>
+    where
+        CS: ConstraintSystem<Bls12>,
+        H: FnMut(&mut CS, Vec<Elt<Bls12>>) -> Result<AllocatedNum<Bls12>, SynthesisError>,
+    {
         let mut rng = thread_rng();
         let arity = A::to_usize();
-        let constants = PoseidonConstants::<Bls12, A>::new();
 
         for _ in 0..self.n {
             let mut i = 0;
             let mut fr_data = vec![Fr::random(&mut rng); arity];
-            let data: Vec<AllocatedNum<Bls12>> = (0..arity)
+            let data: Vec<Elt<Bls12>> = (0..arity)
                 .enumerate()
                 .map(|_| {
                     let fr = Fr::random(&mut rng);
                     fr_data[i] = fr;
                     i += 1;
-                    AllocatedNum::alloc(cs.namespace(|| format!("data {}", i)), || Ok(fr)).unwrap()
+                    AllocatedNum::alloc(cs.namespace(|| format!("data {}", i)), || Ok(fr))
+                        .unwrap()
+                        .into()
                 })
                 .collect::<Vec<_>>();
-            let _ = poseidon_hash(&mut cs, data, &constants).expect("poseidon hashing failed");
+            let _ = hash(cs, data).expect("poseidon hashing failed");
         }
         Ok(())
     }
+
+    fn synthesize_witness(self, cs: &mut WitnessGenerator<Bls12>) -> Result<(), SynthesisError> {
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        self.hash_all(cs, |cs, data| {
+            poseidon_hash_in_mode(cs, data, &constants, SynthesisMode::WitnessOnly)
+        })
+    }
+}
+
+impl<A: Arity<Fr>> Circuit<Bls12> for BenchCircuit<A> {
+    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let template = CircuitTemplate::new(&constants);
+        let variant = self.variant;
+        self.hash_all(cs, |cs, data| match variant {
+            Variant::Template => template.hash_elts(cs, data),
+            _ => poseidon_hash_elts(cs, data, &constants),
+        })
+    }
 }
 
 fn bench_synthesis<A>(c: &mut Criterion)
@@ -52,23 +90,36 @@ where
     let mut num_hashes = 1;
 
     for _ in 0..4 {
-        group.bench_with_input(
-            BenchmarkId::new(
-                "Poseidon Synthesis",
-                format!("arity: {}, count: {}", A::to_usize(), num_hashes),
-            ),
-            &num_hashes,
-            |b, n| {
-                b.iter(|| {
-                    let mut cs = BenchCS::<Bls12>::new();
-                    let circuit = BenchCircuit::<A> {
-                        n: *n,
-                        _a: PhantomData::<A>,
-                    };
-                    circuit.synthesize(&mut cs)
-                })
-            },
-        );
+        for (name, variant) in [
+            ("Poseidon Synthesis", Variant::Synthesis),
+            ("Poseidon Template", Variant::Template),
+            ("Poseidon Witness", Variant::Witness),
+        ]
+        .iter()
+        {
+            group.bench_with_input(
+                BenchmarkId::new(
+                    *name,
+                    format!("arity: {}, count: {}", A::to_usize(), num_hashes),
+                ),
+                &num_hashes,
+                |b, n| {
+                    b.iter(|| {
+                        let circuit = BenchCircuit::<A> {
+                            n: *n,
+                            variant: *variant,
+                            _a: PhantomData::<A>,
+                        };
+                        match variant {
+                            Variant::Witness => {
+                                circuit.synthesize_witness(&mut WitnessGenerator::new())
+                            }
+                            _ => circuit.synthesize(&mut BenchCS::<Bls12>::new()),
+                        }
+                    })
+                },
+            );
+        }
         num_hashes *= 10;
     }
 }
@@ -0,0 +1,81 @@
+//! Prove and verify Merkle membership of a leaf with Groth16.
+//!
+//! Run with `cargo run --release --example groth16 --features groth16,opencl`. The tree is built by `TreeBuilder`, with
+//! the CPU batcher, which requires the `opencl` feature.
+use bellperson::bls::Fr;
+use ff::Field;
+use generic_array::typenum::U8;
+use neptune::batch_hasher::BatcherType;
+use neptune::groth16::{generate_parameters, prove, verify, verifying_key};
+use neptune::merkle::{MerkleConstants, MerklePath};
+use neptune::statements::MerkleMembershipCircuit;
+use neptune::tree_builder::{TreeBuilder, TreeBuilderTrait};
+use rand::thread_rng;
+use std::time::Instant;
+
+const HEIGHT: usize = 3;
+
+fn main() {
+    let mut rng = thread_rng();
+    let constants = MerkleConstants::<U8>::new();
+
+    // The leaves, followed by each row above them, as `MerklePath::from_tree` reads them.
+    let leaf_count = 8usize.pow(HEIGHT as u32);
+    let leaves = (0..leaf_count)
+        .map(|_| Fr::random(&mut rng))
+        .collect::<Vec<_>>();
+    let mut data = vec![Fr::zero(); leaf_count + TreeBuilder::<U8>::tree_size2(leaf_count, 0)];
+    let mut builder = TreeBuilder::<U8>::new(
+        Some(BatcherType::CPU),
+        leaf_count,
+        leaf_count,
+        0,
+        Some(&mut data),
+    )
+    .expect("failed to create tree builder");
+    let (mut tree, rows) = builder
+        .add_final_leaves(&leaves)
+        .expect("failed to build tree");
+    tree.extend(rows);
+    let root = *tree.last().unwrap();
+
+    let start = Instant::now();
+    let params = generate_parameters(
+        MerkleMembershipCircuit {
+            constants: &constants,
+            height: HEIGHT,
+            leaf: None,
+            path: None,
+        },
+        &mut rng,
+    )
+    .expect("parameter generation failed");
+    let vk = verifying_key(&params);
+    println!("Generated parameters in {:?}", start.elapsed());
+
+    let index = 42;
+    let path = MerklePath::from_tree::<U8>(&tree, leaf_count, index).expect("invalid tree");
+    let start = Instant::now();
+    let proof = prove(
+        MerkleMembershipCircuit {
+            constants: &constants,
+            height: HEIGHT,
+            leaf: Some(leaves[index]),
+            path: Some(path),
+        },
+        &params,
+        &mut rng,
+    )
+    .expect("proving failed");
+    println!(
+        "Proved membership of leaf {} in {:?}",
+        index,
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let valid = verify(&vk, &proof, &[root]).expect("verification failed");
+    println!("Verified proof in {:?}: {}", start.elapsed(), valid);
+    assert!(valid);
+    assert!(!verify(&vk, &proof, &[leaves[index]]).expect("verification failed"));
+}
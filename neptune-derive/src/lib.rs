@@ -0,0 +1,173 @@
+//! `#[derive(PoseidonHash)]` for `neptune`. Use it through `neptune::structured::PoseidonHash`, with `neptune`'s
+//! `derive` feature.
+//!
+//! The derived `ToFieldElements` encodes a struct as the concatenation of the encodings of its fields, in declaration
+//! order. The derived `AllocateFieldElements` allocates them in the same order, each in a namespace named after the
+//! field. Every field type must implement both traits. Vector fields can only be allocated with a value, since their
+//! length is part of the shape of the circuit. Users of `neptune` without `std` opt out of the latter with
+//! `#[poseidon_hash(no_circuit)]`.
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Fields, Index, Member, Meta, NestedMeta,
+};
+
+#[proc_macro_derive(PoseidonHash, attributes(poseidon_hash))]
+pub fn derive_poseidon_hash(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "PoseidonHash can only be derived for structs",
+            ))
+        }
+    };
+
+    let members = match fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|f| Member::Named(f.ident.clone().expect("named fields have identifiers")))
+            .collect::<Vec<_>>(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|i| Member::Unnamed(Index::from(i)))
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+    let names = members
+        .iter()
+        .map(|member| match member {
+            Member::Named(ident) => ident.to_string(),
+            Member::Unnamed(index) => index.index.to_string(),
+        })
+        .collect::<Vec<_>>();
+    let types = fields.iter().map(|f| &f.ty).collect::<Vec<_>>();
+
+    let mut output = to_field_elements(&input, &members, &types);
+    if !no_circuit(&input)? {
+        output.extend(allocate_field_elements(&input, &members, &names, &types));
+    }
+
+    Ok(output)
+}
+
+/// Whether the container has the `#[poseidon_hash(no_circuit)]` attribute.
+fn no_circuit(input: &DeriveInput) -> syn::Result<bool> {
+    let mut no_circuit = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path.is_ident("poseidon_hash"))
+    {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested.iter() {
+                    match nested {
+                        NestedMeta::Meta(Meta::Path(path)) if path.is_ident("no_circuit") => {
+                            no_circuit = true
+                        }
+                        _ => {
+                            return Err(syn::Error::new(
+                                nested.span(),
+                                "unknown poseidon_hash attribute",
+                            ))
+                        }
+                    }
+                }
+            }
+            meta => {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    "expected #[poseidon_hash(...)]",
+                ))
+            }
+        }
+    }
+    Ok(no_circuit)
+}
+
+fn to_field_elements(input: &DeriveInput, members: &[Member], types: &[&syn::Type]) -> TokenStream {
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    {
+        let where_clause = generics.make_where_clause();
+        for ty in types {
+            where_clause
+                .predicates
+                .push(parse_quote!(#ty: ::neptune::structured::ToFieldElements));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::neptune::structured::ToFieldElements for #name #ty_generics #where_clause {
+            const LENGTH: ::core::option::Option<usize> = ::neptune::structured::sum_lengths(&[
+                #(<#types as ::neptune::structured::ToFieldElements>::LENGTH),*
+            ]);
+
+            fn append_elements(
+                &self,
+                elements: &mut ::neptune::structured::__private::Vec<::neptune::structured::__private::Fr>,
+            ) {
+                #(::neptune::structured::ToFieldElements::append_elements(&self.#members, elements);)*
+            }
+        }
+    }
+}
+
+fn allocate_field_elements(
+    input: &DeriveInput,
+    members: &[Member],
+    names: &[String],
+    types: &[&syn::Type],
+) -> TokenStream {
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    {
+        let where_clause = generics.make_where_clause();
+        for ty in types {
+            where_clause
+                .predicates
+                .push(parse_quote!(#ty: ::neptune::structured::AllocateFieldElements));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::neptune::structured::AllocateFieldElements for #name #ty_generics #where_clause {
+            #[allow(unused_mut, unused_variables)]
+            fn alloc_elements<CS>(
+                mut cs: CS,
+                value: ::core::option::Option<&Self>,
+            ) -> ::core::result::Result<
+                ::neptune::structured::__private::Vec<
+                    ::neptune::structured::__private::AllocatedNum<::neptune::structured::__private::Bls12>,
+                >,
+                ::neptune::structured::__private::SynthesisError,
+            >
+            where
+                CS: ::neptune::structured::__private::ConstraintSystem<::neptune::structured::__private::Bls12>,
+            {
+                let mut elements = ::neptune::structured::__private::Vec::new();
+                #(
+                    elements.extend(<#types as ::neptune::structured::AllocateFieldElements>::alloc_elements(
+                        ::neptune::structured::__private::ConstraintSystem::namespace(&mut cs, || #names),
+                        value.map(|value| &value.#members),
+                    )?);
+                )*
+                ::core::result::Result::Ok(elements)
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use bellperson::bls::{Bls12, Fr};
+use bellperson::util_cs::test_cs::TestConstraintSystem;
+use bellperson::ConstraintSystem;
+use generic_array::typenum::{U11, U4, U8};
+use neptune::poseidon::Poseidon;
+use neptune::sponge::Sponge;
+use neptune::structured::{
+    poseidon_hash_circuit, AllocateFieldElements, PoseidonHash, ToFieldElements,
+};
+
+#[derive(PoseidonHash)]
+struct Commitment([u8; 32]);
+
+#[derive(PoseidonHash)]
+struct Sector {
+    id: u64,
+    sealed: bool,
+    comm_r: Commitment,
+    randomness: Fr,
+}
+
+#[derive(PoseidonHash)]
+struct Batch {
+    epoch: u32,
+    sectors: Vec<Sector>,
+}
+
+#[derive(PoseidonHash)]
+#[poseidon_hash(no_circuit)]
+struct Epoch(u32);
+
+#[derive(PoseidonHash)]
+struct Pair<T> {
+    left: T,
+    right: T,
+}
+
+fn sector(id: u64) -> Sector {
+    Sector {
+        id,
+        sealed: id % 2 == 0,
+        comm_r: Commitment([id as u8; 32]),
+        randomness: neptune::scalar_from_u64(id * 7),
+    }
+}
+
+#[test]
+fn test_derived_encoding() {
+    assert_eq!(Some(2), Commitment::LENGTH);
+    assert_eq!(Some(5), Sector::LENGTH);
+    assert_eq!(None, Batch::LENGTH);
+    assert_eq!(Some(10), <Pair<Sector>>::LENGTH);
+
+    // Fields are concatenated in declaration order.
+    let sector = sector(3);
+    let mut expected = Vec::new();
+    expected.extend(sector.id.to_elements());
+    expected.extend(sector.sealed.to_elements());
+    expected.extend(sector.comm_r.0.to_elements());
+    expected.push(sector.randomness);
+    assert_eq!(expected, sector.to_elements());
+
+    let batch = Batch {
+        epoch: 9,
+        sectors: vec![sector, self::sector(4)],
+    };
+    let elements = batch.to_elements();
+    assert_eq!(1 + 1 + 2 * 5, elements.len());
+    assert_eq!(neptune::scalar_from_u64::<Fr>(2), elements[1]);
+}
+
+#[test]
+fn test_derived_hash() {
+    let sector = sector(5);
+    let constants = Sector::poseidon_constants::<U8>().unwrap();
+    assert_eq!(
+        Poseidon::new_with_preimage(&sector.to_elements(), &constants).hash(),
+        sector.poseidon_hash(&constants).unwrap()
+    );
+
+    // Sectors are longer than 4 elements, so they are hashed with the sponge.
+    let constants = Sector::poseidon_constants::<U4>().unwrap();
+    assert_eq!(
+        Sponge::hash(&sector.to_elements(), &constants),
+        sector.poseidon_hash(&constants).unwrap()
+    );
+
+    let epoch = Epoch(7);
+    let constants = Epoch::poseidon_constants::<U4>().unwrap();
+    assert!(epoch.poseidon_hash(&constants).is_ok());
+
+    let batch = Batch {
+        epoch: 1,
+        sectors: vec![sector],
+    };
+    let constants = Batch::poseidon_constants::<U4>().unwrap();
+    assert!(batch.poseidon_hash(&constants).is_ok());
+}
+
+#[test]
+fn test_derived_circuit() {
+    let pair = Pair {
+        left: sector(1),
+        right: sector(2),
+    };
+    let constants = <Pair<Sector>>::poseidon_constants::<U11>().unwrap();
+
+    let mut cs = TestConstraintSystem::<Bls12>::new();
+    let digest = poseidon_hash_circuit(cs.namespace(|| "pair"), Some(&pair), &constants).unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(
+        pair.poseidon_hash(&constants).unwrap(),
+        digest.get_value().unwrap()
+    );
+
+    // Allocation follows the field structure.
+    let sector = sector(3);
+    let mut cs = TestConstraintSystem::<Bls12>::new();
+    let elements = Sector::alloc_elements(cs.namespace(|| "sector"), Some(&sector)).unwrap();
+    assert_eq!(
+        sector.to_elements(),
+        elements
+            .iter()
+            .map(|e| e.get_value().unwrap())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        sector.to_elements()[3],
+        cs.get("sector/comm_r/0/bytes 1/value/num")
+    );
+
+    // Vector fields are allocated with their length.
+    let batch = Batch {
+        epoch: 1,
+        sectors: vec![sector, self::sector(4)],
+    };
+    let constants = Batch::poseidon_constants::<U4>().unwrap();
+    let mut cs = TestConstraintSystem::<Bls12>::new();
+    let digest = poseidon_hash_circuit(cs.namespace(|| "batch"), Some(&batch), &constants).unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(
+        batch.poseidon_hash(&constants).unwrap(),
+        digest.get_value().unwrap()
+    );
+    assert_eq!(
+        neptune::scalar_from_u64::<Fr>(2),
+        cs.get("batch/elements/sectors/length/num")
+    );
+}
@@ -0,0 +1,197 @@
+//! Const-generic front end over plain arrays.
+//!
+//! `Poseidon<ARITY>` wraps `poseidon::Poseidon` for the `typenum` arity corresponding to `ARITY`, so callers can pass
+//! `[Fr; ARITY]` preimages and `&[[Fr; ARITY]]` batches without naming `GenericArray` or `typenum` types. Digests are
+//! identical to those of the generic API.
+//!
+//! ```ignore
+//! use neptune::array::{hash, Constants};
+//!
+//! let constants = Constants::<8>::new();
+//! let digest = hash(&[Fr::one(); 8], &constants);
+//! ```
+use crate::poseidon::{self, Arity, PoseidonConstants};
+use crate::{BatchHasher, Error};
+use bellperson::bls::{Bls12, Fr};
+use generic_array::typenum::*;
+use generic_array::GenericArray;
+
+/// Marker type carrying a const-generic arity.
+pub struct ConstArity<const ARITY: usize>;
+
+/// Implemented for `ConstArity<ARITY>` for every arity supported by `Poseidon`.
+pub trait SupportedArity {
+    /// The `typenum` arity used by the generic API.
+    type Arity: Arity<Fr>;
+}
+
+macro_rules! impl_supported_arity {
+    ($($n:literal => $a:ty),*) => {
+        $(
+            impl SupportedArity for ConstArity<$n> {
+                type Arity = $a;
+            }
+        )*
+    };
+}
+
+impl_supported_arity!(
+    2 => U2, 3 => U3, 4 => U4, 5 => U5, 6 => U6, 7 => U7, 8 => U8, 9 => U9, 10 => U10, 11 => U11, 12 => U12,
+    13 => U13, 14 => U14, 15 => U15, 16 => U16, 17 => U17, 18 => U18, 19 => U19, 20 => U20, 21 => U21, 22 => U22,
+    23 => U23, 24 => U24, 25 => U25, 26 => U26, 27 => U27, 28 => U28, 29 => U29, 30 => U30, 31 => U31, 32 => U32,
+    33 => U33, 34 => U34, 35 => U35, 36 => U36
+);
+
+/// `PoseidonConstants` for `ARITY`, e.g. `Constants::<8>::new()`.
+pub type Constants<const ARITY: usize> =
+    PoseidonConstants<Bls12, <ConstArity<ARITY> as SupportedArity>::Arity>;
+
+/// `poseidon::Poseidon` with a const-generic arity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Poseidon<'a, const ARITY: usize>
+where
+    ConstArity<ARITY>: SupportedArity,
+{
+    inner: poseidon::Poseidon<'a, Bls12, <ConstArity<ARITY> as SupportedArity>::Arity>,
+}
+
+impl<'a, const ARITY: usize> Poseidon<'a, ARITY>
+where
+    ConstArity<ARITY>: SupportedArity,
+{
+    pub fn new(constants: &'a Constants<ARITY>) -> Self {
+        Self {
+            inner: poseidon::Poseidon::new(constants),
+        }
+    }
+
+    pub fn new_with_preimage(preimage: &[Fr; ARITY], constants: &'a Constants<ARITY>) -> Self {
+        Self {
+            inner: poseidon::Poseidon::new_with_preimage(preimage, constants),
+        }
+    }
+
+    /// Append an element to the preimage. See `poseidon::Poseidon::input`.
+    pub fn input(&mut self, element: Fr) -> Result<usize, Error> {
+        self.inner.input(element)
+    }
+
+    pub fn hash(&mut self) -> Fr {
+        self.inner.hash()
+    }
+
+    pub fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    /// The wrapped `poseidon::Poseidon`.
+    pub fn inner(
+        &mut self,
+    ) -> &mut poseidon::Poseidon<'a, Bls12, <ConstArity<ARITY> as SupportedArity>::Arity> {
+        &mut self.inner
+    }
+}
+
+/// Hash a single preimage.
+pub fn hash<const ARITY: usize>(preimage: &[Fr; ARITY], constants: &Constants<ARITY>) -> Fr
+where
+    ConstArity<ARITY>: SupportedArity,
+{
+    Poseidon::new_with_preimage(preimage, constants).hash()
+}
+
+/// Hash each of `preimages` on the CPU.
+pub fn hash_many<const ARITY: usize>(
+    preimages: &[[Fr; ARITY]],
+    constants: &Constants<ARITY>,
+) -> Vec<Fr>
+where
+    ConstArity<ARITY>: SupportedArity,
+{
+    preimages
+        .iter()
+        .map(|preimage| hash(preimage, constants))
+        .collect()
+}
+
+/// Convert `preimages` to the `GenericArray`s taken by the generic API.
+pub fn to_generic_arrays<const ARITY: usize>(
+    preimages: &[[Fr; ARITY]],
+) -> Vec<GenericArray<Fr, <ConstArity<ARITY> as SupportedArity>::Arity>>
+where
+    ConstArity<ARITY>: SupportedArity,
+{
+    preimages
+        .iter()
+        .map(|preimage| GenericArray::clone_from_slice(preimage))
+        .collect()
+}
+
+/// Hash `preimages` with any `BatchHasher` of the corresponding `typenum` arity.
+pub fn hash_batch<B, const ARITY: usize>(
+    batcher: &mut B,
+    preimages: &[[Fr; ARITY]],
+) -> Result<Vec<Fr>, Error>
+where
+    ConstArity<ARITY>: SupportedArity,
+    B: BatchHasher<<ConstArity<ARITY> as SupportedArity>::Arity>,
+{
+    batcher.hash(&to_generic_arrays(preimages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon::SimplePoseidonBatchHasher;
+    use crate::Strength;
+    use ff::Field;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn test_array_aux<const ARITY: usize>()
+    where
+        ConstArity<ARITY>: SupportedArity,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = Constants::<ARITY>::new_with_strength(Strength::Standard);
+
+        let mut preimages = [[Fr::zero(); ARITY]; 3];
+        for preimage in preimages.iter_mut() {
+            for element in preimage.iter_mut() {
+                *element = Fr::random(&mut rng);
+            }
+        }
+
+        let expected = preimages
+            .iter()
+            .map(|preimage| poseidon::Poseidon::new_with_preimage(preimage, &constants).hash())
+            .collect::<Vec<_>>();
+
+        assert_eq!(expected[0], hash(&preimages[0], &constants));
+        assert_eq!(expected, hash_many(&preimages, &constants));
+
+        let mut batcher =
+            SimplePoseidonBatchHasher::<<ConstArity<ARITY> as SupportedArity>::Arity>::new(
+                preimages.len(),
+            )
+            .unwrap();
+        assert_eq!(expected, hash_batch(&mut batcher, &preimages).unwrap());
+
+        // Incremental input agrees with the preimage constructor.
+        let mut p = Poseidon::<ARITY>::new(&constants);
+        for element in preimages[1].iter() {
+            p.input(*element).unwrap();
+        }
+        assert_eq!(expected[1], p.hash());
+        assert!(p.input(Fr::one()).is_err());
+    }
+
+    #[test]
+    fn test_array() {
+        test_array_aux::<2>();
+        test_array_aux::<4>();
+        test_array_aux::<8>();
+        test_array_aux::<11>();
+        test_array_aux::<36>();
+    }
+}
@@ -4,12 +4,13 @@ use std::marker::PhantomData;
 use std::sync::{Arc, Mutex};
 
 use crate::error::Error;
+use crate::fingerprint::Fingerprint;
 use crate::poseidon::SimplePoseidonBatchHasher;
 #[cfg(feature = "opencl")]
 use crate::proteus::gpu::{get_device, CLBatchHasher};
 #[cfg(feature = "gpu")]
 use crate::triton::cl;
-use crate::{Arity, BatchHasher, Strength, DEFAULT_STRENGTH};
+use crate::{Arity, BatchHasher, Fingerprinted, Strength, DEFAULT_STRENGTH};
 use bellperson::bls::Fr;
 use generic_array::GenericArray;
 use rust_gpu_tools::opencl::GPUSelector;
@@ -211,3 +212,18 @@ where
         }
     }
 }
+
+impl<A> Fingerprinted for Batcher<A>
+where
+    A: Arity<Fr>,
+{
+    fn fingerprint(&self) -> Fingerprint {
+        match self {
+            Batcher::CPU(batcher) => batcher.fingerprint(),
+            #[cfg(feature = "gpu")]
+            Batcher::GPU(batcher) => batcher.fingerprint(),
+            #[cfg(feature = "opencl")]
+            Batcher::OpenCL(batcher) => batcher.fingerprint(),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! `CheckedBatchHasher` wraps another `BatchHasher`, typically a GPU one, and checks its output against `Poseidon` on
+//! the CPU.
+//!
+//! At construction, the wrapped hasher must have the fingerprint of the given constants and must agree with the CPU on
+//! a fixed set of known-answer preimages: all zeros, `1, 2, ..., arity`, their negations and all `-1`. Afterwards, a
+//! configurable fraction of the outputs of every batch is picked at random and re-hashed on the CPU. Any disagreement
+//! is returned as an error instead of silently producing a wrong tree root.
+//!
+//! Sampling only bounds the probability of missing a fault: a device which corrupts a single output of a batch of `n`
+//! is caught with probability about `sample_rate`. Use a sample rate of `1.0` to check every output.
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::{scalar_from_u64, BatchHasher, Error, Fingerprint, Fingerprinted};
+use bellperson::bls::{Bls12, Fr};
+use ff::Field;
+use generic_array::sequence::GenericSequence;
+use generic_array::GenericArray;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A `BatchHasher` whose output is checked against the CPU. See the module documentation.
+pub struct CheckedBatchHasher<B, A>
+where
+    B: BatchHasher<A>,
+    A: Arity<Fr>,
+{
+    inner: B,
+    constants: PoseidonConstants<Bls12, A>,
+    sample_rate: f64,
+    /// State of the xorshift generator used to pick the sampled outputs.
+    rng_state: u64,
+    samples_checked: usize,
+}
+
+impl<B, A> CheckedBatchHasher<B, A>
+where
+    B: BatchHasher<A> + Fingerprinted,
+    A: Arity<Fr>,
+{
+    /// Wrap `inner`, which must hash with `constants`, and run the known-answer tests. A fraction `sample_rate` in
+    /// `0.0..=1.0` of later outputs is cross-checked.
+    pub fn new(
+        inner: B,
+        constants: PoseidonConstants<Bls12, A>,
+        sample_rate: f64,
+    ) -> Result<Self, Error> {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::new_with_seed(inner, constants, sample_rate, seed)
+    }
+
+    /// Like `new`, but seeds the choice of sampled outputs, so that it is reproducible.
+    pub fn new_with_seed(
+        inner: B,
+        constants: PoseidonConstants<Bls12, A>,
+        sample_rate: f64,
+        seed: u64,
+    ) -> Result<Self, Error> {
+        if !(0.0..=1.0).contains(&sample_rate) {
+            return Err(Error::InvalidSampleRate(sample_rate));
+        }
+        constants.fingerprint().check(&inner.fingerprint())?;
+
+        let mut hasher = Self {
+            inner,
+            constants,
+            sample_rate,
+            // Xorshift never leaves the zero state.
+            rng_state: if seed == 0 {
+                0x9e37_79b9_7f4a_7c15
+            } else {
+                seed
+            },
+            samples_checked: 0,
+        };
+        hasher.self_test()?;
+
+        Ok(hasher)
+    }
+
+    /// The wrapped `BatchHasher`.
+    pub fn inner(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    /// Number of outputs cross-checked since construction, excluding the known-answer tests.
+    pub fn samples_checked(&self) -> usize {
+        self.samples_checked
+    }
+
+    fn self_test(&mut self) -> Result<(), Error> {
+        let preimages = known_answer_preimages::<A>();
+        let max_batch_size = self.inner.max_batch_size().max(1);
+
+        for (i, chunk) in preimages.chunks(max_batch_size).enumerate() {
+            let hashed = self.inner.hash(chunk)?;
+            for (j, preimage) in chunk.iter().enumerate() {
+                if hashed.get(j) != Some(&self.cpu_hash(preimage)) {
+                    return Err(Error::SelfTestFailed {
+                        index: i * max_batch_size + j,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn cpu_hash(&self, preimage: &GenericArray<Fr, A>) -> Fr {
+        Poseidon::new_with_preimage(preimage, &self.constants).hash()
+    }
+
+    fn next_index(&mut self, len: usize) -> usize {
+        // xorshift64
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        (self.rng_state % len as u64) as usize
+    }
+
+    fn cross_check(
+        &mut self,
+        preimages: &[GenericArray<Fr, A>],
+        hashed: &[Fr],
+    ) -> Result<(), Error> {
+        if hashed.len() != preimages.len() {
+            return Err(Error::InvalidPreimageLength {
+                expected: preimages.len(),
+                actual: hashed.len(),
+            });
+        }
+        if preimages.is_empty() {
+            return Ok(());
+        }
+
+        let samples = (preimages.len() as f64 * self.sample_rate).ceil() as usize;
+        for i in 0..samples {
+            // Check every output if all are to be sampled.
+            let index = if samples >= preimages.len() {
+                i
+            } else {
+                self.next_index(preimages.len())
+            };
+            self.check_output(index, preimages, hashed)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_output(
+        &mut self,
+        index: usize,
+        preimages: &[GenericArray<Fr, A>],
+        hashed: &[Fr],
+    ) -> Result<(), Error> {
+        self.samples_checked += 1;
+        if hashed[index] == self.cpu_hash(&preimages[index]) {
+            Ok(())
+        } else {
+            Err(Error::CrossCheckFailed { index })
+        }
+    }
+}
+
+impl<B, A> BatchHasher<A> for CheckedBatchHasher<B, A>
+where
+    B: BatchHasher<A>,
+    A: Arity<Fr>,
+{
+    fn hash(&mut self, preimages: &[GenericArray<Fr, A>]) -> Result<Vec<Fr>, Error> {
+        let hashed = self.inner.hash(preimages)?;
+        self.cross_check(preimages, &hashed)?;
+        Ok(hashed)
+    }
+
+    fn hash2(&mut self, preimages: &[GenericArray<Fr, A>], result: &mut [Fr]) -> Result<(), Error> {
+        self.inner.hash2(preimages, result)?;
+        self.cross_check(preimages, result)
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.inner.max_batch_size()
+    }
+}
+
+impl<B, A> Fingerprinted for CheckedBatchHasher<B, A>
+where
+    B: BatchHasher<A> + Fingerprinted,
+    A: Arity<Fr>,
+{
+    fn fingerprint(&self) -> Fingerprint {
+        self.inner.fingerprint()
+    }
+}
+
+/// All zeros, `1, 2, ..., arity`, their negations, which exercise the high limbs, and all `-1`.
+fn known_answer_preimages<A: Arity<Fr>>() -> Vec<GenericArray<Fr, A>> {
+    let counting = GenericArray::<Fr, A>::generate(|i| scalar_from_u64(i as u64 + 1));
+    let mut negated = counting.clone();
+    negated.iter_mut().for_each(|element| element.negate());
+    let mut minus_one = Fr::one();
+    minus_one.negate();
+
+    vec![
+        GenericArray::generate(|_| Fr::zero()),
+        counting,
+        negated,
+        GenericArray::generate(|_| minus_one),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon::SimplePoseidonBatchHasher;
+    use crate::Strength;
+    use generic_array::typenum::{U2, U8};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    /// Adds one to the digest of every preimage for which `faulty` returns true.
+    struct FaultyBatchHasher<A: Arity<Fr>> {
+        inner: SimplePoseidonBatchHasher<A>,
+        faulty: fn(&[Fr]) -> bool,
+    }
+
+    impl<A: Arity<Fr>> FaultyBatchHasher<A> {
+        fn new(faulty: fn(&[Fr]) -> bool) -> Self {
+            Self {
+                inner: SimplePoseidonBatchHasher::new(3).unwrap(),
+                faulty,
+            }
+        }
+    }
+
+    impl<A: Arity<Fr>> BatchHasher<A> for FaultyBatchHasher<A> {
+        fn hash(&mut self, preimages: &[GenericArray<Fr, A>]) -> Result<Vec<Fr>, Error> {
+            let mut hashed = self.inner.hash(preimages)?;
+            for (digest, preimage) in hashed.iter_mut().zip(preimages) {
+                if (self.faulty)(preimage) {
+                    digest.add_assign(&Fr::one());
+                }
+            }
+            Ok(hashed)
+        }
+
+        fn max_batch_size(&self) -> usize {
+            self.inner.max_batch_size()
+        }
+    }
+
+    impl<A: Arity<Fr>> Fingerprinted for FaultyBatchHasher<A> {
+        fn fingerprint(&self) -> Fingerprint {
+            self.inner.fingerprint()
+        }
+    }
+
+    fn random_preimages<A: Arity<Fr>>(count: usize) -> Vec<GenericArray<Fr, A>> {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        (0..count)
+            .map(|_| GenericArray::generate(|_| Fr::random(&mut rng)))
+            .collect()
+    }
+
+    #[test]
+    fn test_checked_batch_hasher() {
+        let constants = PoseidonConstants::<Bls12, U8>::new();
+        let preimages = random_preimages::<U8>(10);
+        let expected = preimages
+            .iter()
+            .map(|preimage| Poseidon::new_with_preimage(preimage, &constants).hash())
+            .collect::<Vec<_>>();
+
+        let mut hasher =
+            CheckedBatchHasher::new(FaultyBatchHasher::new(|_| false), constants.clone(), 1.0)
+                .unwrap();
+        assert_eq!(expected, hasher.hash(&preimages).unwrap());
+        assert_eq!(10, hasher.samples_checked());
+
+        let mut hasher = CheckedBatchHasher::new_with_seed(
+            SimplePoseidonBatchHasher::new(4).unwrap(),
+            constants,
+            0.25,
+            7,
+        )
+        .unwrap();
+        assert_eq!(expected, hasher.hash(&preimages).unwrap());
+        assert_eq!(3, hasher.samples_checked());
+    }
+
+    #[test]
+    fn test_checked_batch_hasher_faults() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+
+        // A hasher which is always wrong fails the known-answer tests.
+        match CheckedBatchHasher::new(FaultyBatchHasher::new(|_| true), constants.clone(), 0.0) {
+            Err(Error::SelfTestFailed { index: 0 }) => (),
+            _ => panic!("faulty hasher passed the self-test"),
+        }
+
+        // One which is wrong only on `[-1, -2]` fails the third known answer.
+        let negative = |preimage: &[Fr]| {
+            let mut minus_two = scalar_from_u64::<Fr>(2);
+            minus_two.negate();
+            preimage[1] == minus_two
+        };
+        match CheckedBatchHasher::new(FaultyBatchHasher::new(negative), constants.clone(), 0.0) {
+            Err(Error::SelfTestFailed { index: 2 }) => (),
+            _ => panic!("faulty hasher passed the self-test"),
+        }
+
+        // A fault on other inputs passes the self-test and is caught by cross-checking.
+        let seven = |preimage: &[Fr]| preimage[1] == scalar_from_u64::<Fr>(7);
+        let mut preimages = random_preimages::<U2>(5);
+        preimages[3][1] = scalar_from_u64(7);
+
+        let mut hasher =
+            CheckedBatchHasher::new(FaultyBatchHasher::new(seven), constants.clone(), 1.0).unwrap();
+        match hasher.hash(&preimages) {
+            Err(Error::CrossCheckFailed { index: 3 }) => (),
+            _ => panic!("faulty output was not detected"),
+        }
+
+        // Without sampling it goes unnoticed.
+        let mut hasher =
+            CheckedBatchHasher::new(FaultyBatchHasher::new(seven), constants, 0.0).unwrap();
+        assert!(hasher.hash(&preimages).is_ok());
+        assert_eq!(0, hasher.samples_checked());
+    }
+
+    #[test]
+    fn test_checked_batch_hasher_invalid() {
+        let strengthened =
+            PoseidonConstants::<Bls12, U2>::new_with_strength(Strength::Strengthened);
+        match CheckedBatchHasher::new(FaultyBatchHasher::new(|_| false), strengthened, 1.0) {
+            Err(Error::FingerprintMismatch { .. }) => (),
+            _ => panic!("hasher with other constants was accepted"),
+        }
+
+        for &sample_rate in &[-0.1, 1.5, f64::NAN] {
+            match CheckedBatchHasher::new(
+                FaultyBatchHasher::new(|_| false),
+                PoseidonConstants::<Bls12, U2>::new(),
+                sample_rate,
+            ) {
+                Err(Error::InvalidSampleRate(_)) => (),
+                _ => panic!("invalid sample rate was accepted"),
+            }
+        }
+    }
+}
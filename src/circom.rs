@@ -0,0 +1,390 @@
+//! Export of constraint systems to the binary `.r1cs` and `.wtns` formats of circom and snarkjs.
+//!
+//! `R1csRecorder` is a `ConstraintSystem` which records every constraint and assignment a circuit synthesizes, such as
+//! those of `circuit::poseidon_hash`. Wires are numbered as circom numbers them: wire 0 is the constant one, followed by
+//! the public inputs in allocation order, and then by the auxiliary variables. Every public input is exported as a
+//! public input; there are no public outputs or private inputs, since bellperson does not distinguish them from other
+//! variables. Field elements are written in canonical form, as little-endian bytes.
+//!
+//! Constraints can be recorded without a witness: assignments which are missing are recorded as such, and only writing
+//! the witness fails.
+use bellperson::{ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine as Engine};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Version of the `.r1cs` format written.
+const R1CS_VERSION: u32 = 1;
+/// Version of the `.wtns` format written.
+const WTNS_VERSION: u32 = 2;
+
+/// A constraint `a * b = c`, each linear combination as its terms.
+type Constraint<F> = [Vec<(Index, F)>; 3];
+
+/// A constraint system recording constraints and assignments, to export them. See the module documentation.
+pub struct R1csRecorder<E: Engine> {
+    inputs: Vec<Option<E::Fr>>,
+    aux: Vec<Option<E::Fr>>,
+    constraints: Vec<Constraint<E::Fr>>,
+}
+
+impl<E: Engine> R1csRecorder<E> {
+    pub fn new() -> Self {
+        Self {
+            inputs: vec![Some(E::Fr::one())],
+            aux: Vec::new(),
+            constraints: Vec::new(),
+        }
+    }
+
+    pub fn num_constraints(&self) -> usize {
+        self.constraints.len()
+    }
+
+    /// Number of public inputs, including the constant one.
+    pub fn num_inputs(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn num_aux(&self) -> usize {
+        self.aux.len()
+    }
+
+    /// Number of wires, including the constant one.
+    pub fn num_wires(&self) -> usize {
+        self.inputs.len() + self.aux.len()
+    }
+
+    /// The assignment of each wire, in order, if none is missing.
+    pub fn witness(&self) -> Option<Vec<E::Fr>> {
+        self.inputs.iter().chain(self.aux.iter()).cloned().collect()
+    }
+
+    /// Whether the witness is complete and satisfies every constraint.
+    pub fn is_satisfied(&self) -> bool {
+        let witness = match self.witness() {
+            Some(witness) => witness,
+            None => return false,
+        };
+        let eval = |terms: &[(Index, E::Fr)]| {
+            terms.iter().fold(E::Fr::zero(), |mut sum, (index, coeff)| {
+                let mut term = witness[self.wire(*index)];
+                term.mul_assign(coeff);
+                sum.add_assign(&term);
+                sum
+            })
+        };
+
+        self.constraints.iter().all(|[a, b, c]| {
+            let mut product = eval(a);
+            product.mul_assign(&eval(b));
+            product == eval(c)
+        })
+    }
+
+    /// Write the constraints in the `.r1cs` format, with a header, constraints and wire-to-label sections. Labels
+    /// are the wire numbers.
+    pub fn write_r1cs<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let num_wires = self.num_wires();
+
+        let mut header = field_header::<E>();
+        header.extend_from_slice(&(num_wires as u32).to_le_bytes());
+        // Public outputs, public inputs except the constant one, and private inputs.
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(self.inputs.len() as u32 - 1).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(num_wires as u64).to_le_bytes());
+        header.extend_from_slice(&(self.constraints.len() as u32).to_le_bytes());
+
+        let mut constraints = Vec::new();
+        for constraint in self.constraints.iter() {
+            for terms in constraint.iter() {
+                // Terms of the same wire are merged, and ordered by wire as circom writes them.
+                let mut merged = BTreeMap::new();
+                for (index, coeff) in terms.iter() {
+                    merged
+                        .entry(self.wire(*index) as u32)
+                        .or_insert_with(E::Fr::zero)
+                        .add_assign(coeff);
+                }
+                let merged = merged
+                    .into_iter()
+                    .filter(|(_, coeff)| !coeff.is_zero())
+                    .collect::<Vec<_>>();
+
+                constraints.extend_from_slice(&(merged.len() as u32).to_le_bytes());
+                for (wire, coeff) in merged {
+                    constraints.extend_from_slice(&wire.to_le_bytes());
+                    extend_with_element::<E>(&mut constraints, &coeff);
+                }
+            }
+        }
+
+        let labels = (0..num_wires as u64)
+            .flat_map(|label| label.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+
+        writer.write_all(b"r1cs")?;
+        writer.write_all(&R1CS_VERSION.to_le_bytes())?;
+        writer.write_all(&3u32.to_le_bytes())?;
+        write_section(&mut writer, 1, &header)?;
+        write_section(&mut writer, 2, &constraints)?;
+        write_section(&mut writer, 3, &labels)
+    }
+
+    /// Write the witness in the `.wtns` format. Fails with `io::ErrorKind::InvalidData` if an assignment is missing.
+    pub fn write_wtns<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let witness = self
+            .witness()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "witness is incomplete"))?;
+
+        let mut header = field_header::<E>();
+        header.extend_from_slice(&(witness.len() as u32).to_le_bytes());
+
+        let mut values = Vec::new();
+        for value in witness.iter() {
+            extend_with_element::<E>(&mut values, value);
+        }
+
+        writer.write_all(b"wtns")?;
+        writer.write_all(&WTNS_VERSION.to_le_bytes())?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        write_section(&mut writer, 1, &header)?;
+        write_section(&mut writer, 2, &values)
+    }
+
+    /// Number of the wire of a variable.
+    fn wire(&self, index: Index) -> usize {
+        match index {
+            Index::Input(i) => i,
+            Index::Aux(i) => self.inputs.len() + i,
+        }
+    }
+}
+
+impl<E: Engine> Default for R1csRecorder<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Engine> ConstraintSystem<E> for R1csRecorder<E> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.aux.push(assignment(f)?);
+        Ok(Variable::new_unchecked(Index::Aux(self.aux.len() - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.inputs.push(assignment(f)?);
+        Ok(Variable::new_unchecked(Index::Input(self.inputs.len() - 1)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _annotation: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        let terms = |lc: LinearCombination<E>| {
+            lc.iter()
+                .map(|(variable, coeff)| (variable.get_unchecked(), *coeff))
+                .collect::<Vec<_>>()
+        };
+
+        self.constraints.push([
+            terms(a(LinearCombination::zero())),
+            terms(b(LinearCombination::zero())),
+            terms(c(LinearCombination::zero())),
+        ]);
+    }
+
+    fn push_namespace<NR, N>(&mut self, _name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+/// The assignment `f` computes, or none if it is missing.
+fn assignment<F, Fr>(f: F) -> Result<Option<Fr>, SynthesisError>
+where
+    F: FnOnce() -> Result<Fr, SynthesisError>,
+{
+    match f() {
+        Ok(value) => Ok(Some(value)),
+        Err(SynthesisError::AssignmentMissing) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The start of the header sections of both formats: the size of field elements in bytes, and the field modulus.
+fn field_header<E: Engine>() -> Vec<u8> {
+    let modulus = E::Fr::char();
+    let mut header = Vec::new();
+    header.extend_from_slice(&(modulus.as_ref().len() as u32 * 8).to_le_bytes());
+    for limb in modulus.as_ref() {
+        header.extend_from_slice(&limb.to_le_bytes());
+    }
+    header
+}
+
+fn extend_with_element<E: Engine>(bytes: &mut Vec<u8>, element: &E::Fr) {
+    for limb in element.into_repr().as_ref() {
+        bytes.extend_from_slice(&limb.to_le_bytes());
+    }
+}
+
+fn write_section<W: Write>(writer: &mut W, section_type: u32, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&section_type.to_le_bytes())?;
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    writer.write_all(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::poseidon_hash;
+    use crate::poseidon::{Poseidon, PoseidonConstants};
+    use bellperson::bls::{Bls12, Fr};
+    use bellperson::gadgets::num::AllocatedNum;
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use generic_array::typenum::U2;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        let mut le = [0; 4];
+        le.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(le)
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        let mut le = [0; 8];
+        le.copy_from_slice(&bytes[offset..offset + 8]);
+        u64::from_le_bytes(le)
+    }
+
+    fn synthesize<CS: ConstraintSystem<Bls12>>(cs: &mut CS, preimage: Option<[Fr; 2]>) -> Fr {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let root = AllocatedNum::alloc_input(cs.namespace(|| "root"), || {
+            preimage
+                .map(|preimage| Poseidon::new_with_preimage(&preimage, &constants).hash())
+                .ok_or(SynthesisError::AssignmentMissing)
+        })
+        .unwrap();
+        let data = (0..2)
+            .map(|i| {
+                AllocatedNum::alloc(cs.namespace(|| format!("data {}", i)), || {
+                    preimage
+                        .map(|preimage| preimage[i])
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let out = poseidon_hash(cs.namespace(|| "hash"), data, &constants).unwrap();
+        cs.enforce(
+            || "hash is root",
+            |lc| lc + out.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + root.get_variable(),
+        );
+        root.get_value().unwrap_or_else(Fr::zero)
+    }
+
+    #[test]
+    fn test_r1cs_recorder() {
+        let preimage = [Fr::one(), Fr::one()];
+        let mut test_cs = TestConstraintSystem::<Bls12>::new();
+        synthesize(&mut test_cs, Some(preimage));
+        let mut cs = R1csRecorder::<Bls12>::new();
+        let root = synthesize(&mut cs, Some(preimage));
+
+        assert!(cs.is_satisfied());
+        assert_eq!(test_cs.num_constraints(), cs.num_constraints());
+        assert_eq!(311 + 1, cs.num_constraints());
+        assert_eq!(test_cs.scalar_aux(), cs.witness().unwrap()[2..].to_vec());
+        assert_eq!(2, cs.num_inputs());
+
+        let mut r1cs = Vec::new();
+        cs.write_r1cs(&mut r1cs).unwrap();
+        assert_eq!(b"r1cs", &r1cs[..4]);
+        assert_eq!((1, 3), (u32_at(&r1cs, 4), u32_at(&r1cs, 8)));
+        // The header section.
+        assert_eq!((1, 64), (u32_at(&r1cs, 12), u64_at(&r1cs, 16)));
+        assert_eq!(32, u32_at(&r1cs, 24));
+        assert_eq!(cs.num_wires() as u32, u32_at(&r1cs, 60));
+        assert_eq!(
+            (0, 1, 0),
+            (u32_at(&r1cs, 64), u32_at(&r1cs, 68), u32_at(&r1cs, 72))
+        );
+        assert_eq!(cs.num_wires() as u64, u64_at(&r1cs, 76));
+        assert_eq!(cs.num_constraints() as u32, u32_at(&r1cs, 84));
+        // The constraints section, whose last constraint is `out * 1 = root`.
+        assert_eq!(2, u32_at(&r1cs, 88));
+        let constraints_len = u64_at(&r1cs, 92) as usize;
+        let last = 100 + constraints_len - 3 * (4 + 36);
+        let out_wire = cs.num_wires() as u32 - 1;
+        assert_eq!(
+            (1, out_wire),
+            (u32_at(&r1cs, last), u32_at(&r1cs, last + 4))
+        );
+        assert_eq!((1, 0), (u32_at(&r1cs, last + 40), u32_at(&r1cs, last + 44)));
+        assert_eq!((1, 1), (u32_at(&r1cs, last + 80), u32_at(&r1cs, last + 84)));
+        // The labels section.
+        let labels = 100 + constraints_len;
+        assert_eq!(3, u32_at(&r1cs, labels));
+        assert_eq!(8 * cs.num_wires() as u64, u64_at(&r1cs, labels + 4));
+        assert_eq!(labels + 12 + 8 * cs.num_wires(), r1cs.len());
+
+        let mut wtns = Vec::new();
+        cs.write_wtns(&mut wtns).unwrap();
+        assert_eq!(b"wtns", &wtns[..4]);
+        assert_eq!((2, 2), (u32_at(&wtns, 4), u32_at(&wtns, 8)));
+        assert_eq!(cs.num_wires() as u32, u32_at(&wtns, 60));
+        assert_eq!(
+            (2, 32 * cs.num_wires() as u64),
+            (u32_at(&wtns, 64), u64_at(&wtns, 68))
+        );
+        // The constant one, followed by the root.
+        assert_eq!(1, u64_at(&wtns, 76));
+        let mut root_bytes = Vec::new();
+        extend_with_element::<Bls12>(&mut root_bytes, &root);
+        assert_eq!(root_bytes, wtns[108..140].to_vec());
+        assert_eq!(76 + 32 * cs.num_wires(), wtns.len());
+    }
+
+    #[test]
+    fn test_r1cs_recorder_without_witness() {
+        let mut cs = R1csRecorder::<Bls12>::new();
+        synthesize(&mut cs, None);
+
+        assert_eq!(311 + 1, cs.num_constraints());
+        assert!(cs.witness().is_none());
+        assert!(!cs.is_satisfied());
+        cs.write_r1cs(&mut Vec::new()).unwrap();
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            cs.write_wtns(&mut Vec::new()).unwrap_err().kind()
+        );
+    }
+}
@@ -1,27 +1,46 @@
+use crate::cost::InputKind;
 use crate::hash_type::HashType;
 use crate::matrix::Matrix;
 use crate::mds::SparseMatrix;
 use crate::poseidon::{Arity, PoseidonConstants};
+use crate::Error;
 use bellperson::gadgets::boolean::Boolean;
 use bellperson::gadgets::num;
 use bellperson::gadgets::num::AllocatedNum;
-use bellperson::{ConstraintSystem, LinearCombination, SynthesisError};
+use bellperson::{ConstraintSystem, Index, LinearCombination, Namespace, SynthesisError, Variable};
 use ff::Field;
 use ff::ScalarEngine as Engine;
 use std::marker::PhantomData;
+use std::ops::Deref;
 
 /// Similar to `num::Num`, we use `Elt` to accumulate both values and linear combinations, then eventually
 /// extract into a `num::AllocatedNum`, enforcing that the linear combination corresponds to the result.
 /// In this way, all intermediate calculations are accounted for, with the restriction that we can only
 /// accumulate linear (not polynomial) constraints. The set of operations provided here ensure this invariant is maintained.
+///
+/// Gadgets such as `poseidon_hash_elts` take `Elt`s, so that linear combinations of variables, as `num::Num`s, and
+/// constants, as made by `num_from_fr`, are hashed without first allocating them. Constant inputs cost no constraints
+/// in the first round of the permutation.
 #[derive(Clone)]
-enum Elt<E: Engine> {
+pub enum Elt<E: Engine> {
     Allocated(AllocatedNum<E>),
     Num(num::Num<E>),
 }
 
+impl<E: Engine> From<AllocatedNum<E>> for Elt<E> {
+    fn from(allocated: AllocatedNum<E>) -> Self {
+        Self::Allocated(allocated)
+    }
+}
+
+impl<E: Engine> From<num::Num<E>> for Elt<E> {
+    fn from(num: num::Num<E>) -> Self {
+        Self::Num(num)
+    }
+}
+
 impl<E: Engine> Elt<E> {
-    fn is_allocated(&self) -> bool {
+    pub fn is_allocated(&self) -> bool {
         if let Self::Allocated(_) = self {
             true
         } else {
@@ -29,7 +48,7 @@ impl<E: Engine> Elt<E> {
         }
     }
 
-    fn is_num(&self) -> bool {
+    pub fn is_num(&self) -> bool {
         if let Self::Num(_) = self {
             true
         } else {
@@ -37,12 +56,26 @@ impl<E: Engine> Elt<E> {
         }
     }
 
-    fn num_from_fr<CS: ConstraintSystem<E>>(fr: E::Fr) -> Self {
+    /// The constant `fr`.
+    pub fn num_from_fr<CS: ConstraintSystem<E>>(fr: E::Fr) -> Self {
         let num = num::Num::<E>::zero();
         Self::Num(num.add_bool_with_coeff(CS::one(), &Boolean::Constant(true), fr))
     }
 
-    fn ensure_allocated<CS: ConstraintSystem<E>>(
+    /// Whether the element is a constant, i.e. a Num whose linear combination involves no variable but `CS::one()`.
+    pub fn is_constant<CS: ConstraintSystem<E>>(&self) -> bool {
+        match self {
+            Self::Allocated(_) => false,
+            Self::Num(num) => num
+                .lc(E::Fr::one())
+                .iter()
+                .all(|(variable, _)| variable.get_unchecked() == CS::one().get_unchecked()),
+        }
+    }
+
+    /// The element as an `AllocatedNum`. Nums are allocated, and if `enforce` is set, constrained to equal their
+    /// linear combination.
+    pub fn ensure_allocated<CS: ConstraintSystem<E>>(
         &self,
         cs: &mut CS,
         enforce: bool,
@@ -68,14 +101,14 @@ impl<E: Engine> Elt<E> {
         }
     }
 
-    fn val(&self) -> Option<E::Fr> {
+    pub fn val(&self) -> Option<E::Fr> {
         match self {
             Self::Allocated(v) => v.get_value(),
             Self::Num(num) => num.get_value(),
         }
     }
 
-    fn lc(&self) -> LinearCombination<E> {
+    pub fn lc(&self) -> LinearCombination<E> {
         match self {
             Self::Num(num) => num.lc(E::Fr::one()),
             Self::Allocated(v) => LinearCombination::<E>::zero() + v.get_variable(),
@@ -91,6 +124,18 @@ impl<E: Engine> Elt<E> {
         }
     }
 
+    /// Add any element, tracking the calculation in a Num.
+    fn add_elt(self, other: Elt<E>) -> Elt<E> {
+        Elt::Num(self.num().add(&other.num()))
+    }
+
+    fn num(self) -> num::Num<E> {
+        match self {
+            Elt::Num(num) => num,
+            Elt::Allocated(a) => a.into(),
+        }
+    }
+
     /// Scale
     fn scale<CS: ConstraintSystem<E>>(self, scalar: E::Fr) -> Result<Elt<E>, SynthesisError> {
         match self {
@@ -100,18 +145,173 @@ impl<E: Engine> Elt<E> {
     }
 }
 
+/// The constant coefficients of the Poseidon gadget's linear combinations, computed once from `PoseidonConstants` and
+/// instantiated for every hash with them. Hashes of a template synthesize exactly the constraints of
+/// `poseidon_hash_elts`, without rebuilding the columns of the MDS matrices, or looking up the round keys and sparse
+/// matrices, for each round.
+pub struct CircuitTemplate<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    constants: &'a PoseidonConstants<E, A>,
+    /// Columns of the MDS matrix and of the pre-sparse matrix: the coefficients of each element of their products.
+    mds_columns: Vec<Vec<E::Fr>>,
+    pre_sparse_columns: Vec<Vec<E::Fr>>,
+    rounds: Vec<RoundTemplate<'a, E::Fr>>,
+}
+
+/// What a round of the Poseidon gadget takes from the constants.
+struct RoundTemplate<'a, F> {
+    /// Keys added to each element before its s-box, in the first round only.
+    pre_round_keys: &'a [F],
+    /// Keys added to each element after its s-box: to the first element only in partial rounds, and to none in the
+    /// last round.
+    post_round_keys: &'a [F],
+    matrix: RoundMatrix<'a, F>,
+}
+
+/// Matrix the elements are multiplied by at the end of a round.
+enum RoundMatrix<'a, F> {
+    Mds,
+    PreSparse,
+    /// A sparse matrix, by its first row and the rest of its first column. The rest of the matrix is the identity.
+    Sparse {
+        w_hat: &'a [F],
+        v_rest: &'a [F],
+    },
+}
+
+impl<'a, E, A> CircuitTemplate<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        fn columns<F: Copy>(matrix: &Matrix<F>) -> Vec<Vec<F>> {
+            (0..matrix.len())
+                .map(|j| matrix.iter().map(|row| row[j]).collect())
+                .collect()
+        }
+
+        let width = constants.width();
+        let round_constants = &constants.compressed_round_constants;
+        let full_half = constants.half_full_rounds;
+        let partial_rounds = constants.partial_rounds;
+        let round_count = constants.full_rounds + partial_rounds;
+
+        let mut rounds = Vec::with_capacity(round_count);
+        let mut offset = 0;
+        for round in 0..round_count {
+            let partial = round >= full_half && round < full_half + partial_rounds;
+            let pre_key_count = if round == 0 { width } else { 0 };
+            let post_key_count = if partial {
+                1
+            } else if round == round_count - 1 {
+                0
+            } else {
+                width
+            };
+            let pre_round_keys = &round_constants[offset..offset + pre_key_count];
+            offset += pre_key_count;
+            let post_round_keys = &round_constants[offset..offset + post_key_count];
+            offset += post_key_count;
+
+            let matrix = if round == full_half - 1 {
+                RoundMatrix::PreSparse
+            } else if partial {
+                let sparse_matrix = &constants.sparse_matrixes[round - full_half];
+                RoundMatrix::Sparse {
+                    w_hat: &sparse_matrix.w_hat,
+                    v_rest: &sparse_matrix.v_rest,
+                }
+            } else {
+                RoundMatrix::Mds
+            };
+
+            rounds.push(RoundTemplate {
+                pre_round_keys,
+                post_round_keys,
+                matrix,
+            });
+        }
+
+        Self {
+            constants,
+            mds_columns: columns(&constants.mds_matrices.m),
+            pre_sparse_columns: columns(&constants.pre_sparse_matrix),
+            rounds,
+        }
+    }
+
+    pub fn constants(&self) -> &'a PoseidonConstants<E, A> {
+        self.constants
+    }
+
+    /// Create circuit for Poseidon hash, as `poseidon_hash` does.
+    pub fn hash<CS>(
+        &self,
+        cs: CS,
+        preimage: Vec<AllocatedNum<E>>,
+    ) -> Result<AllocatedNum<E>, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        self.hash_elts(cs, preimage.into_iter().map(Elt::Allocated).collect())
+    }
+
+    /// Create circuit for Poseidon hash of `Elt`s, as `poseidon_hash_elts` does.
+    pub fn hash_elts<CS>(
+        &self,
+        mut cs: CS,
+        preimage: Vec<Elt<E>>,
+    ) -> Result<AllocatedNum<E>, SynthesisError>
+    where
+        CS: ConstraintSystem<E>,
+    {
+        let constants = self.constants;
+        if let HashType::VariableLength = constants.hash_type {
+            let mut sponge = SpongeCircuit::with_template(SpongeTemplate::Borrowed(self));
+            for (i, element) in preimage.into_iter().enumerate() {
+                sponge.absorb_elt(cs.namespace(|| format!("absorb {}", i)), element)?;
+            }
+            return sponge.squeeze(cs.namespace(|| "squeeze"));
+        }
+
+        let arity = A::to_usize();
+        let tag_element = Elt::num_from_fr::<CS>(constants.domain_tag);
+        let mut elements = Vec::with_capacity(arity + 1);
+        elements.push(tag_element);
+        elements.extend(preimage);
+
+        match constants.hash_type {
+            HashType::ConstantLength(length) => {
+                assert!(length <= arity, "illegal length: constants are malformed");
+                // Add zero-padding.
+                for _ in 0..(arity - length) {
+                    elements.push(Elt::num_from_fr::<CS>(E::Fr::zero()));
+                }
+            }
+            _ => (),
+        }
+
+        let mut p = PoseidonCircuit::new(elements, self);
+
+        p.hash(cs)
+    }
+}
+
 /// Circuit for Poseidon hash.
 pub struct PoseidonCircuit<'a, E, A>
 where
     E: Engine,
     A: Arity<E::Fr>,
 {
-    constants_offset: usize,
     width: usize,
     elements: Vec<Elt<E>>,
     pos: usize,
-    current_round: usize,
     constants: &'a PoseidonConstants<E, A>,
+    template: &'a CircuitTemplate<'a, E, A>,
     _w: PhantomData<A>,
 }
 
@@ -122,16 +322,16 @@ where
     A: Arity<E::Fr>,
 {
     /// Create a new Poseidon hasher for `preimage`.
-    fn new(elements: Vec<Elt<E>>, constants: &'a PoseidonConstants<E, A>) -> Self {
+    fn new(elements: Vec<Elt<E>>, template: &'a CircuitTemplate<'a, E, A>) -> Self {
+        let constants = template.constants;
         let width = constants.width();
 
         PoseidonCircuit {
-            constants_offset: 0,
             width,
             elements,
             pos: width,
-            current_round: 0,
             constants,
+            template,
             _w: PhantomData::<A>,
         }
     }
@@ -140,78 +340,60 @@ where
         &mut self,
         mut cs: CS,
     ) -> Result<AllocatedNum<E>, SynthesisError> {
-        self.full_round(cs.namespace(|| "first round"), true, false)?;
-
-        for i in 1..self.constants.full_rounds / 2 {
-            self.full_round(
-                cs.namespace(|| format!("initial full round {}", i)),
-                false,
-                false,
-            )?;
-        }
+        self.permute(&mut cs)?;
 
-        for i in 0..self.constants.partial_rounds {
-            self.partial_round(cs.namespace(|| format!("partial round {}", i)))?;
-        }
+        self.elements[1].ensure_allocated(&mut cs.namespace(|| "hash result"), true)
+    }
 
-        for i in 0..(self.constants.full_rounds / 2) - 1 {
-            self.full_round(
-                cs.namespace(|| format!("final full round {}", i)),
-                false,
-                false,
-            )?;
+    /// Apply the permutation to the elements, leaving them unallocated.
+    fn permute<CS: ConstraintSystem<E>>(&mut self, mut cs: CS) -> Result<(), SynthesisError> {
+        let template = self.template;
+        let full_half = self.constants.half_full_rounds;
+        let partial_rounds = self.constants.partial_rounds;
+        let last_round = template.rounds.len() - 1;
+
+        for (round, round_template) in template.rounds.iter().enumerate() {
+            if round >= full_half && round < full_half + partial_rounds {
+                self.partial_round(
+                    cs.namespace(|| format!("partial round {}", round - full_half)),
+                    round_template,
+                )?;
+            } else {
+                self.full_round(
+                    cs.namespace(|| {
+                        if round == 0 {
+                            "first round".to_string()
+                        } else if round < full_half {
+                            format!("initial full round {}", round)
+                        } else if round < last_round {
+                            format!("final full round {}", round - full_half - partial_rounds)
+                        } else {
+                            "terminal full round".to_string()
+                        }
+                    }),
+                    round_template,
+                )?;
+            }
         }
-        self.full_round(cs.namespace(|| "terminal full round"), false, true)?;
-
-        self.elements[1].ensure_allocated(&mut cs.namespace(|| "hash result"), true)
+        Ok(())
     }
 
     fn full_round<CS: ConstraintSystem<E>>(
         &mut self,
         mut cs: CS,
-        first_round: bool,
-        last_round: bool,
+        round: &RoundTemplate<'a, E::Fr>,
     ) -> Result<(), SynthesisError> {
-        let mut constants_offset = self.constants_offset;
-
-        let pre_round_keys = if first_round {
-            (0..self.width)
-                .map(|i| self.constants.compressed_round_constants[constants_offset + i])
-                .collect::<Vec<_>>()
-        } else {
-            Vec::new()
-        };
-        constants_offset += pre_round_keys.len();
-
-        let post_round_keys = if first_round || !last_round {
-            (0..self.width)
-                .map(|i| self.constants.compressed_round_constants[constants_offset + i])
-                .collect::<Vec<_>>()
-        } else {
-            Vec::new()
-        };
-        constants_offset += post_round_keys.len();
+        let first_round = !round.pre_round_keys.is_empty();
 
         // Apply the quintic S-Box to all elements
         for i in 0..self.elements.len() {
-            let pre_round_key = if first_round {
-                let rk = pre_round_keys[i];
-                Some(rk)
-            } else {
-                None
-            };
-
-            let post_round_key = if first_round || !last_round {
-                let rk = post_round_keys[i];
-                Some(rk)
-            } else {
-                None
-            };
+            let pre_round_key = round.pre_round_keys.get(i).copied();
+            let post_round_key = round.post_round_keys.get(i).copied();
 
             if first_round {
-                if i == 0 {
-                    // The very first s-box for the constant arity tag can also be computed statically, as a constant.
-                    self.elements[i] = constant_quintic_s_box_pre_add_tag::<CS, E>(
+                if self.elements[i].is_constant::<CS>() {
+                    // The first s-box of a constant, such as the domain tag, can be computed statically, as a constant.
+                    self.elements[i] = constant_quintic_s_box_pre_add::<CS, E>(
                         &self.elements[i],
                         pre_round_key,
                         post_round_key,
@@ -232,68 +414,53 @@ where
                 )?;
             }
         }
-        self.constants_offset = constants_offset;
 
         // Multiply the elements by the constant MDS matrix
-        self.product_mds::<CS>()?;
-        Ok(())
+        self.product_mds::<CS>(&round.matrix)
     }
 
-    fn partial_round<CS: ConstraintSystem<E>>(&mut self, mut cs: CS) -> Result<(), SynthesisError> {
-        let round_key = self.constants.compressed_round_constants[self.constants_offset];
-        self.constants_offset += 1;
+    fn partial_round<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        round: &RoundTemplate<'a, E::Fr>,
+    ) -> Result<(), SynthesisError> {
         // Apply the quintic S-Box to the first element.
         self.elements[0] = quintic_s_box(
             cs.namespace(|| "solitary quintic s-box"),
             &self.elements[0],
-            Some(round_key),
+            Some(round.post_round_keys[0]),
         )?;
 
         // Multiply the elements by the constant MDS matrix
-        self.product_mds::<CS>()?;
-        Ok(())
-    }
-
-    fn product_mds_m<CS: ConstraintSystem<E>>(&mut self) -> Result<(), SynthesisError> {
-        self.product_mds_with_matrix::<CS>(&self.constants.mds_matrices.m)
+        self.product_mds::<CS>(&round.matrix)
     }
 
-    /// Set the provided elements with the result of the product between the elements and the appropriate
-    /// MDS matrix.
-    fn product_mds<CS: ConstraintSystem<E>>(&mut self) -> Result<(), SynthesisError> {
-        let full_half = self.constants.half_full_rounds;
-        let sparse_offset = full_half - 1;
-        if self.current_round == sparse_offset {
-            self.product_mds_with_matrix::<CS>(&self.constants.pre_sparse_matrix)?;
-        } else {
-            if (self.current_round > sparse_offset)
-                && (self.current_round < full_half + self.constants.partial_rounds)
-            {
-                let index = self.current_round - sparse_offset - 1;
-                let sparse_matrix = &self.constants.sparse_matrixes[index];
-
-                self.product_mds_with_sparse_matrix::<CS>(&sparse_matrix)?;
-            } else {
-                self.product_mds_m::<CS>()?;
+    /// Set the provided elements with the result of the product between the elements and the round's matrix.
+    fn product_mds<CS: ConstraintSystem<E>>(
+        &mut self,
+        matrix: &RoundMatrix<'a, E::Fr>,
+    ) -> Result<(), SynthesisError> {
+        let template = self.template;
+        match matrix {
+            RoundMatrix::Mds => self.product_mds_with_columns::<CS>(&template.mds_columns),
+            RoundMatrix::PreSparse => {
+                self.product_mds_with_columns::<CS>(&template.pre_sparse_columns)
             }
-        };
-
-        self.current_round += 1;
-        Ok(())
+            RoundMatrix::Sparse { w_hat, v_rest } => {
+                self.product_mds_with_sparse_matrix::<CS>(w_hat, v_rest)
+            }
+        }
     }
 
-    fn product_mds_with_matrix<CS: ConstraintSystem<E>>(
+    /// Product with the matrix whose columns are `columns`, as the template holds them.
+    fn product_mds_with_columns<CS: ConstraintSystem<E>>(
         &mut self,
-        matrix: &Matrix<E::Fr>,
+        columns: &[Vec<E::Fr>],
     ) -> Result<(), SynthesisError> {
         let mut result: Vec<Elt<E>> = Vec::with_capacity(self.constants.width());
 
-        for j in 0..self.constants.width() {
-            let column = (0..self.constants.width())
-                .map(|i| matrix[i][j])
-                .collect::<Vec<_>>();
-
-            let product = scalar_product::<E, CS>(self.elements.as_slice(), &column)?;
+        for column in columns {
+            let product = scalar_product::<E, CS>(self.elements.as_slice(), column)?;
 
             result.push(product);
         }
@@ -306,23 +473,19 @@ where
     // Sparse matrix in this context means one of the form, M''.
     fn product_mds_with_sparse_matrix<CS: ConstraintSystem<E>>(
         &mut self,
-        matrix: &SparseMatrix<E>,
+        w_hat: &[E::Fr],
+        v_rest: &[E::Fr],
     ) -> Result<(), SynthesisError> {
         let mut result: Vec<Elt<E>> = Vec::with_capacity(self.constants.width());
 
-        result.push(scalar_product::<E, CS>(
-            self.elements.as_slice(),
-            &matrix.w_hat,
-        )?);
-
-        for j in 1..self.width {
-            result.push(
-                self.elements[j].clone().add::<CS>(
-                    self.elements[0]
-                        .clone() // First row is dense.
-                        .scale::<CS>(matrix.v_rest[j - 1])?, // Except for first row/column, diagonals are one.
-                )?,
-            );
+        result.push(scalar_product::<E, CS>(self.elements.as_slice(), w_hat)?);
+
+        // The other elements are only used once more, so are moved into the result rather than cloned.
+        let mut elements = std::mem::take(&mut self.elements).into_iter();
+        let first = elements.next().expect("elements are never empty");
+        for (element, v) in elements.zip(v_rest) {
+            // First row is dense. Except for first row/column, diagonals are one.
+            result.push(element.add::<CS>(first.clone().scale::<CS>(*v)?)?);
         }
 
         self.elements = result;
@@ -332,13 +495,183 @@ where
 
     fn debug(&self) {
         let element_frs: Vec<_> = self.elements.iter().map(|n| n.val()).collect::<Vec<_>>();
-        dbg!(element_frs, self.constants_offset);
+        dbg!(element_frs);
     }
 }
 
-/// Create circuit for Poseidon hash.
+/// Native counterpart of `PoseidonCircuit`, recording the values of the auxiliary variables it allocates, in order,
+/// except for the hash result.
+struct PoseidonWitness<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    constants_offset: usize,
+    current_round: usize,
+    elements: Vec<E::Fr>,
+    aux: Vec<E::Fr>,
+    constants: &'a PoseidonConstants<E, A>,
+}
+
+impl<'a, E, A> PoseidonWitness<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    fn new(elements: Vec<E::Fr>, constants: &'a PoseidonConstants<E, A>) -> Self {
+        let full_rounds = constants.full_rounds;
+        let partial_rounds = constants.partial_rounds;
+        let s_boxes = elements.len() * full_rounds + partial_rounds;
+
+        PoseidonWitness {
+            constants_offset: 0,
+            current_round: 0,
+            elements,
+            aux: Vec::with_capacity(4 * s_boxes),
+            constants,
+        }
+    }
+
+    /// Apply the permutation, to elements whose first s-boxes are synthesized according to `kinds`.
+    fn permute(&mut self, kinds: &[InputKind]) {
+        self.full_round(Some(kinds), false);
+
+        for _ in 1..self.constants.full_rounds / 2 {
+            self.full_round(None, false);
+        }
+
+        for _ in 0..self.constants.partial_rounds {
+            let round_key = self.constants.compressed_round_constants[self.constants_offset];
+            self.constants_offset += 1;
+            self.quintic_s_box(0, Some(round_key));
+            self.product_mds();
+        }
+
+        for _ in 0..(self.constants.full_rounds / 2) - 1 {
+            self.full_round(None, false);
+        }
+        self.full_round(None, true);
+
+        // Allocating the result allocates the linear combination left by the last MDS product.
+    }
+
+    fn full_round(&mut self, first_round: Option<&[InputKind]>, last_round: bool) {
+        let width = self.elements.len();
+        let round_constants = &self.constants.compressed_round_constants;
+
+        if let Some(kinds) = first_round {
+            let pre_round_keys = &round_constants[self.constants_offset..][..width];
+            let post_round_keys = &round_constants[self.constants_offset + width..][..width];
+            for i in 0..width {
+                let (pre_round_key, post_round_key) = (pre_round_keys[i], post_round_keys[i]);
+                match kinds[i] {
+                    InputKind::Constant => crate::quintic_s_box::<E>(
+                        &mut self.elements[i],
+                        Some(&pre_round_key),
+                        Some(&post_round_key),
+                    ),
+                    kind => {
+                        if kind == InputKind::LinearCombination {
+                            self.aux.push(self.elements[i]);
+                        }
+                        let mut l = self.elements[i];
+                        l.add_assign(&pre_round_key);
+                        self.elements[i] = self.s_box_values(l, Some(post_round_key));
+                    }
+                }
+            }
+            self.constants_offset += 2 * width;
+        } else {
+            for i in 0..width {
+                let post_round_key = if last_round {
+                    None
+                } else {
+                    Some(round_constants[self.constants_offset + i])
+                };
+                self.quintic_s_box(i, post_round_key);
+            }
+            if !last_round {
+                self.constants_offset += width;
+            }
+        }
+
+        self.product_mds();
+    }
+
+    /// The s-box of an element left by an MDS product, which is allocated first.
+    fn quintic_s_box(&mut self, i: usize, post_round_key: Option<E::Fr>) {
+        let l = self.elements[i];
+        self.aux.push(l);
+        self.elements[i] = self.s_box_values(l, post_round_key);
+    }
+
+    /// Record l^2 and l^4, and return and record l^5, plus the round key if any.
+    fn s_box_values(&mut self, l: E::Fr, post_round_key: Option<E::Fr>) -> E::Fr {
+        let mut l2 = l;
+        l2.square();
+        let mut l4 = l2;
+        l4.square();
+        let mut l5 = l4;
+        l5.mul_assign(&l);
+        if let Some(post_round_key) = post_round_key {
+            l5.add_assign(&post_round_key);
+        }
+
+        self.aux.extend_from_slice(&[l2, l4, l5]);
+        l5
+    }
+
+    fn product_mds(&mut self) {
+        let full_half = self.constants.half_full_rounds;
+        let sparse_offset = full_half - 1;
+        if self.current_round == sparse_offset {
+            self.product_mds_with_matrix(&self.constants.pre_sparse_matrix);
+        } else if (self.current_round > sparse_offset)
+            && (self.current_round < full_half + self.constants.partial_rounds)
+        {
+            let index = self.current_round - sparse_offset - 1;
+            let sparse_matrix = &self.constants.sparse_matrixes[index];
+
+            let mut first = E::Fr::zero();
+            for (element, w) in self.elements.iter().zip(sparse_matrix.w_hat.iter()) {
+                let mut product = *element;
+                product.mul_assign(w);
+                first.add_assign(&product);
+            }
+            for j in 1..self.elements.len() {
+                let mut product = self.elements[0];
+                product.mul_assign(&sparse_matrix.v_rest[j - 1]);
+                self.elements[j].add_assign(&product);
+            }
+            self.elements[0] = first;
+        } else {
+            self.product_mds_with_matrix(&self.constants.mds_matrices.m);
+        }
+
+        self.current_round += 1;
+    }
+
+    fn product_mds_with_matrix(&mut self, matrix: &Matrix<E::Fr>) {
+        let width = self.elements.len();
+        let result = (0..width)
+            .map(|j| {
+                let mut sum = E::Fr::zero();
+                for i in 0..width {
+                    let mut product = self.elements[i];
+                    product.mul_assign(&matrix[i][j]);
+                    sum.add_assign(&product);
+                }
+                sum
+            })
+            .collect();
+
+        self.elements = result;
+    }
+}
+
+/// Create circuit for Poseidon hash. Variable-length hashes are delegated to `sponge_hash`.
 pub fn poseidon_hash<CS, E, A>(
-    mut cs: CS,
+    cs: CS,
     preimage: Vec<AllocatedNum<E>>,
     constants: &PoseidonConstants<E, A>,
 ) -> Result<AllocatedNum<E>, SynthesisError>
@@ -347,31 +680,497 @@ where
     E: Engine,
     A: Arity<E::Fr>,
 {
-    let arity = A::to_usize();
-    let tag_element = Elt::num_from_fr::<CS>(constants.domain_tag);
-    let mut elements = Vec::with_capacity(arity + 1);
-    elements.push(tag_element);
-    elements.extend(preimage.into_iter().map(Elt::Allocated));
-
-    match constants.hash_type {
-        HashType::ConstantLength(length) => {
-            assert!(length <= arity, "illegal length: constants are malformed");
-            // Add zero-padding.
-            for i in 0..(arity - length) {
-                let allocated =
-                    AllocatedNum::alloc(cs.namespace(|| format!("padding {}", i)), || {
-                        Ok(E::Fr::zero())
-                    })?;
-                let elt = Elt::Allocated(allocated);
-                elements.push(elt);
+    poseidon_hash_elts(
+        cs,
+        preimage.into_iter().map(Elt::Allocated).collect(),
+        constants,
+    )
+}
+
+/// Create circuit for Poseidon hash of `Elt`s, which may be allocated, linear combinations or constants. Each
+/// allocated input costs 3 constraints in the first round, each other input 4, and each constant none.
+/// Constant-length hashes are padded with constant zeros. `cost::poseidon_hash_cost` gives the cost of the whole hash.
+/// Circuits hashing many times with the same constants can build a `CircuitTemplate` once instead.
+pub fn poseidon_hash_elts<CS, E, A>(
+    cs: CS,
+    preimage: Vec<Elt<E>>,
+    constants: &PoseidonConstants<E, A>,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    CircuitTemplate::new(constants).hash_elts(cs, preimage)
+}
+
+/// How `poseidon_hash_in_mode` synthesizes a hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SynthesisMode {
+    /// Allocate every auxiliary variable and enforce every constraint, as `poseidon_hash_elts` does.
+    Constraints,
+    /// Only allocate the auxiliary variables, in the order `Constraints` allocates them, with values computed natively.
+    /// No linear combination is built and no constraint is enforced, so this is only available with a `WitnessCS`.
+    WitnessOnly,
+}
+
+/// Constraint systems which only record assignments, such as witness generators, and so may be given gadgets that skip
+/// their constraints with `SynthesisMode::WitnessOnly`.
+///
+/// # Safety
+///
+/// Implement this only for constraint systems whose constraints are discarded. For one whose constraints are kept, such
+/// as one generating parameters or checking satisfiability, `poseidon_hash_in_mode` would leave the hashes it
+/// synthesizes unconstrained, and any prover could choose their outputs. This breaks soundness rather than memory
+/// safety, but is as easy to get wrong, hence the `unsafe impl`.
+pub unsafe trait WitnessCS<E: Engine>: ConstraintSystem<E> {}
+
+unsafe impl<'cs, E: Engine, CS: WitnessCS<E>> WitnessCS<E> for &'cs mut CS {}
+
+unsafe impl<'cs, E: Engine, CS: WitnessCS<E>> WitnessCS<E> for Namespace<'cs, E, CS> {}
+
+/// A constraint system which only computes the assignments of a circuit, discarding its constraints, to generate the
+/// witness of a proof. The inputs start with the constant one, as in `TestConstraintSystem`.
+pub struct WitnessGenerator<E: Engine> {
+    inputs: Vec<E::Fr>,
+    aux: Vec<E::Fr>,
+}
+
+impl<E: Engine> WitnessGenerator<E> {
+    pub fn new() -> Self {
+        Self {
+            inputs: vec![E::Fr::one()],
+            aux: Vec::new(),
+        }
+    }
+
+    /// The assignments of the public inputs, including the constant one.
+    pub fn inputs(&self) -> &[E::Fr] {
+        &self.inputs
+    }
+
+    /// The assignments of the auxiliary variables.
+    pub fn aux(&self) -> &[E::Fr] {
+        &self.aux
+    }
+
+    /// The assignments of the public inputs and of the auxiliary variables.
+    pub fn into_assignments(self) -> (Vec<E::Fr>, Vec<E::Fr>) {
+        (self.inputs, self.aux)
+    }
+}
+
+impl<E: Engine> Default for WitnessGenerator<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Engine> ConstraintSystem<E> for WitnessGenerator<E> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.aux.push(f()?);
+        Ok(Variable::new_unchecked(Index::Aux(self.aux.len() - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.inputs.push(f()?);
+        Ok(Variable::new_unchecked(Index::Input(self.inputs.len() - 1)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _annotation: A, _a: LA, _b: LB, _c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+    }
+
+    fn push_namespace<NR, N>(&mut self, _name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+/// `WitnessGenerator` discards every constraint.
+unsafe impl<E: Engine> WitnessCS<E> for WitnessGenerator<E> {}
+
+/// Create circuit for Poseidon hash of `Elt`s in the given mode. Variable-length hashes are always synthesized with
+/// constraints. Constraint systems which are not a `WitnessCS` must hash with `poseidon_hash_elts` instead.
+pub fn poseidon_hash_in_mode<CS, E, A>(
+    mut cs: CS,
+    preimage: Vec<Elt<E>>,
+    constants: &PoseidonConstants<E, A>,
+    mode: SynthesisMode,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: WitnessCS<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    match (mode, &constants.hash_type) {
+        (SynthesisMode::Constraints, _) | (_, HashType::VariableLength) => {
+            poseidon_hash_elts(cs, preimage, constants)
+        }
+        (SynthesisMode::WitnessOnly, _) => {
+            let arity = A::to_usize();
+            let mut kinds = Vec::with_capacity(arity + 1);
+            let mut elements = Vec::with_capacity(arity + 1);
+            kinds.push(InputKind::Constant);
+            elements.push(constants.domain_tag);
+            for element in preimage.iter() {
+                kinds.push(if element.is_constant::<CS>() {
+                    InputKind::Constant
+                } else if element.is_allocated() {
+                    InputKind::Allocated
+                } else {
+                    InputKind::LinearCombination
+                });
+                elements.push(element.val().ok_or(SynthesisError::AssignmentMissing)?);
+            }
+            if let HashType::ConstantLength(length) = constants.hash_type {
+                assert!(length <= arity, "illegal length: constants are malformed");
+                for _ in 0..(arity - length) {
+                    kinds.push(InputKind::Constant);
+                    elements.push(E::Fr::zero());
+                }
             }
+
+            let mut witness = PoseidonWitness::new(elements, constants);
+            witness.permute(&kinds);
+            // The hash result is allocated last, from the second element.
+            let result = witness.elements[1];
+            for (i, value) in witness.aux.into_iter().enumerate() {
+                cs.alloc(|| format!("witness {}", i), || Ok(value))?;
+            }
+            AllocatedNum::alloc(cs.namespace(|| "hash result"), || Ok(result))
         }
-        _ => (),
     }
+}
 
-    let mut p = PoseidonCircuit::new(elements, constants);
+/// In-circuit counterpart of `Sponge`. It absorbs and squeezes `AllocatedNum`s with the same padding and domain tag, so
+/// it computes the same outputs as a `Sponge` given the same elements.
+///
+/// With `S = width * full_rounds + partial_rounds` s-boxes per permutation, absorbing `n` allocated elements and
+/// squeezing `outputs`, over `permutations` permutations, costs
+/// `4 * S * permutations - 4 * width + 3 * min(n, arity) + outputs` constraints: the constant domain tag and empty rate
+/// elements of the first block cost nothing in its first round. Absorbing `n` elements and squeezing one needs
+/// `n / arity + 1` permutations, so `sponge_hash` of `arity` elements costs as much as `poseidon_hash` of them with
+/// constant-length constants, plus `4 * S`.
+pub struct SpongeCircuit<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    elements: Vec<Elt<E>>,
+    /// Number of elements absorbed into the current block.
+    absorbed: usize,
+    /// Position of the next element to squeeze from the rate, once squeezing has started.
+    squeezed: Option<usize>,
+    permutations: usize,
+    outputs: usize,
+    template: SpongeTemplate<'a, E, A>,
+}
 
-    p.hash(cs)
+/// The template of a `SpongeCircuit`: its own, or that of the `CircuitTemplate` hashing with it.
+enum SpongeTemplate<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    Owned(CircuitTemplate<'a, E, A>),
+    Borrowed(&'a CircuitTemplate<'a, E, A>),
+}
+
+impl<'a, E, A> Deref for SpongeTemplate<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    type Target = CircuitTemplate<'a, E, A>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            SpongeTemplate::Owned(template) => template,
+            SpongeTemplate::Borrowed(template) => template,
+        }
+    }
+}
+
+impl<'a, E, A> SpongeCircuit<'a, E, A>
+where
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    /// Create a new sponge circuit. `constants` must be of type `HashType::VariableLength` or `HashType::Custom`.
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        Self::try_new(constants).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `new`, but returns `Error::UnsupportedHashType` for constants of any other hash type.
+    pub fn try_new(constants: &'a PoseidonConstants<E, A>) -> Result<Self, Error> {
+        crate::sponge::check_constants(constants)?;
+
+        Ok(Self::with_template(SpongeTemplate::Owned(
+            CircuitTemplate::new(constants),
+        )))
+    }
+
+    fn with_template(template: SpongeTemplate<'a, E, A>) -> Self {
+        // The domain tag is set by the first permutation, which has a constraint system to express it with.
+        Self {
+            elements: vec![Elt::Num(num::Num::zero()); template.constants.width()],
+            absorbed: 0,
+            squeezed: None,
+            permutations: 0,
+            outputs: 0,
+            template,
+        }
+    }
+
+    /// Number of permutations applied so far.
+    pub fn permutations(&self) -> usize {
+        self.permutations
+    }
+
+    /// Absorb a single element.
+    ///
+    /// # Panics
+    ///
+    /// Panics once squeezing has started.
+    pub fn absorb<CS: ConstraintSystem<E>>(
+        &mut self,
+        cs: CS,
+        element: &AllocatedNum<E>,
+    ) -> Result<(), SynthesisError> {
+        self.absorb_elt(cs, Elt::Allocated(element.clone()))
+    }
+
+    /// Absorb a single element, which may be allocated, a linear combination or a constant.
+    ///
+    /// # Panics
+    ///
+    /// Panics once squeezing has started.
+    pub fn absorb_elt<CS: ConstraintSystem<E>>(
+        &mut self,
+        cs: CS,
+        element: Elt<E>,
+    ) -> Result<(), SynthesisError> {
+        assert!(
+            self.squeezed.is_none(),
+            "cannot absorb into a sponge after squeezing"
+        );
+
+        if self.absorbed == A::to_usize() {
+            self.permute(cs)?;
+            self.absorbed = 0;
+        }
+        let position = 1 + self.absorbed;
+        self.elements[position] = if self.permutations == 0 {
+            // The rate of the first block is zero, so the element can be used as is.
+            element
+        } else {
+            self.elements[position].clone().add_elt(element)
+        };
+        self.absorbed += 1;
+
+        Ok(())
+    }
+
+    /// Absorb all `elements`, in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics once squeezing has started.
+    pub fn absorb_elements<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        elements: &[AllocatedNum<E>],
+    ) -> Result<(), SynthesisError> {
+        for (i, element) in elements.iter().enumerate() {
+            self.absorb(cs.namespace(|| format!("absorb {}", i)), element)?;
+        }
+        Ok(())
+    }
+
+    /// Squeeze a single element. The first call pads the absorbed input, after which no more elements may be absorbed.
+    pub fn squeeze<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+    ) -> Result<AllocatedNum<E>, SynthesisError> {
+        let position = match self.squeezed {
+            None => {
+                self.pad(cs.namespace(|| "pad"))?;
+                0
+            }
+            Some(position) if position == A::to_usize() => {
+                self.permute(cs.namespace(|| "permute"))?;
+                0
+            }
+            Some(position) => position,
+        };
+        self.squeezed = Some(position + 1);
+
+        let output = self.elements[1 + position].ensure_allocated(
+            &mut cs.namespace(|| format!("output {}", self.outputs)),
+            true,
+        )?;
+        self.outputs += 1;
+        Ok(output)
+    }
+
+    /// Squeeze `n` elements.
+    pub fn squeeze_elements<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        n: usize,
+    ) -> Result<Vec<AllocatedNum<E>>, SynthesisError> {
+        (0..n)
+            .map(|i| self.squeeze(cs.namespace(|| format!("squeeze {}", i))))
+            .collect()
+    }
+
+    fn pad<CS: ConstraintSystem<E>>(&mut self, mut cs: CS) -> Result<(), SynthesisError> {
+        if self.absorbed == A::to_usize() {
+            self.permute(cs.namespace(|| "full block"))?;
+            self.absorbed = 0;
+        }
+        let position = 1 + self.absorbed;
+        self.elements[position] = self.elements[position]
+            .clone()
+            .add_elt(Elt::num_from_fr::<CS>(E::Fr::one()));
+        self.permute(cs.namespace(|| "padded block"))
+    }
+
+    fn permute<CS: ConstraintSystem<E>>(&mut self, mut cs: CS) -> Result<(), SynthesisError> {
+        let mut elements = std::mem::take(&mut self.elements);
+        if self.permutations == 0 {
+            elements[0] = Elt::num_from_fr::<CS>(self.template.constants.domain_tag);
+        }
+        let mut circuit = PoseidonCircuit::new(elements, &*self.template);
+        circuit.permute(cs.namespace(|| format!("permutation {}", self.permutations)))?;
+
+        self.elements = circuit.elements;
+        self.permutations += 1;
+        Ok(())
+    }
+}
+
+/// Create circuit for the `Sponge` hash of `preimage`, a single element squeezed after absorbing all of `preimage`.
+/// Returns `SynthesisError::Unsatisfiable` for constants `SpongeCircuit::try_new` rejects.
+pub fn sponge_hash<CS, E, A>(
+    mut cs: CS,
+    preimage: &[AllocatedNum<E>],
+    constants: &PoseidonConstants<E, A>,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    CS: ConstraintSystem<E>,
+    E: Engine,
+    A: Arity<E::Fr>,
+{
+    let mut sponge =
+        SpongeCircuit::try_new(constants).map_err(|_| SynthesisError::Unsatisfiable)?;
+    sponge.absorb_elements(cs.namespace(|| "absorb"), preimage)?;
+    sponge.squeeze(cs.namespace(|| "squeeze"))
+}
+
+/// Circuit decomposing `digest` into its bits, least significant first, as `hash_to_field::digest_bits` does. The
+/// bits are constrained to be the canonical representation of the digest, so the decomposition is unique.
+pub fn digest_to_bits<E, CS>(
+    cs: CS,
+    digest: &AllocatedNum<E>,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    digest.to_bits_le_strict(cs)
+}
+
+/// Circuit computing the `k` low bits of `digest`, least significant first, and the integer they make, as
+/// `hash_to_field::digest_low_bits` does.
+///
+/// # Panics
+///
+/// Panics if `k` is greater than 64.
+pub fn digest_to_low_bits<E, CS>(
+    mut cs: CS,
+    digest: &AllocatedNum<E>,
+    k: usize,
+) -> Result<(Vec<Boolean>, AllocatedNum<E>), SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    assert!(k <= 64, "at most 64 bits fit an integer");
+    let mut bits = digest_to_bits(cs.namespace(|| "digest bits"), digest)?;
+    bits.truncate(k);
+
+    let value = AllocatedNum::alloc(cs.namespace(|| "low bits"), || {
+        bits.iter()
+            .rev()
+            .try_fold(E::Fr::zero(), |mut value, bit| {
+                value.double();
+                if bit.get_value()? {
+                    value.add_assign(&E::Fr::one());
+                }
+                Some(value)
+            })
+            .ok_or(SynthesisError::AssignmentMissing)
+    })?;
+    cs.enforce(
+        || "low bits packing",
+        |_| crate::hash_to_field::bits_lc::<E>(&bits, CS::one(), E::Fr::one()),
+        |lc| lc + CS::one(),
+        |lc| lc + value.get_variable(),
+    );
+
+    Ok((bits, value))
+}
+
+/// Circuit computing the index below `n` that `hash_to_field::reduce_to_index` derives from `digest`, such as a
+/// challenged leaf of a tree with `n` leaves. Indices below powers of two are low bits of the digest, and cost fewer
+/// constraints than the reduction of other bounds.
+///
+/// # Panics
+///
+/// Panics if `n` is zero.
+pub fn digest_to_index<E, CS>(
+    cs: CS,
+    digest: &AllocatedNum<E>,
+    n: u64,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    assert!(n > 0, "bound must be positive");
+    if n.is_power_of_two() {
+        digest_to_low_bits(cs, digest, n.trailing_zeros() as usize).map(|(_, index)| index)
+    } else {
+        crate::hash_to_field::reduce_to_index_circuit(cs, digest, n)
+    }
 }
 
 /// Compute l^5 and enforce constraint. If round_key is supplied, add it to result.
@@ -425,19 +1224,23 @@ fn quintic_s_box_pre_add<CS: ConstraintSystem<E>, E: Engine>(
     }
 }
 
-/// Compute l^5 and enforce constraint. If round_key is supplied, add it to l first.
-fn constant_quintic_s_box_pre_add_tag<CS: ConstraintSystem<E>, E: Engine>(
-    tag: &Elt<E>,
+/// Compute l^5 of a constant, as a constant. If round_key is supplied, add it to l first.
+fn constant_quintic_s_box_pre_add<CS: ConstraintSystem<E>, E: Engine>(
+    constant: &Elt<E>,
     pre_round_key: Option<E::Fr>,
     post_round_key: Option<E::Fr>,
 ) -> Elt<E> {
-    let mut tag = tag.val().expect("missing tag val");
+    let mut constant = constant.val().expect("missing constant val");
     pre_round_key.expect("pre_round_key must be provided");
     post_round_key.expect("post_round_key must be provided");
 
-    crate::quintic_s_box::<E>(&mut tag, pre_round_key.as_ref(), post_round_key.as_ref());
+    crate::quintic_s_box::<E>(
+        &mut constant,
+        pre_round_key.as_ref(),
+        post_round_key.as_ref(),
+    );
 
-    Elt::num_from_fr::<CS>(tag)
+    Elt::num_from_fr::<CS>(constant)
 }
 
 /// Calculates square of sum and enforces that constraint.
@@ -661,11 +1464,14 @@ mod tests {
             } else {
                 constants_x.clone()
             };
+            // Constant-length hashes are padded with constants, whose first s-boxes need no constraints.
+            let padding = arity - preimage_length;
             let expected_constraints_calculated = {
                 let arity_tag_constraints = 0;
                 let width = 1 + arity;
                 // The '- 1' term represents the first s-box for the arity tag, which is a constant and needs no constraint.
-                let s_boxes = (width * constants.full_rounds) + constants.partial_rounds - 1;
+                let s_boxes =
+                    (width * constants.full_rounds) + constants.partial_rounds - 1 - padding;
                 let s_box_constraints = 3 * s_boxes;
                 let mds_constraints =
                     (width * constants.full_rounds) + constants.partial_rounds - arity;
@@ -706,13 +1512,349 @@ mod tests {
             );
 
             assert_eq!(
-                expected_constraints,
+                expected_constraints - 3 * padding,
                 cs.num_constraints(),
                 "constraint number changed",
             );
         }
     }
 
+    #[test]
+    fn test_poseidon_hash_elts() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let s_boxes = 5 * constants.full_rounds + constants.partial_rounds;
+        let (a, b, c) = (
+            Fr::random(&mut rng),
+            Fr::random(&mut rng),
+            Fr::random(&mut rng),
+        );
+        let mut sum = a;
+        sum.add_assign(&b);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let allocated_a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(a)).unwrap();
+        let allocated_b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(b)).unwrap();
+        let lc = num::Num::from(allocated_a.clone()).add(&allocated_b.into());
+        let preimage = vec![
+            Elt::from(allocated_a),
+            Elt::from(lc),
+            Elt::num_from_fr::<TestConstraintSystem<Bls12>>(c),
+            Elt::num_from_fr::<TestConstraintSystem<Bls12>>(Fr::zero()),
+        ];
+        assert!(preimage[2].is_constant::<TestConstraintSystem<Bls12>>());
+        assert!(!preimage[1].is_constant::<TestConstraintSystem<Bls12>>());
+
+        let out = poseidon_hash_elts(&mut cs, preimage, &constants).unwrap();
+
+        let expected = Poseidon::new_with_preimage(&[a, sum, c, Fr::zero()], &constants).hash();
+        assert!(cs.is_satisfied(), "constraints not satisfied");
+        assert_eq!(expected, out.get_value().unwrap());
+        // The allocated input's first s-box costs 3 constraints, the linear combination's 4, and the constants' none.
+        assert_eq!(3 + 4 + 4 * (s_boxes - 5) + 1, cs.num_constraints());
+    }
+
+    /// Only so that the tests can compare the assignments of both modes: `TestConstraintSystem` keeps constraints.
+    unsafe impl WitnessCS<Bls12> for TestConstraintSystem<Bls12> {}
+
+    #[test]
+    fn test_poseidon_hash_in_mode() {
+        test_poseidon_hash_in_mode_aux::<typenum::U2>(HashType::MerkleTree);
+        test_poseidon_hash_in_mode_aux::<typenum::U4>(HashType::ConstantLength(3));
+        test_poseidon_hash_in_mode_aux::<typenum::U8>(HashType::MerkleTree);
+        test_poseidon_hash_in_mode_aux::<typenum::U11>(HashType::ConstantLength(5));
+        test_poseidon_hash_in_mode_aux::<typenum::U4>(HashType::VariableLength);
+    }
+
+    fn test_poseidon_hash_in_mode_aux<A>(hash_type: HashType<Fr, A>)
+    where
+        A: Arity<Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new_with_strength_and_type(
+            Strength::Standard,
+            hash_type.clone(),
+        );
+        let length = match hash_type {
+            HashType::ConstantLength(length) => length,
+            _ => A::to_usize(),
+        };
+        let values = (0..length)
+            .map(|_| Fr::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        let synthesize = |mode| {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            // Cycle through allocated inputs, linear combinations and constants.
+            let preimage = values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    let allocated =
+                        AllocatedNum::alloc(cs.namespace(|| format!("input {}", i)), || Ok(*value))
+                            .unwrap();
+                    match i % 3 {
+                        0 => Elt::from(allocated),
+                        1 => Elt::from(num::Num::from(allocated)),
+                        _ => Elt::num_from_fr::<TestConstraintSystem<Bls12>>(*value),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let inputs_constraints = cs.num_constraints();
+
+            let out =
+                poseidon_hash_in_mode(cs.namespace(|| "hash"), preimage, &constants, mode).unwrap();
+            (cs, out, inputs_constraints)
+        };
+
+        let (constraints_cs, constraints_out, _) = synthesize(SynthesisMode::Constraints);
+        let (witness_cs, witness_out, inputs_constraints) = synthesize(SynthesisMode::WitnessOnly);
+
+        assert!(constraints_cs.is_satisfied(), "constraints not satisfied");
+        assert_eq!(constraints_out.get_value(), witness_out.get_value());
+        assert_eq!(constraints_cs.scalar_aux(), witness_cs.scalar_aux());
+        if let HashType::VariableLength = hash_type {
+            assert_eq!(
+                constraints_cs.num_constraints(),
+                witness_cs.num_constraints()
+            );
+        } else {
+            assert_eq!(inputs_constraints, witness_cs.num_constraints());
+        }
+    }
+
+    #[test]
+    fn test_witness_generator() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        let values = (0..4).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            cs: &mut CS,
+            values: &[Fr],
+            hash: impl Fn(&mut CS, Vec<Elt<Bls12>>) -> AllocatedNum<Bls12>,
+        ) {
+            let input =
+                AllocatedNum::alloc_input(cs.namespace(|| "input"), || Ok(values[0])).unwrap();
+            let mut preimage = vec![Elt::from(input)];
+            for (i, value) in values[1..].iter().enumerate() {
+                let allocated =
+                    AllocatedNum::alloc(cs.namespace(|| format!("value {}", i)), || Ok(*value))
+                        .unwrap();
+                preimage.push(Elt::from(allocated));
+            }
+            let out = hash(cs, preimage);
+            out.inputize(cs.namespace(|| "output")).unwrap();
+        }
+
+        let mut test_cs = TestConstraintSystem::<Bls12>::new();
+        synthesize(&mut test_cs, &values, |cs, preimage| {
+            poseidon_hash_elts(cs.namespace(|| "hash"), preimage, &constants).unwrap()
+        });
+        assert!(test_cs.is_satisfied(), "constraints not satisfied");
+
+        // Both modes generate the same witness.
+        for mode in [SynthesisMode::Constraints, SynthesisMode::WitnessOnly].iter() {
+            let mut cs = WitnessGenerator::<Bls12>::new();
+            synthesize(&mut cs, &values, |cs, preimage| {
+                poseidon_hash_in_mode(cs.namespace(|| "hash"), preimage, &constants, *mode).unwrap()
+            });
+            assert_eq!(test_cs.scalar_inputs(), cs.inputs());
+            assert_eq!(test_cs.scalar_aux(), cs.aux());
+
+            let (inputs, aux) = cs.into_assignments();
+            assert_eq!(test_cs.scalar_inputs(), inputs);
+            assert_eq!(test_cs.scalar_aux(), aux);
+        }
+
+        // Missing assignments are errors.
+        let mut cs = WitnessGenerator::<Bls12>::new();
+        assert!(AllocatedNum::alloc(&mut cs, || Err(SynthesisError::AssignmentMissing)).is_err());
+    }
+
+    #[test]
+    fn test_circuit_template() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U8>::new();
+        let template = CircuitTemplate::new(&constants);
+        let preimages = (0..3)
+            .map(|_| (0..8).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let mut template_cs = TestConstraintSystem::<Bls12>::new();
+        for (i, preimage) in preimages.iter().enumerate() {
+            let allocated =
+                alloc_preimage(&mut cs.namespace(|| format!("preimage {}", i)), preimage);
+            let out = poseidon_hash(
+                cs.namespace(|| format!("hash {}", i)),
+                allocated,
+                &constants,
+            )
+            .unwrap();
+
+            let allocated = alloc_preimage(
+                &mut template_cs.namespace(|| format!("preimage {}", i)),
+                preimage,
+            );
+            let template_out = template
+                .hash(template_cs.namespace(|| format!("hash {}", i)), allocated)
+                .unwrap();
+
+            let expected = Poseidon::new_with_preimage(preimage, &constants).hash();
+            assert_eq!(expected, template_out.get_value().unwrap());
+            assert_eq!(out.get_value(), template_out.get_value());
+        }
+
+        assert!(template_cs.is_satisfied(), "constraints not satisfied");
+        assert_eq!(cs.num_constraints(), template_cs.num_constraints());
+        assert_eq!(cs.scalar_aux(), template_cs.scalar_aux());
+        assert_eq!(cs.hash(), template_cs.hash());
+    }
+
+    #[test]
+    fn test_circuit_template_variable_length() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new_with_strength_and_type(
+            Strength::Standard,
+            HashType::VariableLength,
+        );
+        let template = CircuitTemplate::new(&constants);
+
+        // The template lends itself to the sponge, which hashes exactly as `sponge_hash`.
+        for &length in &[1, 4, 11] {
+            let preimage = (0..length)
+                .map(|_| Fr::random(&mut rng))
+                .collect::<Vec<_>>();
+
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let allocated = alloc_preimage(&mut cs, &preimage);
+            let out = sponge_hash(cs.namespace(|| "hash"), &allocated, &constants).unwrap();
+
+            let mut template_cs = TestConstraintSystem::<Bls12>::new();
+            let allocated = alloc_preimage(&mut template_cs, &preimage);
+            let template_out = template
+                .hash(template_cs.namespace(|| "hash"), allocated)
+                .unwrap();
+
+            assert!(template_cs.is_satisfied(), "constraints not satisfied");
+            assert_eq!(
+                crate::sponge::Sponge::hash(&preimage, &constants),
+                template_out.get_value().unwrap()
+            );
+            assert_eq!(out.get_value(), template_out.get_value());
+            assert_eq!(cs.num_constraints(), template_cs.num_constraints());
+            assert_eq!(cs.scalar_aux(), template_cs.scalar_aux());
+        }
+    }
+
+    #[test]
+    fn test_sponge_circuit() {
+        test_sponge_circuit_aux::<typenum::U2>(&[0, 1, 2, 3, 4, 7], 627);
+        test_sponge_circuit_aux::<typenum::U4>(&[0, 3, 4, 5, 8, 13], 761);
+        test_sponge_circuit_aux::<typenum::U8>(&[1, 8, 9], 1021);
+    }
+
+    fn test_sponge_circuit_aux<A>(lengths: &[usize], expected_constraints: usize)
+    where
+        A: Arity<<Bls12 as Engine>::Fr>,
+    {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let arity = A::to_usize();
+        let constants = PoseidonConstants::<Bls12, A>::new_with_strength_and_type(
+            Strength::Standard,
+            HashType::VariableLength,
+        );
+        let s_boxes = (arity + 1) * constants.full_rounds + constants.partial_rounds;
+
+        for &length in lengths {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let fr_data = (0..length)
+                .map(|_| Fr::random(&mut rng))
+                .collect::<Vec<_>>();
+            let data = alloc_preimage(&mut cs, &fr_data);
+
+            let out = sponge_hash(cs.namespace(|| "sponge"), &data, &constants).unwrap();
+            let via_poseidon_hash =
+                poseidon_hash(cs.namespace(|| "poseidon"), data, &constants).unwrap();
+
+            assert!(cs.is_satisfied(), "constraints not satisfied");
+            let expected = crate::sponge::Sponge::hash(&fr_data, &constants);
+            assert_eq!(expected, out.get_value().unwrap());
+            assert_eq!(expected, via_poseidon_hash.get_value().unwrap());
+
+            let permutations = length / arity + 1;
+            let constraints =
+                4 * s_boxes * permutations - 4 * (arity + 1) + 3 * length.min(arity) + 1;
+            assert_eq!(2 * constraints, cs.num_constraints());
+            if length == arity {
+                assert_eq!(
+                    expected_constraints, constraints,
+                    "constraint number changed"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_sponge_circuit_unsupported_hash_type() {
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new();
+        assert!(matches!(
+            SpongeCircuit::try_new(&constants),
+            Err(Error::UnsupportedHashType(_))
+        ));
+    }
+
+    #[test]
+    fn test_sponge_circuit_squeeze() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, typenum::U4>::new_with_strength_and_type(
+            Strength::Standard,
+            HashType::VariableLength,
+        );
+        let s_boxes = 5 * constants.full_rounds + constants.partial_rounds;
+        let fr_data = (0..6).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        let mut native = crate::sponge::Sponge::new(&constants);
+        native.absorb_elements(&fr_data).unwrap();
+        let expected = native.squeeze_elements(9);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let data = alloc_preimage(&mut cs, &fr_data);
+        let mut sponge = SpongeCircuit::new(&constants);
+        sponge
+            .absorb_elements(cs.namespace(|| "absorb"), &data)
+            .unwrap();
+        let outputs = sponge
+            .squeeze_elements(cs.namespace(|| "squeeze"), 9)
+            .unwrap();
+
+        assert!(cs.is_satisfied(), "constraints not satisfied");
+        assert_eq!(
+            expected,
+            outputs
+                .iter()
+                .map(|output| output.get_value().unwrap())
+                .collect::<Vec<_>>()
+        );
+        // One permutation for the full first block, one for the padded block, and one each time the rate is exhausted.
+        assert_eq!(4, sponge.permutations());
+        assert_eq!(4 * s_boxes * 4 - 4 * 5 + 3 * 4 + 9, cs.num_constraints());
+    }
+
+    fn alloc_preimage<CS: ConstraintSystem<Bls12>>(
+        cs: &mut CS,
+        preimage: &[Fr],
+    ) -> Vec<AllocatedNum<Bls12>> {
+        preimage
+            .iter()
+            .enumerate()
+            .map(|(i, fr)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("data {}", i)), || Ok(*fr)).unwrap()
+            })
+            .collect()
+    }
+
     fn fr(n: u64) -> <Bls12 as Engine>::Fr {
         scalar_from_u64::<<Bls12 as Engine>::Fr>(n)
     }
@@ -721,6 +1863,49 @@ mod tests {
         Elt::num_from_fr::<TestConstraintSystem<Bls12>>(fr(n))
     }
 
+    #[test]
+    fn test_digest_to_bits() {
+        use crate::hash_to_field::{digest_bits, digest_low_bits, reduce_to_index};
+
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let mut largest = Fr::zero();
+        largest.sub_assign(&Fr::one());
+
+        for digest in [Fr::random(&mut rng), largest].iter() {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let allocated = AllocatedNum::alloc(cs.namespace(|| "digest"), || Ok(*digest)).unwrap();
+
+            let bits = digest_to_bits(cs.namespace(|| "bits"), &allocated).unwrap();
+            assert_eq!(
+                digest_bits(digest),
+                bits.iter()
+                    .map(|bit| bit.get_value().unwrap())
+                    .collect::<Vec<_>>()
+            );
+
+            for &k in [0, 1, 20, 64].iter() {
+                let (bits, value) =
+                    digest_to_low_bits(cs.namespace(|| format!("low bits {}", k)), &allocated, k)
+                        .unwrap();
+                assert_eq!(k, bits.len());
+                assert_eq!(
+                    scalar_from_u64::<Fr>(digest_low_bits(digest, k)),
+                    value.get_value().unwrap()
+                );
+            }
+
+            for &n in [1, 8, 1 << 20, 7, 1_000_003].iter() {
+                let index = digest_to_index(cs.namespace(|| format!("index {}", n)), &allocated, n)
+                    .unwrap();
+                assert_eq!(
+                    scalar_from_u64::<Fr>(reduce_to_index(digest, n)),
+                    index.get_value().unwrap()
+                );
+            }
+            assert!(cs.is_satisfied(), "constraints not satisfied");
+        }
+    }
+
     #[test]
     fn test_square_sum() {
         let mut cs = TestConstraintSystem::<Bls12>::new();
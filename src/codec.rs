@@ -0,0 +1,332 @@
+//! Bulk conversion between bytes and field elements, and regrouping of field elements into preimages and columns.
+//!
+//! An element is encoded as 32 bytes: four little-endian 64-bit limbs, least significant first, in one of two `Form`s.
+//! `Canonical` bytes hold the element's integer value, as in `Digest::to_bytes`. `Montgomery` bytes hold its
+//! in-memory representation, `value * 2^256 mod p`, as produced by GPUs or by dumping `Fr`s to disk. In either form
+//! the limbs must be below the modulus: decoders return `Error::ElementOutOfRange` with the index of the first
+//! element which is not.
+//!
+//! Montgomery limbs can be viewed as `Fr`s without copying. So can Montgomery bytes on little-endian platforms, if
+//! they are aligned like `Fr`, as buffers read into a `Vec<u64>` or memory-mapped are. The views check the whole
+//! buffer, which is much faster than converting it.
+use crate::poseidon::Arity;
+use crate::Error;
+use bellperson::bls::{Fr, FrRepr};
+use core::mem::{align_of, size_of};
+use core::slice;
+use ff::{Field, PrimeField};
+use generic_array::sequence::GenericSequence;
+use generic_array::GenericArray;
+
+/// Number of bytes of an encoded element.
+pub const FR_BYTES: usize = 32;
+/// Number of 64-bit limbs of an element.
+pub const FR_LIMBS: usize = 4;
+
+/// How elements are encoded. See the module documentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Form {
+    Canonical,
+    Montgomery,
+}
+
+/// Decode `bytes`, whose length must be a multiple of `FR_BYTES`.
+pub fn bytes_to_frs(bytes: &[u8], form: Form) -> Result<Vec<Fr>, Error> {
+    check_length(bytes.len(), FR_BYTES)?;
+
+    let limbs = bytes
+        .chunks(8)
+        .map(|chunk| {
+            let mut limb = [0u8; 8];
+            limb.copy_from_slice(chunk);
+            u64::from_le_bytes(limb)
+        })
+        .collect::<Vec<_>>();
+    check_range(&limbs)?;
+
+    match form {
+        Form::Canonical => Ok(limbs
+            .chunks(FR_LIMBS)
+            .map(|element| {
+                let mut repr = FrRepr::default();
+                repr.as_mut().copy_from_slice(element);
+                Fr::from_repr(repr).expect("range was checked")
+            })
+            .collect()),
+        Form::Montgomery => {
+            let mut frs = vec![Fr::zero(); limbs.len() / FR_LIMBS];
+            frs_as_mont_u64s_mut(&mut frs).copy_from_slice(&limbs);
+            Ok(frs)
+        }
+    }
+}
+
+/// Encode `frs`.
+pub fn frs_to_bytes(frs: &[Fr], form: Form) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(frs.len() * FR_BYTES);
+    match form {
+        Form::Canonical => {
+            for fr in frs {
+                for limb in fr.into_repr().as_ref() {
+                    bytes.extend_from_slice(&limb.to_le_bytes());
+                }
+            }
+        }
+        Form::Montgomery => {
+            for limb in frs_as_mont_u64s(frs) {
+                bytes.extend_from_slice(&limb.to_le_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+/// View `frs` as their Montgomery limbs.
+pub fn frs_as_mont_u64s(frs: &[Fr]) -> &[u64] {
+    check_layout();
+    // Every `Fr` is `FR_LIMBS` initialized limbs.
+    unsafe { slice::from_raw_parts(frs.as_ptr() as *const u64, frs.len() * FR_LIMBS) }
+}
+
+/// Mutable limbs could be set out of range, so this view is private.
+fn frs_as_mont_u64s_mut(frs: &mut [Fr]) -> &mut [u64] {
+    check_layout();
+    unsafe { slice::from_raw_parts_mut(frs.as_mut_ptr() as *mut u64, frs.len() * FR_LIMBS) }
+}
+
+/// View Montgomery limbs, whose number must be a multiple of `FR_LIMBS`, as `Fr`s.
+pub fn mont_u64s_as_frs(limbs: &[u64]) -> Result<&[Fr], Error> {
+    check_layout();
+    check_length(limbs.len(), FR_LIMBS)?;
+    check_range(limbs)?;
+    // The limbs are aligned like `Fr` and every element is in range.
+    Ok(unsafe { slice::from_raw_parts(limbs.as_ptr() as *const Fr, limbs.len() / FR_LIMBS) })
+}
+
+/// Like `mont_u64s_as_frs`, but mutable. The limbs can only be changed through the `Fr`s, which stay in range.
+pub fn mont_u64s_as_frs_mut(limbs: &mut [u64]) -> Result<&mut [Fr], Error> {
+    check_layout();
+    check_length(limbs.len(), FR_LIMBS)?;
+    check_range(limbs)?;
+    Ok(unsafe { slice::from_raw_parts_mut(limbs.as_mut_ptr() as *mut Fr, limbs.len() / FR_LIMBS) })
+}
+
+/// View `frs` as Montgomery bytes.
+#[cfg(target_endian = "little")]
+pub fn frs_as_mont_bytes(frs: &[Fr]) -> &[u8] {
+    let limbs = frs_as_mont_u64s(frs);
+    unsafe { slice::from_raw_parts(limbs.as_ptr() as *const u8, limbs.len() * 8) }
+}
+
+/// View Montgomery bytes, whose length must be a multiple of `FR_BYTES`, as `Fr`s. Returns `Error::MisalignedBuffer`
+/// unless `bytes` is aligned like `Fr`.
+#[cfg(target_endian = "little")]
+pub fn mont_bytes_as_frs(bytes: &[u8]) -> Result<&[Fr], Error> {
+    check_length(bytes.len(), FR_BYTES)?;
+    if bytes.as_ptr() as usize % align_of::<u64>() != 0 {
+        return Err(Error::MisalignedBuffer);
+    }
+    let limbs = unsafe { slice::from_raw_parts(bytes.as_ptr() as *const u64, bytes.len() / 8) };
+    mont_u64s_as_frs(limbs)
+}
+
+/// Like `mont_bytes_as_frs`, but mutable.
+#[cfg(target_endian = "little")]
+pub fn mont_bytes_as_frs_mut(bytes: &mut [u8]) -> Result<&mut [Fr], Error> {
+    check_length(bytes.len(), FR_BYTES)?;
+    if bytes.as_ptr() as usize % align_of::<u64>() != 0 {
+        return Err(Error::MisalignedBuffer);
+    }
+    let limbs =
+        unsafe { slice::from_raw_parts_mut(bytes.as_mut_ptr() as *mut u64, bytes.len() / 8) };
+    mont_u64s_as_frs_mut(limbs)
+}
+
+/// Group `frs`, whose length must be a multiple of the arity, into consecutive preimages.
+pub fn as_generic_arrays<A: Arity<Fr>>(frs: &[Fr]) -> Result<&[GenericArray<Fr, A>], Error> {
+    check_length(frs.len(), A::to_usize())?;
+    // A `GenericArray` is an array, so it has the layout of `A` consecutive `Fr`s.
+    Ok(unsafe {
+        slice::from_raw_parts(
+            frs.as_ptr() as *const GenericArray<Fr, A>,
+            frs.len() / A::to_usize(),
+        )
+    })
+}
+
+/// Like `as_generic_arrays`, but mutable.
+pub fn as_generic_arrays_mut<A: Arity<Fr>>(
+    frs: &mut [Fr],
+) -> Result<&mut [GenericArray<Fr, A>], Error> {
+    check_length(frs.len(), A::to_usize())?;
+    Ok(unsafe {
+        slice::from_raw_parts_mut(
+            frs.as_mut_ptr() as *mut GenericArray<Fr, A>,
+            frs.len() / A::to_usize(),
+        )
+    })
+}
+
+/// View preimages as their concatenated elements.
+pub fn generic_arrays_as_frs<A: Arity<Fr>>(arrays: &[GenericArray<Fr, A>]) -> &[Fr] {
+    unsafe { slice::from_raw_parts(arrays.as_ptr() as *const Fr, arrays.len() * A::to_usize()) }
+}
+
+/// Regroup `rows`, which holds `A` rows of equal length one after another, into columns: column `i` holds element
+/// `i` of each row. This is the layout of `ColumnTreeBuilder` columns built from per-layer data.
+pub fn to_columns<A: Arity<Fr>>(rows: &[Fr]) -> Result<Vec<GenericArray<Fr, A>>, Error> {
+    check_length(rows.len(), A::to_usize())?;
+    let row_length = rows.len() / A::to_usize();
+
+    Ok((0..row_length)
+        .map(|i| GenericArray::generate(|row| rows[row * row_length + i]))
+        .collect())
+}
+
+/// The inverse of `to_columns`.
+pub fn from_columns<A: Arity<Fr>>(columns: &[GenericArray<Fr, A>]) -> Vec<Fr> {
+    let mut rows = vec![Fr::zero(); columns.len() * A::to_usize()];
+    for (i, column) in columns.iter().enumerate() {
+        for (row, element) in column.iter().enumerate() {
+            rows[row * columns.len() + i] = *element;
+        }
+    }
+    rows
+}
+
+/// The views rely on `Fr` being exactly its limbs.
+fn check_layout() {
+    assert_eq!(FR_BYTES, size_of::<Fr>(), "Fr size changed");
+    assert_eq!(align_of::<u64>(), align_of::<Fr>(), "Fr alignment changed");
+}
+
+fn check_length(length: usize, multiple: usize) -> Result<(), Error> {
+    if length % multiple == 0 {
+        Ok(())
+    } else {
+        Err(Error::InvalidBufferLength { length, multiple })
+    }
+}
+
+/// Returns an error unless every element of `limbs` is below the modulus.
+fn check_range(limbs: &[u64]) -> Result<(), Error> {
+    let modulus = Fr::char();
+    let below_modulus = |element: &[u64]| {
+        for (limb, m) in element.iter().rev().zip(modulus.as_ref().iter().rev()) {
+            if limb != m {
+                return limb < m;
+            }
+        }
+        false
+    };
+
+    match limbs
+        .chunks(FR_LIMBS)
+        .position(|element| !below_modulus(element))
+    {
+        Some(index) => Err(Error::ElementOutOfRange { index }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Digest;
+    use generic_array::typenum::U3;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn random_frs(count: usize) -> Vec<Fr> {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        (0..count).map(|_| Fr::random(&mut rng)).collect()
+    }
+
+    #[test]
+    fn test_codec_bytes() {
+        let frs = random_frs(9);
+        for &form in &[Form::Canonical, Form::Montgomery] {
+            let bytes = frs_to_bytes(&frs, form);
+            assert_eq!(9 * FR_BYTES, bytes.len());
+            assert_eq!(frs, bytes_to_frs(&bytes, form).unwrap());
+        }
+
+        // Canonical bytes are those of `Digest`, and differ from the Montgomery ones.
+        let canonical = frs_to_bytes(&frs, Form::Canonical);
+        assert_eq!(&Digest::new(frs[1]).to_bytes()[..], &canonical[32..64]);
+        assert_ne!(canonical, frs_to_bytes(&frs, Form::Montgomery));
+        assert_eq!(
+            frs_to_bytes(&frs, Form::Montgomery),
+            frs_as_mont_bytes(&frs)
+        );
+
+        // The modulus is out of range in either form.
+        let mut bytes = frs_to_bytes(&frs, Form::Canonical);
+        bytes[64..96].copy_from_slice(&frs_to_bytes(&[Fr::zero()], Form::Canonical));
+        for (limb, chunk) in Fr::char().as_ref().iter().zip(bytes[96..128].chunks_mut(8)) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        for &form in &[Form::Canonical, Form::Montgomery] {
+            match bytes_to_frs(&bytes, form) {
+                Err(Error::ElementOutOfRange { index: 3 }) => (),
+                _ => panic!("out-of-range element was accepted"),
+            }
+        }
+
+        match bytes_to_frs(&bytes[1..], Form::Canonical) {
+            Err(Error::InvalidBufferLength {
+                length,
+                multiple: FR_BYTES,
+            }) => assert_eq!(bytes.len() - 1, length),
+            _ => panic!("partial element was accepted"),
+        }
+    }
+
+    #[test]
+    fn test_codec_views() {
+        let mut frs = random_frs(6);
+        let expected = frs.clone();
+
+        let limbs = frs_as_mont_u64s(&frs).to_vec();
+        assert_eq!(&expected[..], mont_u64s_as_frs(&limbs).unwrap());
+
+        let bytes = frs_as_mont_bytes(&frs);
+        let view = mont_bytes_as_frs(bytes).unwrap();
+        assert_eq!(&expected[..], view);
+        assert_eq!(frs.as_ptr(), view.as_ptr());
+
+        let mut limbs = vec![0u64; 4 * FR_LIMBS];
+        limbs[8..12].copy_from_slice(Fr::char().as_ref());
+        match mont_u64s_as_frs(&limbs) {
+            Err(Error::ElementOutOfRange { index: 2 }) => (),
+            _ => panic!("out-of-range element was accepted"),
+        }
+        limbs[8..12].copy_from_slice(&[0; FR_LIMBS]);
+        mont_u64s_as_frs_mut(&mut limbs).unwrap()[2] = Fr::one();
+        assert_eq!(frs_as_mont_u64s(&[Fr::one()]), &limbs[8..12]);
+        assert!(mont_u64s_as_frs(&limbs[..7]).is_err());
+
+        match mont_bytes_as_frs(&frs_as_mont_bytes(&frs)[1..33]) {
+            Err(Error::MisalignedBuffer) => (),
+            _ => panic!("misaligned buffer was accepted"),
+        }
+
+        let arrays = as_generic_arrays::<U3>(&frs).unwrap();
+        assert_eq!(2, arrays.len());
+        assert_eq!(&expected[3..6], arrays[1].as_slice());
+        assert_eq!(&expected[..], generic_arrays_as_frs(arrays));
+        as_generic_arrays_mut::<U3>(&mut frs).unwrap()[0][1] = Fr::one();
+        assert_eq!(Fr::one(), frs[1]);
+        assert!(as_generic_arrays::<U3>(&frs[..5]).is_err());
+    }
+
+    #[test]
+    fn test_codec_columns() {
+        let rows = random_frs(12);
+        let columns = to_columns::<U3>(&rows).unwrap();
+        assert_eq!(4, columns.len());
+        assert_eq!(&[rows[1], rows[5], rows[9]][..], columns[1].as_slice());
+        assert_eq!(rows, from_columns(&columns));
+        assert!(to_columns::<U3>(&rows[..11]).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Openings of column commitments as `ColumnTreeBuilder` makes them, natively and in circuits.
+//!
+//! `ColumnTreeBuilder` hashes each column with the default constants of its arity, and builds a uniform tree over the
+//! column hashes, whose root is the commitment `comm_c`. An opening of column `i` is the column and the Merkle path of
+//! leaf `i`. `column_commitment_circuit` hashes an allocated column with the same constants as the builder, and
+//! enforces that the path leads from its hash to `comm_c`.
+use crate::circuit::poseidon_hash;
+use crate::fingerprint::Fingerprint;
+use crate::merkle::{root_circuit, AllocatedMerklePath, MerkleConstants, MerklePath};
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::Error;
+use bellperson::bls::{Bls12, Fr};
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::{ConstraintSystem, SynthesisError};
+use ff::ScalarEngine as Engine;
+use generic_array::GenericArray;
+
+/// Constants of a column commitment: those columns of arity `C` are hashed with, and those of the tree of arity `T`
+/// over the column hashes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnConstants<C, T>
+where
+    C: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    pub column: PoseidonConstants<Bls12, C>,
+    pub tree: MerkleConstants<T>,
+}
+
+impl<C, T> ColumnConstants<C, T>
+where
+    C: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    /// The constants `ColumnTreeBuilder` uses.
+    pub fn new() -> Self {
+        Self {
+            column: PoseidonConstants::new(),
+            tree: MerkleConstants::new(),
+        }
+    }
+
+    /// Fingerprint of the constants columns are hashed with, to check against `ColumnTreeBuilder::column_fingerprint`.
+    pub fn column_fingerprint(&self) -> Fingerprint {
+        self.column.fingerprint()
+    }
+
+    /// Fingerprint of the constants the tree is hashed with, to check against `ColumnTreeBuilder::tree_fingerprint`.
+    pub fn tree_fingerprint(&self) -> Fingerprint {
+        self.tree.base.fingerprint()
+    }
+
+    /// The leaf committing to `column`.
+    pub fn hash_column(&self, column: &GenericArray<Fr, C>) -> Fr {
+        Poseidon::new_with_preimage(column, &self.column).hash()
+    }
+}
+
+impl<C, T> Default for ColumnConstants<C, T>
+where
+    C: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A column and the Merkle path of its hash.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnOpening<C: Arity<Fr>> {
+    pub column: GenericArray<Fr, C>,
+    pub path: MerklePath,
+}
+
+impl<C: Arity<Fr>> ColumnOpening<C> {
+    /// The opening of column `index`, from the base row and the tree `ColumnTreeBuilder::add_final_columns` returned.
+    /// The tree must be built without discarding rows. Returns `Error::ColumnMismatch` if `column` does not hash to
+    /// the leaf at `index`.
+    pub fn from_column_tree<T: Arity<Fr>>(
+        column: GenericArray<Fr, C>,
+        base: &[Fr],
+        tree: &[Fr],
+        index: usize,
+        constants: &ColumnConstants<C, T>,
+    ) -> Result<Self, Error> {
+        let leaf = base.get(index).ok_or(Error::IndexOutOfBounds)?;
+        if constants.hash_column(&column) != *leaf {
+            return Err(Error::ColumnMismatch { index });
+        }
+
+        let mut nodes = Vec::with_capacity(base.len() + tree.len());
+        nodes.extend_from_slice(base);
+        nodes.extend_from_slice(tree);
+        let path = MerklePath::from_tree::<T>(&nodes, base.len(), index)?;
+
+        Ok(Self { column, path })
+    }
+
+    /// Index of the column.
+    pub fn index(&self) -> usize {
+        self.path.index()
+    }
+
+    /// The commitment the opening leads to. Returns `Error::InvalidMerklePath` if the path does not match the tree.
+    pub fn comm_c<T: Arity<Fr>>(&self, constants: &ColumnConstants<C, T>) -> Result<Fr, Error> {
+        self.path
+            .root(&constants.hash_column(&self.column), &constants.tree)
+    }
+
+    /// Whether the opening proves that the column is committed to by `comm_c`.
+    pub fn verify<T: Arity<Fr>>(&self, comm_c: &Fr, constants: &ColumnConstants<C, T>) -> bool {
+        self.comm_c(constants)
+            .map_or(false, |computed| computed == *comm_c)
+    }
+}
+
+/// A column opening allocated in a circuit.
+pub struct AllocatedColumnOpening<E: Engine> {
+    pub column: Vec<AllocatedNum<E>>,
+    pub path: AllocatedMerklePath<E>,
+}
+
+impl AllocatedColumnOpening<Bls12> {
+    /// Allocate the opening of a column of a tree of height `height`, whose value is `opening` if known.
+    pub fn alloc<CS, C, T>(
+        mut cs: CS,
+        opening: Option<&ColumnOpening<C>>,
+        height: usize,
+        constants: &ColumnConstants<C, T>,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<Bls12>,
+        C: Arity<Fr>,
+        T: Arity<Fr>,
+    {
+        let column = (0..C::to_usize())
+            .map(|i| {
+                AllocatedNum::alloc(cs.namespace(|| format!("column {}", i)), || {
+                    opening
+                        .map(|opening| opening.column[i])
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let path = AllocatedMerklePath::alloc(
+            cs.namespace(|| "path"),
+            opening.map(|opening| &opening.path),
+            height,
+            &constants.tree,
+        )?;
+
+        Ok(Self { column, path })
+    }
+}
+
+/// Circuit enforcing that `opening` opens a column committed to by `comm_c`. Returns the hash of the column.
+pub fn column_commitment_circuit<CS, C, T>(
+    mut cs: CS,
+    opening: &AllocatedColumnOpening<Bls12>,
+    comm_c: &AllocatedNum<Bls12>,
+    constants: &ColumnConstants<C, T>,
+) -> Result<AllocatedNum<Bls12>, SynthesisError>
+where
+    CS: ConstraintSystem<Bls12>,
+    C: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    assert_eq!(
+        C::to_usize(),
+        opening.column.len(),
+        "column length must be the column arity"
+    );
+
+    let leaf = poseidon_hash(
+        cs.namespace(|| "column hash"),
+        opening.column.clone(),
+        &constants.column,
+    )?;
+    let root = root_circuit(
+        cs.namespace(|| "root"),
+        &leaf,
+        &opening.path,
+        &constants.tree,
+    )?;
+    cs.enforce(
+        || "root is comm_c",
+        |lc| lc + root.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + comm_c.get_variable(),
+    );
+
+    Ok(leaf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    use crate::test_trees::build_column_tree;
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use ff::Field;
+    use generic_array::sequence::GenericSequence;
+    use generic_array::typenum::{U11, U8};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    fn test_column_opening() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = ColumnConstants::<U11, U8>::new();
+        let columns = (0..64)
+            .map(|_| GenericArray::<Fr, U11>::generate(|_| Fr::random(&mut rng)))
+            .collect::<Vec<_>>();
+
+        let (base, tree) = build_column_tree::<U11, U8>(&columns);
+        let comm_c = *tree.last().unwrap();
+
+        let opening =
+            ColumnOpening::from_column_tree(columns[42], &base, &tree, 42, &constants).unwrap();
+        assert_eq!(42, opening.index());
+        assert_eq!(2, opening.path.levels.len());
+        assert!(opening.verify(&comm_c, &constants));
+        assert!(!opening.verify(&base[0], &constants));
+
+        assert!(matches!(
+            ColumnOpening::from_column_tree(columns[41], &base, &tree, 42, &constants),
+            Err(Error::ColumnMismatch { index: 42 })
+        ));
+        assert!(matches!(
+            ColumnOpening::from_column_tree(columns[0], &base, &tree, 64, &constants),
+            Err(Error::IndexOutOfBounds)
+        ));
+
+        let mut wrong_column = opening.clone();
+        wrong_column.column[3] = Fr::one();
+        for (opening, comm_c, valid) in [
+            (&opening, comm_c, true),
+            (&opening, base[0], false),
+            (&wrong_column, comm_c, false),
+        ]
+        .iter()
+        {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let comm_c = AllocatedNum::alloc(cs.namespace(|| "comm_c"), || Ok(*comm_c)).unwrap();
+            let allocated = AllocatedColumnOpening::alloc(
+                cs.namespace(|| "opening"),
+                Some(*opening),
+                2,
+                &constants,
+            )
+            .unwrap();
+
+            let leaf = column_commitment_circuit(
+                cs.namespace(|| "column commitment"),
+                &allocated,
+                &comm_c,
+                &constants,
+            )
+            .unwrap();
+            assert_eq!(*valid, cs.is_satisfied());
+            assert_eq!(
+                constants.hash_column(&opening.column),
+                leaf.get_value().unwrap()
+            );
+        }
+    }
+}
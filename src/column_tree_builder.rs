@@ -1,8 +1,9 @@
 use crate::batch_hasher::{Batcher, BatcherType};
 use crate::error::Error;
+use crate::fingerprint::Fingerprint;
 use crate::poseidon::{Poseidon, PoseidonConstants};
 use crate::tree_builder::{TreeBuilder, TreeBuilderTrait};
-use crate::{Arity, BatchHasher};
+use crate::{Arity, BatchHasher, Fingerprinted};
 use bellperson::bls::{Bls12, Fr};
 use ff::Field;
 use generic_array::GenericArray;
@@ -85,24 +86,6 @@ where
         //self.data.iter_mut().for_each(|place| *place = Fr::zero());
     }
 }
-fn as_generic_arrays<'a, A: Arity<Fr>>(vec: &'a [Fr]) -> &'a [GenericArray<Fr, A>] {
-    // It is a programmer error to call `as_generic_arrays` on a vector whose underlying data cannot be divided
-    // into an even number of `GenericArray<Fr, Arity>`.
-    assert_eq!(
-        0,
-        (vec.len() * std::mem::size_of::<Fr>()) % std::mem::size_of::<GenericArray<Fr, A>>()
-    );
-
-    // This block does not affect the underlying `Fr`s. It just groups them into `GenericArray`s of length `Arity`.
-    // We know by the assertion above that `vec` can be evenly divided into these units.
-    unsafe {
-        std::slice::from_raw_parts(
-            vec.as_ptr() as *const () as *const GenericArray<Fr, A>,
-            vec.len() / A::to_usize(),
-        )
-    }
-}
-
 impl<'a, ColumnArity, TreeArity> ColumnTreeBuilder<'a, ColumnArity, TreeArity>
 where
     ColumnArity: Arity<Fr>,
@@ -158,9 +141,24 @@ where
             tree_builder,
         };
 
+        // Columns hashed by the batcher and by `column_constants` must agree.
+        if let Some(batcher) = &builder.column_batcher {
+            builder.column_fingerprint().check(&batcher.fingerprint())?;
+        }
+
         Ok(builder)
     }
 
+    /// Fingerprint of the constants columns are hashed with.
+    pub fn column_fingerprint(&self) -> Fingerprint {
+        self.column_constants.fingerprint()
+    }
+
+    /// Fingerprint of the constants the tree over the column hashes is hashed with.
+    pub fn tree_fingerprint(&self) -> Fingerprint {
+        self.tree_builder.fingerprint()
+    }
+
     pub fn tree_size(&self) -> usize {
         self.tree_builder.tree_size(0)
     }
@@ -0,0 +1,305 @@
+//! Constraint costs of `circuit::poseidon_hash`, computed from the round numbers without synthesizing anything.
+//!
+//! Every s-box costs 3 constraints, each with one auxiliary variable, when its input is allocated. An input which is a
+//! linear combination must be allocated first, which costs one more of each. The first round's s-boxes of constants,
+//! such as the domain tag and constant-length padding, are computed statically and cost nothing. Every later s-box
+//! takes the linear combination left by the MDS matrix, and the output is allocated from one too.
+use crate::hash_type::HashType;
+use crate::poseidon::Arity;
+use crate::{round_numbers, Error, Strength};
+use ff::PrimeField;
+
+/// How an input is passed to a gadget, as a `circuit::Elt`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputKind {
+    /// An `AllocatedNum`.
+    Allocated,
+    /// A `num::Num` involving variables.
+    LinearCombination,
+    /// A `num::Num` involving no variable, as made by `Elt::num_from_fr`.
+    Constant,
+}
+
+impl InputKind {
+    fn first_round_cost(self) -> CircuitCost {
+        match self {
+            InputKind::Allocated => CircuitCost::uniform(3),
+            InputKind::LinearCombination => CircuitCost::uniform(4),
+            InputKind::Constant => CircuitCost::default(),
+        }
+    }
+}
+
+/// Numbers of constraints and auxiliary variables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CircuitCost {
+    pub constraints: usize,
+    pub aux_variables: usize,
+}
+
+impl CircuitCost {
+    /// A cost of `n` constraints, each with its own auxiliary variable.
+    fn uniform(n: usize) -> Self {
+        Self {
+            constraints: n,
+            aux_variables: n,
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            constraints: self.constraints + other.constraints,
+            aux_variables: self.aux_variables + other.aux_variables,
+        }
+    }
+}
+
+/// The cost of a hash, by the rounds it is spent in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoseidonCost {
+    /// Number of permutations: one, or as many as a `SpongeCircuit` needs for variable-length hashes.
+    pub permutations: usize,
+    /// Numbers of full and partial rounds of each permutation.
+    pub rounds: (usize, usize),
+    /// First-round s-boxes computed statically: those of the domain tag, constant inputs and padding.
+    pub folded_s_boxes: usize,
+    /// The first round of the first permutation.
+    pub first_round: CircuitCost,
+    /// Every other full round.
+    pub full_rounds: CircuitCost,
+    pub partial_rounds: CircuitCost,
+    /// Allocating the output.
+    pub output: CircuitCost,
+}
+
+impl PoseidonCost {
+    pub fn total(&self) -> CircuitCost {
+        self.first_round
+            .add(self.full_rounds)
+            .add(self.partial_rounds)
+            .add(self.output)
+    }
+}
+
+/// The cost of `circuit::poseidon_hash_elts` with constants of `strength` and `hash_type`, for inputs of the given
+/// kinds. Returns `Error::InvalidPreimageLength` for preimages the hash type does not accept, and
+/// `Error::UnsupportedHashType` for hash types without constants.
+pub fn poseidon_hash_cost<F, A>(
+    strength: Strength,
+    hash_type: &HashType<F, A>,
+    inputs: &[InputKind],
+) -> Result<PoseidonCost, Error>
+where
+    F: PrimeField,
+    A: Arity<F>,
+{
+    if !hash_type.is_supported() {
+        return Err(Error::UnsupportedHashType(hash_type.name()));
+    }
+    let arity = A::to_usize();
+    let width = arity + 1;
+
+    // Kinds of the rate elements of the first permutation, and the number of permutations.
+    let (first_block, permutations) = match hash_type {
+        HashType::VariableLength => {
+            // Sponge padding adds a constant one after the input, and leaves constant zeros, if the input ends in the
+            // first block. Otherwise the first block is all input.
+            let mut first_block = inputs.iter().take(arity).cloned().collect::<Vec<_>>();
+            first_block.resize(arity, InputKind::Constant);
+            (first_block, inputs.len() / arity + 1)
+        }
+        HashType::ConstantLength(length) => {
+            if inputs.len() != *length {
+                return Err(Error::InvalidPreimageLength {
+                    expected: *length,
+                    actual: inputs.len(),
+                });
+            }
+            let mut first_block = inputs.to_vec();
+            first_block.resize(arity, InputKind::Constant);
+            (first_block, 1)
+        }
+        _ => {
+            if inputs.len() != arity {
+                return Err(Error::InvalidPreimageLength {
+                    expected: arity,
+                    actual: inputs.len(),
+                });
+            }
+            (inputs.to_vec(), 1)
+        }
+    };
+
+    let (full_rounds, partial_rounds) = round_numbers(arity, &strength);
+    let folded_s_boxes = 1 + first_block
+        .iter()
+        .filter(|&&kind| kind == InputKind::Constant)
+        .count();
+    let first_round = first_block
+        .iter()
+        .fold(CircuitCost::default(), |cost, kind| {
+            cost.add(kind.first_round_cost())
+        });
+
+    Ok(PoseidonCost {
+        permutations,
+        rounds: (full_rounds, partial_rounds),
+        folded_s_boxes,
+        first_round,
+        full_rounds: CircuitCost::uniform(4 * width * (full_rounds * permutations - 1)),
+        partial_rounds: CircuitCost::uniform(4 * partial_rounds * permutations),
+        output: CircuitCost::uniform(1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{poseidon_hash_elts, Elt};
+    use crate::poseidon::PoseidonConstants;
+    use bellperson::bls::{Bls12, Fr};
+    use bellperson::gadgets::num::{AllocatedNum, Num};
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use bellperson::ConstraintSystem;
+    use ff::Field;
+    use generic_array::typenum::*;
+
+    const KINDS: [InputKind; 3] = [
+        InputKind::Allocated,
+        InputKind::LinearCombination,
+        InputKind::Constant,
+    ];
+
+    fn synthesized_cost<A: Arity<Fr>>(
+        strength: Strength,
+        hash_type: HashType<Fr, A>,
+        inputs: &[InputKind],
+    ) -> CircuitCost {
+        let constants =
+            PoseidonConstants::<Bls12, A>::new_with_strength_and_type(strength, hash_type);
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let preimage = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| {
+                let mut cs = cs.namespace(|| format!("input {}", i));
+                let mut alloc = |name: &'static str| {
+                    AllocatedNum::alloc(cs.namespace(|| name), || Ok(Fr::one())).unwrap()
+                };
+                match kind {
+                    InputKind::Allocated => Elt::Allocated(alloc("allocated")),
+                    InputKind::LinearCombination => {
+                        Elt::Num(Num::from(alloc("first")).add(&Num::from(alloc("second"))))
+                    }
+                    InputKind::Constant => {
+                        Elt::num_from_fr::<TestConstraintSystem<Bls12>>(Fr::one())
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        let aux_before = cs.scalar_aux().len();
+
+        poseidon_hash_elts(cs.namespace(|| "hash"), preimage, &constants).unwrap();
+        assert!(cs.is_satisfied(), "constraints not satisfied");
+
+        CircuitCost {
+            constraints: cs.num_constraints(),
+            aux_variables: cs.scalar_aux().len() - aux_before,
+        }
+    }
+
+    fn check_arity<A: Arity<Fr>>() {
+        let arity = A::to_usize();
+        // Every kind at every position of the first block, cycling through them.
+        let mixed = (0..arity).map(|i| KINDS[i % 3]).collect::<Vec<_>>();
+        let mut cases: Vec<(HashType<Fr, A>, Vec<InputKind>)> = vec![
+            (HashType::MerkleTree, mixed.clone()),
+            (
+                HashType::ConstantLength(1),
+                vec![InputKind::LinearCombination],
+            ),
+            (HashType::VariableLength, vec![]),
+        ];
+        // Variable-length hashes are only checked for small arities, whose extra permutations are cheap.
+        if arity <= 4 {
+            cases.push((
+                HashType::VariableLength,
+                (0..2 * arity + 1).map(|i| KINDS[i % 3]).collect(),
+            ));
+        }
+
+        for strength in [Strength::Standard, Strength::Strengthened].iter() {
+            for (hash_type, inputs) in cases.iter() {
+                let estimated = poseidon_hash_cost(*strength, hash_type, inputs).unwrap();
+                assert_eq!(
+                    synthesized_cost(*strength, hash_type.clone(), inputs),
+                    estimated.total(),
+                    "arity {}, {:?}",
+                    arity,
+                    hash_type
+                );
+            }
+        }
+    }
+
+    macro_rules! check_arities {
+        ($($a:ty),*) => {
+            $(check_arity::<$a>();)*
+        };
+    }
+
+    #[test]
+    fn test_cost_matches_synthesis() {
+        check_arities!(
+            U2, U3, U4, U5, U6, U7, U8, U9, U10, U11, U12, U13, U14, U15, U16, U17, U18, U19, U20,
+            U21, U22, U23, U24, U25, U26, U27, U28, U29, U30, U31, U32, U33, U34, U35, U36
+        );
+    }
+
+    #[test]
+    fn test_cost_breakdown() {
+        let cost = poseidon_hash_cost::<Fr, U2>(
+            Strength::Standard,
+            &HashType::MerkleTree,
+            &[InputKind::Allocated; 2],
+        )
+        .unwrap();
+        let (full_rounds, partial_rounds) = cost.rounds;
+        assert_eq!((8, 55), (full_rounds, partial_rounds));
+        assert_eq!(1, cost.folded_s_boxes);
+        assert_eq!(CircuitCost::uniform(6), cost.first_round);
+        assert_eq!(CircuitCost::uniform(4 * 3 * 7), cost.full_rounds);
+        assert_eq!(CircuitCost::uniform(4 * 55), cost.partial_rounds);
+        // The constraint count `circuit` tests pin for this hash.
+        assert_eq!(311, cost.total().constraints);
+
+        let padded = poseidon_hash_cost::<Fr, U4>(
+            Strength::Standard,
+            &HashType::ConstantLength(1),
+            &[InputKind::Constant],
+        )
+        .unwrap();
+        assert_eq!(5, padded.folded_s_boxes);
+        assert_eq!(CircuitCost::default(), padded.first_round);
+
+        assert!(matches!(
+            poseidon_hash_cost::<Fr, U4>(
+                Strength::Standard,
+                &HashType::ConstantLength(2),
+                &[InputKind::Allocated]
+            ),
+            Err(Error::InvalidPreimageLength {
+                expected: 2,
+                actual: 1
+            })
+        ));
+        assert!(matches!(
+            poseidon_hash_cost::<Fr, U4>(
+                Strength::Standard,
+                &HashType::MerkleTreeSparse(1),
+                &[InputKind::Allocated; 4]
+            ),
+            Err(Error::UnsupportedHashType(_))
+        ));
+    }
+}
@@ -0,0 +1,337 @@
+//! `Digest`, a field element with canonical encodings, for digests and commitments which leave the program.
+//!
+//! - Bytes: the 32 little-endian bytes of the element's canonical integer value, not of its Montgomery form.
+//! - Hex: the 64 lowercase hex digits of those bytes, in order. This is `Display` and `FromStr`.
+//! - Decimal: the canonical integer value in base 10, without leading zeros.
+//!
+//! Every decoder is strict: it rejects malformed input and values which are not below the field modulus with
+//! `Error::DecodingError`, so that each digest has exactly one encoding of each kind. With serde, digests are hex
+//! strings in human-readable formats and 32 bytes otherwise.
+use crate::Error;
+use bellperson::bls::{Fr, FrRepr};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::str::FromStr;
+use ff::PrimeField;
+use serde::de::{self, SeqAccess, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A field element output by Poseidon. See the module documentation for its encodings.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Digest(Fr);
+
+/// Commitments are digests, with the same encodings.
+pub type Commitment = Digest;
+
+/// `10^19`, the largest power of ten below `2^64`.
+const DECIMAL_CHUNK: u64 = 10_000_000_000_000_000_000;
+const DECIMAL_CHUNK_DIGITS: usize = 19;
+
+impl Digest {
+    pub fn new(element: Fr) -> Self {
+        Digest(element)
+    }
+
+    pub fn scalar(&self) -> Fr {
+        self.0
+    }
+
+    /// The canonical little-endian encoding.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let repr = self.0.into_repr();
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.chunks_mut(8).zip(repr.as_ref()) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode the canonical little-endian encoding.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, Error> {
+        let mut repr = FrRepr::default();
+        for (limb, chunk) in repr.as_mut().iter_mut().zip(bytes.chunks(8)) {
+            let mut limb_bytes = [0u8; 8];
+            limb_bytes.copy_from_slice(chunk);
+            *limb = u64::from_le_bytes(limb_bytes);
+        }
+        Self::from_repr(repr)
+    }
+
+    /// Like `from_bytes`, but also rejects slices which are not 32 bytes long.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != 32 {
+            return Err(Error::DecodingError);
+        }
+        let mut array = [0u8; 32];
+        array.copy_from_slice(bytes);
+        Self::from_bytes(&array)
+    }
+
+    /// The canonical integer value in base 10.
+    pub fn to_decimal(&self) -> String {
+        let mut limbs = [0u64; 4];
+        limbs.copy_from_slice(self.0.into_repr().as_ref());
+
+        // Split off base 10^19 chunks, least significant first.
+        let mut chunks = Vec::new();
+        loop {
+            let mut remainder = 0u128;
+            for limb in limbs.iter_mut().rev() {
+                let value = (remainder << 64) | *limb as u128;
+                *limb = (value / DECIMAL_CHUNK as u128) as u64;
+                remainder = value % DECIMAL_CHUNK as u128;
+            }
+            chunks.push(remainder as u64);
+            if limbs.iter().all(|&limb| limb == 0) {
+                break;
+            }
+        }
+
+        let mut decimal = chunks.pop().expect("at least one chunk").to_string();
+        for chunk in chunks.iter().rev() {
+            decimal.push_str(&format!("{:0width$}", chunk, width = DECIMAL_CHUNK_DIGITS));
+        }
+        decimal
+    }
+
+    /// Decode the canonical integer value in base 10.
+    pub fn from_decimal(s: &str) -> Result<Self, Error> {
+        if s.is_empty()
+            || !s.bytes().all(|b| b.is_ascii_digit())
+            || (s.len() > 1 && s.starts_with('0'))
+        {
+            return Err(Error::DecodingError);
+        }
+
+        let mut repr = FrRepr::default();
+        for digit in s.bytes() {
+            let mut carry = (digit - b'0') as u128;
+            for limb in repr.as_mut().iter_mut() {
+                let value = *limb as u128 * 10 + carry;
+                *limb = value as u64;
+                carry = value >> 64;
+            }
+            if carry != 0 {
+                return Err(Error::DecodingError);
+            }
+        }
+        Self::from_repr(repr)
+    }
+
+    fn from_repr(repr: FrRepr) -> Result<Self, Error> {
+        Fr::from_repr(repr)
+            .map(Digest)
+            .map_err(|_| Error::DecodingError)
+    }
+}
+
+impl From<Fr> for Digest {
+    fn from(element: Fr) -> Self {
+        Digest(element)
+    }
+}
+
+impl From<Digest> for Fr {
+    fn from(digest: Digest) -> Self {
+        digest.0
+    }
+}
+
+impl Hash for Digest {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_bytes().hash(state)
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.to_bytes().iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+impl FromStr for Digest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::DecodingError);
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte =
+                u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| Error::DecodingError)?;
+        }
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.to_bytes())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DigestVisitor;
+
+        impl<'de> Visitor<'de> for DigestVisitor {
+            type Value = Digest;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a field element as 64 hex digits or 32 little-endian bytes")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Digest, E> {
+                s.parse()
+                    .map_err(|_| E::invalid_value(Unexpected::Str(s), &self))
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Digest, E> {
+                Digest::from_slice(bytes)
+                    .map_err(|_| E::invalid_value(Unexpected::Bytes(bytes), &self))
+            }
+
+            fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Digest, S::Error> {
+                let mut bytes = [0u8; 32];
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                if seq.next_element::<u8>()?.is_some() {
+                    return Err(de::Error::invalid_length(33, &self));
+                }
+                Digest::from_bytes(&bytes)
+                    .map_err(|_| de::Error::invalid_value(Unexpected::Bytes(&bytes), &self))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(DigestVisitor)
+        } else {
+            deserializer.deserialize_bytes(DigestVisitor)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalar_from_u64;
+    use ff::Field;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    const MODULUS: &str =
+        "52435875175126190479447740508185965837690552500527637822603658699938581184513";
+
+    fn minus_one() -> Digest {
+        let mut minus_one = Fr::one();
+        minus_one.negate();
+        Digest(minus_one)
+    }
+
+    #[test]
+    fn test_digest_encodings() {
+        let one = Digest(Fr::one());
+        let mut bytes = [0u8; 32];
+        bytes[0] = 1;
+        assert_eq!(bytes, one.to_bytes());
+        assert_eq!(format!("01{}", "00".repeat(31)), one.to_string());
+        assert_eq!("1", one.to_decimal());
+        assert_eq!("0", Digest(Fr::zero()).to_decimal());
+        assert_eq!(
+            "10000000000000000000",
+            Digest(scalar_from_u64(DECIMAL_CHUNK)).to_decimal()
+        );
+        assert_eq!(MODULUS.replace("513", "512"), minus_one().to_decimal());
+
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        for digest in [one, minus_one(), Digest(Fr::random(&mut rng))].iter() {
+            assert_eq!(*digest, Digest::from_bytes(&digest.to_bytes()).unwrap());
+            assert_eq!(*digest, digest.to_string().parse().unwrap());
+            assert_eq!(*digest, digest.to_string().to_uppercase().parse().unwrap());
+            assert_eq!(*digest, Digest::from_decimal(&digest.to_decimal()).unwrap());
+            assert_eq!(digest.scalar(), Fr::from(*digest));
+        }
+    }
+
+    #[test]
+    fn test_digest_decoding_errors() {
+        // The modulus itself is out of range, in every encoding.
+        let mut modulus = minus_one().to_bytes();
+        modulus[0] += 1;
+        assert!(matches!(
+            Digest::from_bytes(&modulus),
+            Err(Error::DecodingError)
+        ));
+        let hex = modulus
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        assert!(matches!(hex.parse::<Digest>(), Err(Error::DecodingError)));
+        assert!(matches!(
+            Digest::from_decimal(MODULUS),
+            Err(Error::DecodingError)
+        ));
+        assert!(matches!(
+            Digest::from_decimal(&"9".repeat(80)),
+            Err(Error::DecodingError)
+        ));
+
+        let one = Digest(Fr::one()).to_string();
+        for invalid in &[
+            String::new(),
+            one[2..].to_string(),
+            format!("0x{}", one),
+            one.replace("0", "g"),
+            format!("{}00", one),
+        ] {
+            assert!(matches!(
+                invalid.parse::<Digest>(),
+                Err(Error::DecodingError)
+            ));
+        }
+        for invalid in &["", "01", "-1", "+1", "1.0", " 1"] {
+            assert!(matches!(
+                Digest::from_decimal(invalid),
+                Err(Error::DecodingError)
+            ));
+        }
+        assert!(matches!(
+            Digest::from_slice(&[1u8; 31]),
+            Err(Error::DecodingError)
+        ));
+    }
+
+    #[test]
+    fn test_digest_serde() {
+        let digest = minus_one();
+        let json = serde_json::to_string(&digest).unwrap();
+        assert_eq!(format!("\"{}\"", digest), json);
+        assert_eq!(digest, serde_json::from_str::<Digest>(&json).unwrap());
+
+        let mut modulus = digest.to_bytes();
+        modulus[0] += 1;
+        let hex = modulus
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        assert!(serde_json::from_str::<Digest>(&format!("\"{}\"", hex)).is_err());
+    }
+}
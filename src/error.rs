@@ -1,6 +1,7 @@
+use crate::fingerprint::Fingerprint;
 #[cfg(feature = "gpu")]
 use crate::triton::cl;
-use std::{error, fmt};
+use core::fmt;
 
 #[derive(Debug, Clone)]
 #[cfg(any(feature = "gpu", feature = "opencl"))]
@@ -17,7 +18,7 @@ pub enum ClError {
 }
 
 #[cfg(any(feature = "gpu", feature = "opencl"))]
-pub type ClResult<T> = std::result::Result<T, ClError>;
+pub type ClResult<T> = core::result::Result<T, ClError>;
 
 #[cfg(any(feature = "gpu", feature = "opencl"))]
 impl fmt::Display for ClError {
@@ -56,6 +57,92 @@ pub enum Error {
     #[cfg(feature = "gpu")]
     TritonError(String),
     DecodingError,
+    /// A constant-length hash must have a length in `1..=arity`.
+    InvalidConstantLength {
+        length: usize,
+        arity: usize,
+    },
+    /// The preimage does not have the length required by the hash type.
+    InvalidPreimageLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// The operation is not available for the hash type, which is given.
+    InvalidHashType(String),
+    /// The hash type, which is given, is not yet supported.
+    UnsupportedHashType(String),
+    /// Custom hash type identifiers must not be multiples of 2^24.
+    InvalidCustomIdentifier(u64),
+    /// The leaf count of a tree is not a power of its arity.
+    InvalidLeafCount {
+        leaf_count: usize,
+        arity: usize,
+    },
+    /// Rows to discard must exclude the base row and the root.
+    InvalidRowsToDiscard {
+        rows_to_discard: usize,
+        tree_height: usize,
+    },
+    /// The batch is larger than the batch hasher's maximum.
+    BatchTooLarge {
+        batch_size: usize,
+        max_batch_size: usize,
+    },
+    /// The constants in use do not have the expected fingerprint.
+    FingerprintMismatch {
+        expected: Fingerprint,
+        actual: Fingerprint,
+    },
+    /// The given string is not a hex-encoded fingerprint.
+    InvalidFingerprint(String),
+    /// No parameter profile has the given name.
+    UnknownProfile(String),
+    /// The profile does not have the requested arity.
+    ProfileArityMismatch {
+        profile: String,
+        arity: usize,
+    },
+    /// A batch hasher disagreed with the CPU on the known answer at `index`.
+    SelfTestFailed {
+        index: usize,
+    },
+    /// A batch hasher's output at `index` of the batch disagreed with the CPU.
+    CrossCheckFailed {
+        index: usize,
+    },
+    /// Sample rates must be in `0.0..=1.0`.
+    InvalidSampleRate(f64),
+    /// A signature does not have the expected number of elements.
+    InvalidSignatureLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// Every one of the given number of one-time keys of a signing key has been used.
+    SigningKeyExhausted(usize),
+    /// The buffer length is not a multiple of the size of its items.
+    InvalidBufferLength {
+        length: usize,
+        multiple: usize,
+    },
+    /// The buffer is not aligned for viewing it as field elements.
+    MisalignedBuffer,
+    /// The element at `index` of a buffer is not below the field modulus.
+    ElementOutOfRange {
+        index: usize,
+    },
+    /// Trees, or forests of trees, do not have the expected number of nodes or trees.
+    InvalidTreeLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// The given level of a Merkle path does not match the tree, or the path is shorter than that level.
+    InvalidMerklePath {
+        level: usize,
+    },
+    /// The column does not hash to the leaf at `index` of a column tree.
+    ColumnMismatch {
+        index: usize,
+    },
     Other(String),
 }
 
@@ -73,7 +160,7 @@ impl From<triton::Error> for Error {
     }
 }
 
-impl error::Error for Error {}
+impl std::error::Error for Error {}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -89,6 +176,97 @@ impl fmt::Display for Error {
             #[cfg(feature = "gpu")]
             Error::TritonError(e) => write!(f, "Neptune-triton Error: {}", e),
             Error::DecodingError => write!(f, "PrimeFieldDecodingError"),
+            Error::InvalidConstantLength { length, arity } => write!(
+                f,
+                "Constant length {} is not between 1 and the arity {}.",
+                length, arity
+            ),
+            Error::InvalidPreimageLength { expected, actual } => write!(
+                f,
+                "Invalid preimage length {}, expected {}.",
+                actual, expected
+            ),
+            Error::InvalidHashType(s) => write!(f, "Invalid hash type: {}", s),
+            Error::UnsupportedHashType(s) => write!(f, "Unsupported hash type: {}", s),
+            Error::InvalidCustomIdentifier(id) => write!(
+                f,
+                "Custom hash type identifier {} is a multiple of 2^24.",
+                id
+            ),
+            Error::InvalidLeafCount { leaf_count, arity } => write!(
+                f,
+                "Tree leaf count {} is not a power of arity {}.",
+                leaf_count, arity
+            ),
+            Error::InvalidRowsToDiscard {
+                rows_to_discard,
+                tree_height,
+            } => write!(
+                f,
+                "Cannot discard {} rows of a tree of height {}.",
+                rows_to_discard, tree_height
+            ),
+            Error::BatchTooLarge {
+                batch_size,
+                max_batch_size,
+            } => write!(
+                f,
+                "Batch size {} exceeds the maximum batch size {}.",
+                batch_size, max_batch_size
+            ),
+            Error::FingerprintMismatch { expected, actual } => write!(
+                f,
+                "Constants fingerprint mismatch: expected {}, got {}.",
+                expected, actual
+            ),
+            Error::InvalidFingerprint(s) => write!(f, "Invalid fingerprint: {}", s),
+            Error::UnknownProfile(s) => write!(f, "Unknown parameter profile: {}", s),
+            Error::ProfileArityMismatch { profile, arity } => write!(
+                f,
+                "Parameter profile {} does not have arity {}.",
+                profile, arity
+            ),
+            Error::SelfTestFailed { index } => write!(
+                f,
+                "Batch hasher self-test failed on known answer {}.",
+                index
+            ),
+            Error::CrossCheckFailed { index } => {
+                write!(f, "Batch hasher output {} disagrees with the CPU.", index)
+            }
+            Error::InvalidSampleRate(rate) => {
+                write!(f, "Sample rate {} is not between 0 and 1.", rate)
+            }
+            Error::InvalidSignatureLength { expected, actual } => write!(
+                f,
+                "Invalid signature length {}, expected {}.",
+                actual, expected
+            ),
+            Error::SigningKeyExhausted(count) => {
+                write!(f, "All {} signatures of the signing key are used.", count)
+            }
+            Error::InvalidBufferLength { length, multiple } => write!(
+                f,
+                "Buffer length {} is not a multiple of {}.",
+                length, multiple
+            ),
+            Error::MisalignedBuffer => write!(f, "Buffer is not aligned for field elements."),
+            Error::ElementOutOfRange { index } => write!(
+                f,
+                "Element {} of the buffer is not below the field modulus.",
+                index
+            ),
+            Error::InvalidTreeLength { expected, actual } => {
+                write!(f, "Invalid tree length {}, expected {}.", actual, expected)
+            }
+            Error::InvalidMerklePath { level } => write!(
+                f,
+                "Level {} of the Merkle path does not match the tree.",
+                level
+            ),
+            Error::ColumnMismatch { index } => {
+                write!(f, "Column does not hash to leaf {} of the tree.", index)
+            }
             Error::Other(s) => write!(f, "{}", s),
         }
     }
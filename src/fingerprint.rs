@@ -0,0 +1,261 @@
+//! Fingerprints of Poseidon parameter sets.
+//!
+//! A `Fingerprint` is a Blake2s digest of everything which determines the hash function: the field modulus, the width,
+//! the numbers of full and partial rounds, and the constants the optimized permutation uses, in the layout GPU kernels
+//! read them: the domain tag, the compressed round constants, the MDS matrix, the pre-sparse matrix and the sparse
+//! matrices. Two parties whose constants have equal fingerprints compute the same digests, however their constants
+//! were built. GPU batch hashers fingerprint the constants they actually pass to their kernels, rather than the
+//! `PoseidonConstants` those were built from.
+//!
+//! Field elements and integers are hashed as little-endian 64-bit limbs of their canonical form, so fingerprints do not
+//! depend on the platform.
+use crate::poseidon::{Arity, PoseidonConstants};
+use crate::Error;
+use core::fmt;
+use core::str::FromStr;
+use ff::{PrimeField, PrimeFieldRepr, ScalarEngine};
+
+/// Prefix of the hashed data. Bump the version if the encoding below changes.
+const FINGERPRINT_PREFIX: &[u8] = b"neptune-constants-v1";
+
+/// Fingerprint of a set of `PoseidonConstants`. Displayed and parsed as 64 lowercase hex digits.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// Placeholder for constants whose fingerprint is not computed yet.
+    pub(crate) const UNSET: Fingerprint = Fingerprint([0; 32]);
+
+    /// Fingerprint of `constants`. `PoseidonConstants::fingerprint` returns it as computed on construction.
+    pub fn of<E, A>(constants: &PoseidonConstants<E, A>) -> Self
+    where
+        E: ScalarEngine,
+        A: Arity<E::Fr>,
+    {
+        Self::of_kernel_constants(
+            constants.width(),
+            constants.full_rounds,
+            constants.partial_rounds,
+            &kernel_constants(constants),
+        )
+    }
+
+    /// Fingerprint of constants for `width`, `full_rounds` and `partial_rounds`, laid out as `kernel_constants` lays
+    /// them out.
+    pub(crate) fn of_kernel_constants<F: PrimeField>(
+        width: usize,
+        full_rounds: usize,
+        partial_rounds: usize,
+        elements: &[F],
+    ) -> Self {
+        let limbs = elements
+            .iter()
+            .flat_map(|element| element.into_repr().as_ref().to_vec())
+            .collect::<Vec<_>>();
+        Self::of_kernel_limbs::<F>(width, full_rounds, partial_rounds, &limbs)
+    }
+
+    /// Like `of_kernel_constants`, with the elements given as the 64-bit limbs of their canonical form, least
+    /// significant first.
+    pub(crate) fn of_kernel_limbs<F: PrimeField>(
+        width: usize,
+        full_rounds: usize,
+        partial_rounds: usize,
+        limbs: &[u64],
+    ) -> Self {
+        let mut state = blake2s_simd::State::new();
+
+        state.update(FINGERPRINT_PREFIX);
+        for limb in F::char().as_ref() {
+            state.update(&limb.to_le_bytes());
+        }
+        for n in &[width, full_rounds, partial_rounds] {
+            state.update(&(*n as u64).to_le_bytes());
+        }
+        for limb in limbs {
+            state.update(&limb.to_le_bytes());
+        }
+
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(state.finalize().as_bytes());
+        Fingerprint(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Returns `Error::FingerprintMismatch` unless `actual` equals `self`, the expected fingerprint.
+    pub fn check(&self, actual: &Fingerprint) -> Result<(), Error> {
+        if self == actual {
+            Ok(())
+        } else {
+            Err(Error::FingerprintMismatch {
+                expected: *self,
+                actual: *actual,
+            })
+        }
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::InvalidFingerprint(s.to_string()));
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16)
+                .map_err(|_| Error::InvalidFingerprint(s.to_string()))?;
+        }
+        Ok(Fingerprint(bytes))
+    }
+}
+
+/// The constants of the optimized permutation in the order GPU kernels read them: the domain tag, the compressed round
+/// constants, the MDS matrix and the pre-sparse matrix by rows, then `w_hat` followed by `v_rest` of each sparse matrix.
+pub(crate) fn kernel_constants<E, A>(constants: &PoseidonConstants<E, A>) -> Vec<E::Fr>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut elements = vec![constants.domain_tag];
+    elements.extend(&constants.compressed_round_constants);
+    elements.extend(constants.mds_matrices.m.iter().flatten());
+    elements.extend(constants.pre_sparse_matrix.iter().flatten());
+    for sparse_matrix in &constants.sparse_matrixes {
+        elements.extend(&sparse_matrix.w_hat);
+        elements.extend(&sparse_matrix.v_rest);
+    }
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_type::HashType;
+    use crate::Strength;
+    use bellperson::bls::Bls12;
+    use generic_array::typenum::{U2, U4, U8};
+
+    #[test]
+    fn test_fingerprint() {
+        let standard = PoseidonConstants::<Bls12, U8>::new();
+        let fingerprint = standard.fingerprint();
+
+        // Independently built constants have the same fingerprint.
+        assert_eq!(
+            fingerprint,
+            PoseidonConstants::<Bls12, U8>::new_with_strength(Strength::Standard).fingerprint()
+        );
+        assert!(fingerprint.check(&standard.fingerprint()).is_ok());
+
+        // Strength, arity and domain tag are all covered.
+        let others = [
+            PoseidonConstants::<Bls12, U8>::new_with_strength(Strength::Strengthened).fingerprint(),
+            PoseidonConstants::<Bls12, U8>::new_constant_length(8).fingerprint(),
+            PoseidonConstants::<Bls12, U8>::new_constant_length(7).fingerprint(),
+            PoseidonConstants::<Bls12, U8>::new_with_strength_and_type(
+                Strength::Standard,
+                HashType::VariableLength,
+            )
+            .fingerprint(),
+            PoseidonConstants::<Bls12, U4>::new().fingerprint(),
+            PoseidonConstants::<Bls12, U2>::new().fingerprint(),
+        ];
+        for (i, a) in others.iter().enumerate() {
+            assert_ne!(fingerprint, *a);
+            for b in others.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+
+        match fingerprint.check(&others[0]) {
+            Err(Error::FingerprintMismatch { expected, actual }) => {
+                assert_eq!(fingerprint, expected);
+                assert_eq!(others[0], actual);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Any change to the constants the permutation uses changes their fingerprint. The stored one is not recomputed.
+        let mut tampered = standard.clone();
+        tampered.compressed_round_constants[17] = tampered.compressed_round_constants[18];
+        assert_ne!(fingerprint, Fingerprint::of(&tampered));
+        let mut tampered = standard.clone();
+        tampered.mds_matrices.m[1][2] = tampered.mds_matrices.m[1][3];
+        assert_ne!(fingerprint, Fingerprint::of(&tampered));
+        let mut tampered = standard.clone();
+        tampered.sparse_matrixes[3].w_hat[1] = tampered.sparse_matrixes[3].w_hat[2];
+        assert_ne!(fingerprint, Fingerprint::of(&tampered));
+        assert_eq!(fingerprint, tampered.fingerprint());
+
+        // Constants derived with another length are fingerprinted anew.
+        let constant_length = PoseidonConstants::<Bls12, U8>::new_constant_length(8);
+        assert_eq!(
+            Fingerprint::of(&constant_length.with_length(7)),
+            constant_length.with_length(7).fingerprint()
+        );
+        assert_eq!(others[2], constant_length.with_length(7).fingerprint());
+    }
+
+    #[test]
+    fn test_kernel_constants() {
+        let constants = PoseidonConstants::<Bls12, U8>::new();
+        let elements = kernel_constants(&constants);
+        let width = constants.width();
+
+        // The size of the constants buffer of the OpenCL kernel.
+        assert_eq!(
+            1 + width * constants.full_rounds
+                + constants.partial_rounds
+                + 2 * width * width
+                + constants.partial_rounds * (2 * width - 1),
+            elements.len()
+        );
+        assert_eq!(constants.domain_tag, elements[0]);
+        assert_eq!(
+            constants.fingerprint(),
+            Fingerprint::of_kernel_constants(
+                width,
+                constants.full_rounds,
+                constants.partial_rounds,
+                &elements
+            )
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_hex() {
+        let fingerprint = PoseidonConstants::<Bls12, U2>::new().fingerprint();
+        let hex = fingerprint.to_string();
+
+        assert_eq!(64, hex.len());
+        assert_eq!(hex, hex.to_lowercase());
+        assert_eq!(fingerprint, hex.parse().unwrap());
+        assert_eq!(fingerprint, hex.to_uppercase().parse().unwrap());
+
+        assert!(hex[1..].parse::<Fingerprint>().is_err());
+        assert!(format!("{}0", hex).parse::<Fingerprint>().is_err());
+        assert!(format!("{}g", &hex[1..]).parse::<Fingerprint>().is_err());
+        assert!(format!("{}é", &hex[2..]).parse::<Fingerprint>().is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! Groth16 proofs of circuits such as those of `statements`, with bellperson's CPU prover.
+//!
+//! Parameters are generated from a circuit without its witness, and are specific to the circuit's shape: the constants,
+//! the preimage length or the tree height. They are generated with randomness from `rng`, and are only sound if it is
+//! discarded afterwards, so parameters for production must come from a trusted setup instead.
+use bellperson::bls::{Bls12, Fr};
+use bellperson::groth16::{
+    create_random_proof, generate_random_parameters, prepare_verifying_key, verify_proof,
+    Parameters, PreparedVerifyingKey, Proof,
+};
+use bellperson::{Circuit, SynthesisError};
+use rand_core::RngCore;
+
+/// Generate parameters for circuits with the shape of `circuit`, which need not have a witness.
+pub fn generate_parameters<C, R>(
+    circuit: C,
+    rng: &mut R,
+) -> Result<Parameters<Bls12>, SynthesisError>
+where
+    C: Circuit<Bls12>,
+    R: RngCore,
+{
+    generate_random_parameters::<Bls12, _, _>(circuit, rng)
+}
+
+/// The verifying key of `params`, prepared for verifying any number of proofs.
+pub fn verifying_key(params: &Parameters<Bls12>) -> PreparedVerifyingKey<Bls12> {
+    prepare_verifying_key(&params.vk)
+}
+
+/// Prove `circuit`, which must have a witness.
+pub fn prove<C, R>(
+    circuit: C,
+    params: &Parameters<Bls12>,
+    rng: &mut R,
+) -> Result<Proof<Bls12>, SynthesisError>
+where
+    C: Circuit<Bls12>,
+    R: RngCore,
+{
+    create_random_proof(circuit, params, rng)
+}
+
+/// Whether `proof` proves the statement of the circuit `vk` was generated for, with `public_inputs`.
+pub fn verify(
+    vk: &PreparedVerifyingKey<Bls12>,
+    proof: &Proof<Bls12>,
+    public_inputs: &[Fr],
+) -> Result<bool, SynthesisError> {
+    verify_proof(vk, proof, public_inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon::{Poseidon, PoseidonConstants};
+    use crate::statements::PreimageCircuit;
+    use ff::Field;
+    use generic_array::typenum::U2;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_groth16_preimage() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let preimage = vec![Fr::random(&mut rng), Fr::random(&mut rng)];
+        let digest = Poseidon::new_with_preimage(&preimage, &constants).hash();
+
+        let blank = PreimageCircuit {
+            constants: &constants,
+            preimage: vec![None; 2],
+        };
+        let params = generate_parameters(blank, &mut rng).unwrap();
+        let vk = verifying_key(&params);
+
+        let circuit = PreimageCircuit {
+            constants: &constants,
+            preimage: preimage.into_iter().map(Some).collect(),
+        };
+        let proof = prove(circuit, &params, &mut rng).unwrap();
+
+        assert!(verify(&vk, &proof, &[digest]).unwrap());
+        assert!(!verify(&vk, &proof, &[Fr::one()]).unwrap());
+    }
+}
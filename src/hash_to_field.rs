@@ -0,0 +1,342 @@
+//! Hash-to-field: map arbitrary inputs to any number of uniformly distributed field elements, and field elements to
+//! bounded integers.
+//!
+//! Inputs are absorbed into a `Sponge` after a domain string and the requested output count, so outputs of different
+//! domains or counts are independent. Modelling the permutation as random, every output is uniform over the field.
+//!
+//! Bounded integers (e.g. challenge indices below a leaf count `n`) are derived from the low `BOUND_BITS` bits of an
+//! output, reduced modulo `n`. The statistical distance of such an index from uniform is at most
+//! `n / 2^BOUND_BITS + 2^BOUND_BITS / p`, which for the BLS12-381 scalar field and `n < 2^64` is below `2^-63`.
+use crate::poseidon::{Arity, PoseidonConstants};
+use crate::scalar_from_u64;
+use crate::sponge::Sponge;
+use bellperson::gadgets::boolean::{AllocatedBit, Boolean};
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::{ConstraintSystem, LinearCombination, SynthesisError, Variable};
+use ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
+
+/// Number of low bits of a field element which are reduced to a bounded integer.
+pub const BOUND_BITS: usize = 128;
+
+/// Number of bytes packed into each field element by `bytes_to_scalars`.
+const BYTES_PER_ELEMENT: usize = 31;
+
+/// Pack `bytes` into field elements, 31 little-endian bytes per element, preceded by the length of `bytes`.
+pub fn bytes_to_scalars<Fr: PrimeField>(bytes: &[u8]) -> Vec<Fr> {
+    let mut scalars = Vec::with_capacity(1 + bytes.len() / BYTES_PER_ELEMENT + 1);
+    scalars.push(scalar_from_u64(bytes.len() as u64));
+
+    for chunk in bytes.chunks(BYTES_PER_ELEMENT) {
+        let mut repr = Fr::Repr::default();
+        let mut buf = vec![0u8; repr.as_ref().len() * 8];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        repr.read_le(buf.as_slice())
+            .expect("buffer has the size of a field element");
+        scalars.push(Fr::from_repr(repr).expect("31 bytes are always smaller than the modulus"));
+    }
+
+    scalars
+}
+
+/// Hash `inputs` to `count` field elements in the domain identified by `domain`.
+/// `constants` must be suitable for `Sponge`.
+pub fn hash_to_field<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    domain: &[u8],
+    inputs: &[E::Fr],
+    count: usize,
+) -> Vec<E::Fr>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let mut preimage = bytes_to_scalars::<E::Fr>(domain);
+    preimage.push(scalar_from_u64(count as u64));
+    preimage.extend_from_slice(inputs);
+
+    let mut sponge = Sponge::new(constants);
+    sponge
+        .absorb_elements(&preimage)
+        .expect("a fresh sponge accepts any number of elements");
+
+    sponge.squeeze_elements(count)
+}
+
+/// Hash `bytes` to `count` field elements in the domain identified by `domain`.
+pub fn hash_bytes_to_field<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    domain: &[u8],
+    bytes: &[u8],
+    count: usize,
+) -> Vec<E::Fr>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    hash_to_field(constants, domain, &bytes_to_scalars::<E::Fr>(bytes), count)
+}
+
+/// Hash `inputs` to `count` integers below `n`.
+pub fn hash_to_indices<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    domain: &[u8],
+    inputs: &[E::Fr],
+    count: usize,
+    n: u64,
+) -> Vec<u64>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    hash_to_field(constants, domain, inputs, count)
+        .iter()
+        .map(|output| reduce_to_index(output, n))
+        .collect()
+}
+
+/// Reduce the low `BOUND_BITS` bits of `element` modulo `n`.
+///
+/// # Panics
+///
+/// Panics if `n` is zero.
+pub fn reduce_to_index<Fr: PrimeField>(element: &Fr, n: u64) -> u64 {
+    assert!(n > 0, "bound must be positive");
+    (low_bits(element) % n as u128) as u64
+}
+
+/// The bits of the canonical representation of `digest`, least-significant first.
+pub fn digest_bits<Fr: PrimeField>(digest: &Fr) -> Vec<bool> {
+    let repr = digest.into_repr();
+    let limbs = repr.as_ref();
+    (0..Fr::NUM_BITS as usize)
+        .map(|i| (limbs[i / 64] >> (i % 64)) & 1 == 1)
+        .collect()
+}
+
+/// The integer of the `k` low bits of `digest`.
+///
+/// # Panics
+///
+/// Panics if `k` is greater than 64.
+pub fn digest_low_bits<Fr: PrimeField>(digest: &Fr, k: usize) -> u64 {
+    assert!(k <= 64, "at most 64 bits fit an integer");
+    let low = digest.into_repr().as_ref()[0];
+    if k == 64 {
+        low
+    } else {
+        low & ((1 << k) - 1)
+    }
+}
+
+fn low_bits<Fr: PrimeField>(element: &Fr) -> u128 {
+    let repr = element.into_repr();
+    let limbs = repr.as_ref();
+    limbs[0] as u128 | (limbs[1] as u128) << 64
+}
+
+/// Allocate `n` bits of `value`, least-significant first.
+fn alloc_bits<E, CS>(
+    mut cs: CS,
+    value: Option<u128>,
+    n: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    (0..n)
+        .map(|i| {
+            AllocatedBit::alloc(
+                cs.namespace(|| format!("bit {}", i)),
+                value.map(|v| (v >> i) & 1 == 1),
+            )
+            .map(Boolean::from)
+        })
+        .collect()
+}
+
+/// Linear combination of `bits`, least-significant first, scaled by `scale`.
+pub(crate) fn bits_lc<E: ScalarEngine>(
+    bits: &[Boolean],
+    one: Variable,
+    scale: E::Fr,
+) -> LinearCombination<E> {
+    let mut coeff = scale;
+    let mut lc = LinearCombination::zero();
+    for bit in bits {
+        lc = lc + &bit.lc::<E>(one, coeff);
+        coeff.double();
+    }
+    lc
+}
+
+/// Constrain `index` to be the low `BOUND_BITS` bits of `element` reduced modulo `n`, and return it.
+/// The element is decomposed strictly, so the index is unique.
+///
+/// # Panics
+///
+/// Panics if `n` is zero.
+pub fn reduce_to_index_circuit<E, CS>(
+    mut cs: CS,
+    element: &AllocatedNum<E>,
+    n: u64,
+) -> Result<AllocatedNum<E>, SynthesisError>
+where
+    E: ScalarEngine,
+    CS: ConstraintSystem<E>,
+{
+    assert!(n > 0, "bound must be positive");
+
+    let bits = element.to_bits_le_strict(cs.namespace(|| "element bits"))?;
+    let value = element.get_value().map(|e| low_bits(&e));
+    let quotient = value.map(|v| v / n as u128);
+    let remainder = value.map(|v| v % n as u128);
+
+    // quotient < 2^BOUND_BITS and remainder < 2^64, so `quotient * n + remainder` cannot wrap around the modulus.
+    let quotient_bits = alloc_bits::<E, _>(cs.namespace(|| "quotient"), quotient, BOUND_BITS)?;
+    let remainder_bits = alloc_bits::<E, _>(cs.namespace(|| "remainder"), remainder, 64)?;
+    let slack_bits = alloc_bits::<E, _>(
+        cs.namespace(|| "slack"),
+        remainder.map(|r| (n as u128) - 1 - r),
+        64,
+    )?;
+
+    let one = E::Fr::one();
+    let n_fr: E::Fr = scalar_from_u64(n);
+
+    // low bits = quotient * n + remainder
+    cs.enforce(
+        || "division",
+        |_| {
+            bits_lc::<E>(&quotient_bits, CS::one(), n_fr)
+                + &bits_lc::<E>(&remainder_bits, CS::one(), one)
+        },
+        |lc| lc + CS::one(),
+        |_| bits_lc::<E>(&bits[..BOUND_BITS], CS::one(), one),
+    );
+
+    // remainder + slack = n - 1, so remainder < n.
+    let mut n_minus_one = n_fr;
+    n_minus_one.sub_assign(&one);
+    cs.enforce(
+        || "remainder bound",
+        |_| {
+            bits_lc::<E>(&remainder_bits, CS::one(), one)
+                + &bits_lc::<E>(&slack_bits, CS::one(), one)
+        },
+        |lc| lc + CS::one(),
+        |lc| lc + (n_minus_one, CS::one()),
+    );
+
+    let index = AllocatedNum::alloc(cs.namespace(|| "index"), || {
+        remainder
+            .map(|r| scalar_from_u64(r as u64))
+            .ok_or(SynthesisError::AssignmentMissing)
+    })?;
+    cs.enforce(
+        || "index",
+        |_| bits_lc::<E>(&remainder_bits, CS::one(), one),
+        |lc| lc + CS::one(),
+        |lc| lc + index.get_variable(),
+    );
+
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_type::HashType;
+    use crate::Strength;
+    use bellperson::bls::{Bls12, Fr};
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use generic_array::typenum::U4;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn constants() -> PoseidonConstants<Bls12, U4> {
+        PoseidonConstants::new_with_strength_and_type(Strength::Standard, HashType::VariableLength)
+    }
+
+    #[test]
+    fn test_hash_to_field() {
+        let constants = constants();
+        let inputs = vec![Fr::one(), Fr::zero()];
+
+        let a = hash_to_field(&constants, b"domain", &inputs, 9);
+        assert_eq!(9, a.len());
+        assert_eq!(a, hash_to_field(&constants, b"domain", &inputs, 9));
+
+        // Outputs depend on the domain, the inputs and the requested count.
+        assert_ne!(a, hash_to_field(&constants, b"domain2", &inputs, 9));
+        assert_ne!(a, hash_to_field(&constants, b"domain", &inputs[..1], 9));
+        assert_ne!(
+            a[..3].to_vec(),
+            hash_to_field(&constants, b"domain", &inputs, 3)
+        );
+    }
+
+    #[test]
+    fn test_bytes_to_scalars() {
+        let bytes = (0..70).map(|i| i as u8).collect::<Vec<_>>();
+        let scalars = bytes_to_scalars::<Fr>(&bytes);
+
+        assert_eq!(4, scalars.len());
+        assert_eq!(scalar_from_u64::<Fr>(70), scalars[0]);
+        assert_ne!(bytes_to_scalars::<Fr>(&bytes[..69]), scalars);
+    }
+
+    #[test]
+    fn test_reduce_to_index_circuit() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for &n in &[1, 2, 7, 1 << 20, 1_000_003, u64::max_value()] {
+            let element = Fr::random(&mut rng);
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let allocated =
+                AllocatedNum::alloc(cs.namespace(|| "element"), || Ok(element)).unwrap();
+
+            let index = reduce_to_index_circuit(cs.namespace(|| "index"), &allocated, n).unwrap();
+
+            assert!(cs.is_satisfied(), "constraints not satisfied");
+            let expected = reduce_to_index(&element, n);
+            assert!(expected < n);
+            assert_eq!(scalar_from_u64::<Fr>(expected), index.get_value().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_digest_bits() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let digest = Fr::random(&mut rng);
+        let bits = digest_bits(&digest);
+
+        assert_eq!(Fr::NUM_BITS as usize, bits.len());
+        let packed = bits.iter().rev().fold(Fr::zero(), |mut acc, bit| {
+            acc.double();
+            if *bit {
+                acc.add_assign(&Fr::one());
+            }
+            acc
+        });
+        assert_eq!(digest, packed);
+
+        // Low bits are the index below the matching power of two.
+        for &k in &[0, 1, 20, 63] {
+            assert_eq!(
+                reduce_to_index(&digest, 1 << k),
+                digest_low_bits(&digest, k)
+            );
+        }
+        assert_eq!(digest.into_repr().as_ref()[0], digest_low_bits(&digest, 64));
+    }
+
+    #[test]
+    fn test_hash_to_indices() {
+        let constants = constants();
+        let indices = hash_to_indices(&constants, b"challenges", &[Fr::one()], 100, 37);
+
+        assert_eq!(100, indices.len());
+        assert!(indices.iter().all(|i| *i < 37));
+    }
+}
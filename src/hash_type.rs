@@ -10,7 +10,13 @@
 /// Because `neptune` also supports a first-class notion of `Strength`, we include a mechanism for composing
 /// `Strength` with `HashType` so that hashes with `Strength` other than `Standard` (currently only `Strengthened`)
 /// may still express the full range of hash function types.
-use crate::{scalar_from_u64, Arity, Strength};
+///
+/// Applications may define their own hash functions with `HashType::Custom`. A custom identifier `id` yields the tag
+/// `id * 2^40`, plus the strength component in bits 32 to 39. Identifiers must not be multiples of 2^24, so that bits
+/// 40 to 63 of a custom tag are never all zero. No built-in tag except `MerkleTreeSparse` (which is unsupported) sets
+/// any of those bits, so custom tags cannot collide with built-in ones. `CType::from_domain` derives a valid
+/// identifier from a human-readable domain name.
+use crate::{scalar_from_u64, Arity, Error, Strength};
 use ff::{Field, PrimeField, ScalarEngine};
 
 #[derive(Clone, Debug, PartialEq)]
@@ -24,7 +30,15 @@ pub enum HashType<Fr: PrimeField, A: Arity<Fr>> {
 }
 
 impl<Fr: PrimeField, A: Arity<Fr>> HashType<Fr, A> {
+    /// # Panics
+    ///
+    /// Panics if the length of a `ConstantLength` hash type is not in `1..=arity`. See `try_domain_tag`.
     pub fn domain_tag(&self, strength: &Strength) -> Fr {
+        self.try_domain_tag(strength)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_domain_tag(&self, strength: &Strength) -> Result<Fr, Error> {
         let pow2 = |n| pow2::<Fr, A>(n);
         let x_pow2 = |coeff, n| x_pow2::<Fr, A>(coeff, n);
         let with_strength = |x: Fr| {
@@ -33,7 +47,7 @@ impl<Fr: PrimeField, A: Arity<Fr>> HashType<Fr, A> {
             tmp
         };
 
-        match self {
+        let tag = match self {
             // 2^arity - 1
             HashType::MerkleTree => with_strength(A::tag()),
             // bitmask
@@ -43,8 +57,12 @@ impl<Fr: PrimeField, A: Arity<Fr>> HashType<Fr, A> {
             // length * 2^64
             // length must be greater than 0 and <= arity
             HashType::ConstantLength(length) => {
-                assert!(*length as usize <= A::to_usize());
-                assert!(*length as usize > 0);
+                if *length == 0 || *length > A::to_usize() {
+                    return Err(Error::InvalidConstantLength {
+                        length: *length,
+                        arity: A::to_usize(),
+                    });
+                }
                 with_strength(x_pow2(*length as u64, 64))
             }
             // 2^32
@@ -52,8 +70,10 @@ impl<Fr: PrimeField, A: Arity<Fr>> HashType<Fr, A> {
             // identifier * 2^40
             // NOTE: in order to leave room for future `Strength` tags,
             // we make identifier a multiple of 2^40 rather than 2^32.
-            HashType::Custom(ref ctype) => ctype.domain_tag(&strength),
-        }
+            HashType::Custom(ref ctype) => ctype.try_domain_tag(strength)?,
+        };
+
+        Ok(tag)
     }
 
     fn strength_tag_component(strength: &Strength) -> Fr {
@@ -66,6 +86,19 @@ impl<Fr: PrimeField, A: Arity<Fr>> HashType<Fr, A> {
         x_pow2::<Fr, A>(id, 32)
     }
 
+    /// Name of the hash type, for error messages.
+    pub(crate) fn name(&self) -> String {
+        match self {
+            HashType::MerkleTree => "MerkleTree".to_string(),
+            HashType::MerkleTreeSparse(bitmask) => format!("MerkleTreeSparse({})", bitmask),
+            HashType::VariableLength => "VariableLength".to_string(),
+            HashType::ConstantLength(length) => format!("ConstantLength({})", length),
+            HashType::Encryption => "Encryption".to_string(),
+            HashType::Custom(CType::Arbitrary(id)) => format!("Custom({})", id),
+            HashType::Custom(_) => "Custom".to_string(),
+        }
+    }
+
     /// Some HashTypes require more testing so are not yet supported, since they are not yet needed.
     /// As and when needed, support can be added, along with tests to ensure the initial implementation
     /// is sound.
@@ -73,10 +106,10 @@ impl<Fr: PrimeField, A: Arity<Fr>> HashType<Fr, A> {
         match self {
             HashType::MerkleTree => true,
             HashType::MerkleTreeSparse(_) => false,
-            HashType::VariableLength => false,
+            HashType::VariableLength => true,
             HashType::ConstantLength(_) => true,
             HashType::Encryption => true,
-            HashType::Custom(_) => false,
+            HashType::Custom(_) => true,
         }
     }
 }
@@ -88,6 +121,18 @@ pub enum CType<Fr: PrimeField, A: Arity<Fr>> {
 }
 
 impl<Fr: PrimeField, A: Arity<Fr>> CType<Fr, A> {
+    /// Derive a custom type from a human-readable domain name, such as `"my-app.commitment.v1"`. The identifier is the
+    /// first 8 bytes of a Blake2s digest of the name, made odd so that it is valid.
+    pub fn from_domain(domain: &str) -> Self {
+        let digest = blake2s_simd::Params::new()
+            .personal(b"nptncust")
+            .hash(domain.as_bytes());
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest.as_bytes()[..8]);
+
+        CType::Arbitrary(u64::from_le_bytes(bytes) | 1)
+    }
+
     fn identifier(&self) -> u64 {
         match self {
             CType::Arbitrary(id) => *id,
@@ -95,8 +140,15 @@ impl<Fr: PrimeField, A: Arity<Fr>> CType<Fr, A> {
         }
     }
 
-    fn domain_tag(&self, _strength: &Strength) -> Fr {
-        x_pow2::<Fr, A>(self.identifier(), 32)
+    fn try_domain_tag(&self, strength: &Strength) -> Result<Fr, Error> {
+        let id = self.identifier();
+        if id % (1 << 24) == 0 {
+            return Err(Error::InvalidCustomIdentifier(id));
+        }
+
+        let mut tag = x_pow2::<Fr, A>(id, 40);
+        tag.add_assign(&HashType::<Fr, A>::strength_tag_component(strength));
+        Ok(tag)
     }
 }
 
@@ -118,7 +170,7 @@ mod tests {
     use super::*;
     use crate::{scalar_from_u64s, Strength};
     use bellperson::bls::{Bls12, Fr, FrRepr};
-    use generic_array::typenum::{U15, U8};
+    use generic_array::typenum::{U11, U15, U2, U24, U36, U4, U8};
     use std::collections::HashSet;
 
     #[test]
@@ -235,4 +287,85 @@ mod tests {
         // hence no tag is duplicated.
         assert_eq!(all_tags.len(), all_tags_set.len());
     }
+
+    #[test]
+    fn test_try_domain_tag() {
+        for &length in &[0, 9] {
+            match HashType::ConstantLength::<Fr, U8>(length).try_domain_tag(&Strength::Standard) {
+                Err(Error::InvalidConstantLength { arity: 8, .. }) => (),
+                _ => panic!("invalid constant length {} was accepted", length),
+            }
+        }
+        assert_eq!(
+            HashType::MerkleTree::<Fr, U8>.domain_tag(&Strength::Standard),
+            HashType::MerkleTree::<Fr, U8>
+                .try_domain_tag(&Strength::Standard)
+                .unwrap()
+        );
+    }
+
+    fn built_in_tags<A: Arity<Fr>>(tags: &mut HashSet<[u64; 4]>) {
+        for strength in &[Strength::Standard, Strength::Strengthened] {
+            let mut hash_types = vec![
+                HashType::<Fr, A>::MerkleTree,
+                HashType::VariableLength,
+                HashType::Encryption,
+            ];
+            hash_types.extend((1..=A::to_usize()).map(HashType::ConstantLength));
+
+            for hash_type in hash_types {
+                tags.insert(hash_type.domain_tag(strength).into_repr().0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_domain_tags() {
+        let mut built_in = HashSet::new();
+        built_in_tags::<U2>(&mut built_in);
+        built_in_tags::<U4>(&mut built_in);
+        built_in_tags::<U8>(&mut built_in);
+        built_in_tags::<U11>(&mut built_in);
+        built_in_tags::<U24>(&mut built_in);
+        built_in_tags::<U36>(&mut built_in);
+
+        let ctypes = vec![
+            CType::<Fr, U8>::Arbitrary(1),
+            CType::Arbitrary(2),
+            CType::Arbitrary((1 << 24) + 1),
+            CType::Arbitrary((1 << 24) - 1),
+            CType::Arbitrary(u64::max_value()),
+            CType::from_domain("neptune.test"),
+            CType::from_domain("neptune.test2"),
+        ];
+
+        let mut custom = HashSet::new();
+        for ctype in ctypes {
+            for strength in &[Strength::Standard, Strength::Strengthened] {
+                let tag = HashType::Custom(ctype.clone())
+                    .domain_tag(strength)
+                    .into_repr()
+                    .0;
+
+                // Custom tags collide neither with built-in tags, nor with each other.
+                assert!(!built_in.contains(&tag));
+                assert!(custom.insert(tag));
+            }
+        }
+
+        assert_eq!(
+            CType::<Fr, U8>::from_domain("neptune.test"),
+            CType::from_domain("neptune.test")
+        );
+        assert!(HashType::Custom(CType::<Fr, U8>::Arbitrary(3)).is_supported());
+
+        for &id in &[0, 1 << 24, 1 << 40] {
+            match HashType::Custom(CType::<Fr, U8>::Arbitrary(id))
+                .try_domain_tag(&Strength::Standard)
+            {
+                Err(Error::InvalidCustomIdentifier(invalid)) => assert_eq!(id, invalid),
+                _ => panic!("invalid custom identifier {} was accepted", id),
+            }
+        }
+    }
 }
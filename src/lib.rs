@@ -1,5 +1,8 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
+// The `std` feature gates circuits, transcripts, batching and tree building, and the dependencies only they use. The
+// crate always links `std`, as `fff` and `bellperson` do.
+#[cfg(feature = "gpu")]
 #[macro_use]
 extern crate lazy_static;
 
@@ -8,7 +11,9 @@ use crate::round_constants::generate_constants;
 use crate::round_numbers::calc_round_numbers;
 pub use bellperson::bls::Fr as Scalar;
 use bellperson::bls::FrRepr;
+pub use digest::{Commitment, Digest};
 pub use error::Error;
+pub use fingerprint::Fingerprint;
 use ff::{Field, PrimeField, ScalarEngine};
 use generic_array::GenericArray;
 
@@ -16,6 +21,7 @@ use generic_array::GenericArray;
 compile_error!("gpu and opencl features are mutually exclusive");
 
 /// Poseidon circuit
+#[cfg(feature = "std")]
 pub mod circuit;
 pub mod error;
 mod matrix;
@@ -31,6 +37,73 @@ mod round_numbers;
 /// Hash types and domain separation tags.
 pub mod hash_type;
 
+/// Fiat-Shamir transcript
+#[cfg(feature = "std")]
+pub mod transcript;
+
+/// Sponge construction for variable-length hashing
+pub mod sponge;
+
+/// Hash-to-field and bounded integers
+#[cfg(feature = "std")]
+pub mod hash_to_field;
+
+/// Round-by-round traces
+pub mod trace;
+
+/// Known-answer test vectors
+#[cfg(feature = "std")]
+pub mod test_vectors;
+
+/// Const-generic API over plain arrays
+pub mod array;
+
+/// Bulk conversion between bytes and field elements
+pub mod codec;
+
+/// Canonical encodings of digests
+pub mod digest;
+
+/// Parameter-set fingerprints
+pub mod fingerprint;
+
+/// Named, versioned parameter profiles
+pub mod profile;
+
+/// Hashing structured data
+pub mod structured;
+
+/// Batch hashing checked against the CPU
+#[cfg(feature = "std")]
+pub mod checked_batch_hasher;
+
+/// Hash-based signatures
+#[cfg(feature = "std")]
+pub mod signature;
+
+/// Merkle inclusion proofs
+#[cfg(feature = "std")]
+pub mod merkle;
+
+/// Column commitment openings
+#[cfg(feature = "std")]
+pub mod column_commitment;
+
+/// Export of constraint systems to circom formats
+#[cfg(feature = "std")]
+pub mod circom;
+
+/// Ready-made circuits for common statements
+#[cfg(feature = "std")]
+pub mod statements;
+
+/// Groth16 proofs of circuits
+#[cfg(feature = "groth16")]
+pub mod groth16;
+
+/// Constraint costs of the circuit gadgets
+pub mod cost;
+
 /// Tree Builder
 #[cfg(any(feature = "gpu", feature = "opencl"))]
 pub mod tree_builder;
@@ -39,6 +112,10 @@ pub mod tree_builder;
 #[cfg(any(feature = "gpu", feature = "opencl"))]
 pub mod column_tree_builder;
 
+/// Trees built with the CPU batcher, for tests
+#[cfg(all(test, any(feature = "gpu", feature = "opencl")))]
+mod test_trees;
+
 #[cfg(feature = "gpu")]
 pub mod triton;
 
@@ -94,6 +171,13 @@ where
     }
 }
 
+/// Implemented by hashers which can report the constants they compute with, such as the `BatchHasher`s provided here.
+pub trait Fingerprinted {
+    /// Fingerprint of the constants this hasher computes with. Callers holding their own `PoseidonConstants` should
+    /// check it before mixing the hasher's digests with their own.
+    fn fingerprint(&self) -> Fingerprint;
+}
+
 // Returns the round numbers for a given arity `(R_F, R_P)`.
 fn round_numbers_base(arity: usize) -> (usize, usize) {
     let t = arity + 1;
@@ -112,7 +196,7 @@ fn round_numbers_strengthened(arity: usize) -> (usize, usize) {
     let (full_round, partial_rounds) = round_numbers_base(arity);
 
     // Increase by 25%, rounding up.
-    let strengthened_partial_rounds = f64::ceil(partial_rounds as f64 * 1.25) as usize;
+    let strengthened_partial_rounds = (partial_rounds * 5 + 3) / 4;
 
     (full_round, strengthened_partial_rounds)
 }
@@ -147,7 +231,7 @@ fn round_constants<E: ScalarEngine>(arity: usize, strength: &Strength) -> Vec<E:
 
     let fr_num_bits = E::Fr::NUM_BITS;
     let field_size = {
-        assert!(fr_num_bits <= std::u16::MAX as u32);
+        assert!(fr_num_bits <= core::u16::MAX as u32);
         // It's safe to convert to u16 for compatibility with other types.
         fr_num_bits as u16
     };
@@ -0,0 +1,858 @@
+//! Merkle inclusion proofs for trees hashed with Poseidon, natively and in circuits.
+//!
+//! A tree is either uniform, with every level of arity `B`, or compound: a forest of base trees of arity `B`, whose
+//! roots are hashed in groups of `S` and then once more with arity `T`, as in Filecoin's sealed-sector trees. A
+//! `MerklePath` records, from the leaves up, the siblings of the node on the path at each level and its position among
+//! them. Paths are read from trees laid out as `TreeBuilder` returns them: the leaves, followed by each row above
+//! them, ending with the root. Only trees built without discarding rows have every node a path needs.
+//!
+//! `root_circuit` recomputes the root from a leaf, the siblings and the bits of the leaf index, least significant
+//! first. Each level of a power-of-two arity, as every tree built by `TreeBuilder` has in practice, consumes as many
+//! bits as address a position within it. Any other level instead consumes one selector bit per position, exactly one
+//! of which is set.
+use crate::circuit::poseidon_hash;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::Error;
+use bellperson::bls::{Bls12, Fr};
+use bellperson::gadgets::boolean::{AllocatedBit, Boolean};
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::{ConstraintSystem, LinearCombination, SynthesisError};
+use ff::{Field, ScalarEngine as Engine};
+use generic_array::typenum::U0;
+
+/// Constants for each kind of level of a tree. `S` and `T` are `U0` for trees without sub or top levels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleConstants<B, S = U0, T = U0>
+where
+    B: Arity<Fr>,
+    S: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    pub base: PoseidonConstants<Bls12, B>,
+    pub sub: Option<PoseidonConstants<Bls12, S>>,
+    pub top: Option<PoseidonConstants<Bls12, T>>,
+}
+
+/// Kind of a level of a tree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Level {
+    Base,
+    Sub,
+    Top,
+}
+
+impl<B, S, T> MerkleConstants<B, S, T>
+where
+    B: Arity<Fr>,
+    S: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    /// Constants of the default Merkle tree hash type for each arity, as used by `TreeBuilder`.
+    pub fn new() -> Self {
+        Self {
+            base: PoseidonConstants::new(),
+            sub: if S::to_usize() == 0 {
+                None
+            } else {
+                Some(PoseidonConstants::new())
+            },
+            top: if T::to_usize() == 0 {
+                None
+            } else {
+                Some(PoseidonConstants::new())
+            },
+        }
+    }
+
+    /// Number of levels above the base trees.
+    fn upper_levels(&self) -> usize {
+        self.sub.is_some() as usize + self.top.is_some() as usize
+    }
+
+    /// Kinds of the levels of a tree of height `height`, from the leaves up.
+    fn levels(&self, height: usize) -> Result<Vec<Level>, Error> {
+        let base_height = height
+            .checked_sub(self.upper_levels())
+            .ok_or(Error::InvalidMerklePath { level: height })?;
+
+        let mut levels = vec![Level::Base; base_height];
+        if self.sub.is_some() {
+            levels.push(Level::Sub);
+        }
+        if self.top.is_some() {
+            levels.push(Level::Top);
+        }
+        Ok(levels)
+    }
+
+    fn arity(&self, level: Level) -> usize {
+        match level {
+            Level::Base => B::to_usize(),
+            Level::Sub => S::to_usize(),
+            Level::Top => T::to_usize(),
+        }
+    }
+
+    fn hash(&self, level: Level, preimage: &[Fr]) -> Fr {
+        match level {
+            Level::Base => Poseidon::new_with_preimage(preimage, &self.base).hash(),
+            Level::Sub => Poseidon::new_with_preimage(preimage, self.sub.as_ref().unwrap()).hash(),
+            Level::Top => Poseidon::new_with_preimage(preimage, self.top.as_ref().unwrap()).hash(),
+        }
+    }
+
+    fn hash_circuit<CS: ConstraintSystem<Bls12>>(
+        &self,
+        cs: CS,
+        level: Level,
+        preimage: Vec<AllocatedNum<Bls12>>,
+    ) -> Result<AllocatedNum<Bls12>, SynthesisError> {
+        match level {
+            Level::Base => poseidon_hash(cs, preimage, &self.base),
+            Level::Sub => poseidon_hash(cs, preimage, self.sub.as_ref().unwrap()),
+            Level::Top => poseidon_hash(cs, preimage, self.top.as_ref().unwrap()),
+        }
+    }
+}
+
+impl<B, S, T> Default for MerkleConstants<B, S, T>
+where
+    B: Arity<Fr>,
+    S: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The siblings of a node on a path, and its position among them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathLevel {
+    pub siblings: Vec<Fr>,
+    pub position: usize,
+}
+
+/// A Merkle inclusion proof, without its leaf. Levels are ordered from the leaves up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerklePath {
+    pub levels: Vec<PathLevel>,
+}
+
+impl MerklePath {
+    /// The path of leaf `index` of a uniform tree with `leaf_count` leaves, laid out as the leaves followed by the rows
+    /// above them.
+    pub fn from_tree<A: Arity<Fr>>(
+        tree: &[Fr],
+        leaf_count: usize,
+        index: usize,
+    ) -> Result<Self, Error> {
+        let arity = A::to_usize();
+        let mut rows = Vec::new();
+        let mut row_len = leaf_count;
+        loop {
+            rows.push(row_len);
+            if row_len == 1 {
+                break;
+            }
+            if row_len == 0 || row_len % arity != 0 {
+                return Err(Error::InvalidLeafCount { leaf_count, arity });
+            }
+            row_len /= arity;
+        }
+
+        let expected = rows.iter().sum();
+        if tree.len() != expected {
+            return Err(Error::InvalidTreeLength {
+                expected,
+                actual: tree.len(),
+            });
+        }
+        if index >= leaf_count {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        let mut levels = Vec::with_capacity(rows.len() - 1);
+        let (mut row_start, mut i) = (0, index);
+        for row_len in rows.iter().take(rows.len() - 1) {
+            let group = row_start + i / arity * arity;
+            levels.push(PathLevel {
+                siblings: (0..arity)
+                    .filter(|&k| k != i % arity)
+                    .map(|k| tree[group + k])
+                    .collect(),
+                position: i % arity,
+            });
+            row_start += row_len;
+            i /= arity;
+        }
+
+        Ok(Self { levels })
+    }
+
+    /// The path of leaf `index` of a compound tree, whose base trees each have `leaf_count` leaves and are laid out as
+    /// for `from_tree`. There must be as many base trees as the sub and top levels hash together.
+    pub fn from_compound_trees<B, S, T>(
+        trees: &[&[Fr]],
+        leaf_count: usize,
+        index: usize,
+        constants: &MerkleConstants<B, S, T>,
+    ) -> Result<Self, Error>
+    where
+        B: Arity<Fr>,
+        S: Arity<Fr>,
+        T: Arity<Fr>,
+    {
+        let sub_arity = constants.sub.as_ref().map_or(1, |_| S::to_usize());
+        let top_arity = constants.top.as_ref().map_or(1, |_| T::to_usize());
+        if trees.len() != sub_arity * top_arity {
+            return Err(Error::InvalidTreeLength {
+                expected: sub_arity * top_arity,
+                actual: trees.len(),
+            });
+        }
+        if index >= leaf_count * trees.len() {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        let tree_index = index / leaf_count;
+        let mut path = Self::from_tree::<B>(trees[tree_index], leaf_count, index % leaf_count)?;
+
+        let mut roots = trees
+            .iter()
+            .map(|tree| *tree.last().expect("trees have a root"))
+            .collect::<Vec<_>>();
+        let mut i = tree_index;
+        for (level, arity) in [(Level::Sub, sub_arity), (Level::Top, top_arity)].iter() {
+            if *arity == 1 {
+                continue;
+            }
+            let group = i / arity * arity;
+            path.levels.push(PathLevel {
+                siblings: (0..*arity)
+                    .filter(|&k| k != i % arity)
+                    .map(|k| roots[group + k])
+                    .collect(),
+                position: i % arity,
+            });
+            roots = roots
+                .chunks(*arity)
+                .map(|preimage| constants.hash(*level, preimage))
+                .collect();
+            i /= arity;
+        }
+
+        Ok(path)
+    }
+
+    /// Index of the leaf the path starts from.
+    pub fn index(&self) -> usize {
+        let mut index = 0;
+        let mut leaves_below = 1;
+        for level in self.levels.iter() {
+            index += level.position * leaves_below;
+            leaves_below *= level.siblings.len() + 1;
+        }
+        index
+    }
+
+    /// Root of the tree with `leaf` at the start of the path. Returns `Error::InvalidMerklePath` if a level of the
+    /// path does not match the arity of its level in the tree.
+    pub fn root<B, S, T>(
+        &self,
+        leaf: &Fr,
+        constants: &MerkleConstants<B, S, T>,
+    ) -> Result<Fr, Error>
+    where
+        B: Arity<Fr>,
+        S: Arity<Fr>,
+        T: Arity<Fr>,
+    {
+        let mut node = *leaf;
+        let levels = constants.levels(self.levels.len())?;
+        for (i, (path_level, level)) in self.levels.iter().zip(levels).enumerate() {
+            let arity = constants.arity(level);
+            if path_level.siblings.len() + 1 != arity || path_level.position >= arity {
+                return Err(Error::InvalidMerklePath { level: i });
+            }
+            let mut preimage = path_level.siblings.clone();
+            preimage.insert(path_level.position, node);
+            node = constants.hash(level, &preimage);
+        }
+        Ok(node)
+    }
+
+    /// Whether the path proves that `leaf` is in the tree with root `root`.
+    pub fn verify<B, S, T>(
+        &self,
+        leaf: &Fr,
+        root: &Fr,
+        constants: &MerkleConstants<B, S, T>,
+    ) -> bool
+    where
+        B: Arity<Fr>,
+        S: Arity<Fr>,
+        T: Arity<Fr>,
+    {
+        self.root(leaf, constants)
+            .map_or(false, |computed| computed == *root)
+    }
+}
+
+/// A Merkle path allocated in a circuit: the siblings at each level, and the bits of the leaf index, least significant
+/// first. Levels whose arity is not a power of two contribute a selector bit per position instead.
+pub struct AllocatedMerklePath<E: Engine> {
+    pub siblings: Vec<Vec<AllocatedNum<E>>>,
+    pub index_bits: Vec<Boolean>,
+}
+
+impl AllocatedMerklePath<Bls12> {
+    /// Allocate the path of a tree of height `height`, whose value is `path` if known.
+    pub fn alloc<CS, B, S, T>(
+        mut cs: CS,
+        path: Option<&MerklePath>,
+        height: usize,
+        constants: &MerkleConstants<B, S, T>,
+    ) -> Result<Self, SynthesisError>
+    where
+        CS: ConstraintSystem<Bls12>,
+        B: Arity<Fr>,
+        S: Arity<Fr>,
+        T: Arity<Fr>,
+    {
+        let levels = constants
+            .levels(height)
+            .map_err(|_| SynthesisError::Unsatisfiable)?;
+
+        let mut siblings = Vec::with_capacity(height);
+        let mut index_bits = Vec::new();
+        for (i, level) in levels.into_iter().enumerate() {
+            let mut cs = cs.namespace(|| format!("level {}", i));
+            let arity = constants.arity(level);
+            let path_level = path
+                .and_then(|path| path.levels.get(i))
+                .filter(|path_level| path_level.siblings.len() + 1 == arity);
+
+            siblings.push(
+                (0..arity - 1)
+                    .map(|k| {
+                        AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", k)), || {
+                            path_level
+                                .map(|path_level| path_level.siblings[k])
+                                .ok_or(SynthesisError::AssignmentMissing)
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+            if arity.is_power_of_two() {
+                for bit in 0..position_bits(arity) {
+                    index_bits.push(Boolean::from(AllocatedBit::alloc(
+                        cs.namespace(|| format!("position bit {}", bit)),
+                        path_level.map(|path_level| (path_level.position >> bit) & 1 == 1),
+                    )?));
+                }
+            } else {
+                for k in 0..arity {
+                    index_bits.push(Boolean::from(AllocatedBit::alloc(
+                        cs.namespace(|| format!("position selector {}", k)),
+                        path_level.map(|path_level| path_level.position == k),
+                    )?));
+                }
+            }
+        }
+
+        Ok(Self {
+            siblings,
+            index_bits,
+        })
+    }
+}
+
+/// Number of index bits a level of arity `arity` consumes: the bits addressing a position within it if `arity` is a
+/// power of two, and one selector per position otherwise.
+fn position_bits(arity: usize) -> usize {
+    if arity.is_power_of_two() {
+        arity.trailing_zeros() as usize
+    } else {
+        arity
+    }
+}
+
+/// Circuit computing the root of the tree with `leaf` at the start of `path`. Returns
+/// `SynthesisError::Unsatisfiable` unless the index bits address exactly the levels of the path.
+pub fn root_circuit<CS, B, S, T>(
+    mut cs: CS,
+    leaf: &AllocatedNum<Bls12>,
+    path: &AllocatedMerklePath<Bls12>,
+    constants: &MerkleConstants<B, S, T>,
+) -> Result<AllocatedNum<Bls12>, SynthesisError>
+where
+    CS: ConstraintSystem<Bls12>,
+    B: Arity<Fr>,
+    S: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    let levels = constants
+        .levels(path.siblings.len())
+        .map_err(|_| SynthesisError::Unsatisfiable)?;
+
+    let mut node = leaf.clone();
+    let mut bits = path.index_bits.as_slice();
+    for (i, (siblings, level)) in path.siblings.iter().zip(levels).enumerate() {
+        let mut cs = cs.namespace(|| format!("level {}", i));
+        let arity = constants.arity(level);
+        let bit_count = position_bits(arity);
+        if bits.len() < bit_count {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        let preimage = if arity.is_power_of_two() {
+            insert_circuit(
+                cs.namespace(|| "insert"),
+                &node,
+                siblings,
+                &bits[..bit_count],
+            )?
+        } else {
+            insert_circuit_with_selectors(
+                cs.namespace(|| "insert"),
+                &node,
+                siblings,
+                &bits[..bit_count],
+            )?
+        };
+        bits = &bits[bit_count..];
+        node = constants.hash_circuit(cs.namespace(|| "hash"), level, preimage)?;
+    }
+    if !bits.is_empty() {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    Ok(node)
+}
+
+/// Circuit inserting `node` among `siblings` at the position whose bits, least significant first, are
+/// `position_bits`. Returns `SynthesisError::Unsatisfiable` unless there is one less sibling than the number of
+/// positions the bits address.
+///
+/// For `arity = siblings.len() + 1` and `h` position bits, this costs `3 * arity - h - 3` constraints: `2 * arity - 2`
+/// to select the elements, and one for each product of two or more bits, from which the position is decoded.
+pub fn insert_circuit<E, CS>(
+    mut cs: CS,
+    node: &AllocatedNum<E>,
+    siblings: &[AllocatedNum<E>],
+    position_bits: &[Boolean],
+) -> Result<Vec<AllocatedNum<E>>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    let arity = siblings.len() + 1;
+    if 1 << position_bits.len() != arity {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+    let position = position_bits.iter().rev().try_fold(0, |position, bit| {
+        bit.get_value().map(|bit| position << 1 | bit as usize)
+    });
+
+    // The product of the bits of each subset of them, indexed by its mask.
+    let mut products = vec![Boolean::Constant(true)];
+    for mask in 1..arity {
+        let top = (mask + 1).next_power_of_two().trailing_zeros() as usize - 1;
+        let product = Boolean::and(
+            cs.namespace(|| format!("product {}", mask)),
+            &products[mask ^ (1 << top)],
+            &position_bits[top],
+        )?;
+        products.push(product);
+    }
+
+    // The indicator of each position: the product of its bits and of the complements of the others, expanded.
+    let mut minus_one = E::Fr::one();
+    minus_one.negate();
+    let selectors = (0..arity)
+        .map(|p| {
+            (0..arity).filter(|mask| mask & p == p).fold(
+                LinearCombination::<E>::zero(),
+                |lc, mask| {
+                    let coeff = if (mask ^ p).count_ones() % 2 == 0 {
+                        E::Fr::one()
+                    } else {
+                        minus_one
+                    };
+                    lc + &products[mask].lc(CS::one(), coeff)
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    select_circuit(cs, node, siblings, &selectors, position)
+}
+
+/// Circuit inserting `node` among `siblings` at the position whose selector, of one per position, is set. Unlike
+/// `insert_circuit`, this works for any arity, but takes a bit per position rather than per halving of the arity. Returns `SynthesisError::Unsatisfiable` unless there is one more selector than siblings.
+///
+/// For `arity = siblings.len() + 1`, this costs `2 * arity - 1` constraints: one requiring exactly one selector to be
+/// set, and `2 * arity - 2` to select the elements.
+pub fn insert_circuit_with_selectors<E, CS>(
+    mut cs: CS,
+    node: &AllocatedNum<E>,
+    siblings: &[AllocatedNum<E>],
+    selectors: &[Boolean],
+) -> Result<Vec<AllocatedNum<E>>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    if selectors.len() != siblings.len() + 1 {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+    let position = selectors
+        .iter()
+        .map(Boolean::get_value)
+        .collect::<Option<Vec<_>>>()
+        .and_then(|selected| selected.iter().position(|&selected| selected));
+
+    let selectors = selectors
+        .iter()
+        .map(|selector| selector.lc(CS::one(), E::Fr::one()))
+        .collect::<Vec<_>>();
+    let sum = selectors
+        .iter()
+        .fold(LinearCombination::<E>::zero(), |lc, selector| lc + selector);
+    cs.enforce(
+        || "one selected",
+        |_| sum,
+        |lc| lc + CS::one(),
+        |lc| lc + CS::one(),
+    );
+
+    select_circuit(cs, node, siblings, &selectors, position)
+}
+
+/// Circuit inserting `node` among `siblings`, given the indicator of each position and, if known, the position.
+fn select_circuit<E, CS>(
+    mut cs: CS,
+    node: &AllocatedNum<E>,
+    siblings: &[AllocatedNum<E>],
+    selectors: &[LinearCombination<E>],
+    position: Option<usize>,
+) -> Result<Vec<AllocatedNum<E>>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+{
+    let arity = siblings.len() + 1;
+    let value = |element: &AllocatedNum<E>| element.get_value();
+    let mut preimage = Vec::with_capacity(arity);
+    for k in 0..arity {
+        // The sibling at `k` when the node is after it, and the one before it when the node is before it.
+        let shifted = if k == 0 {
+            siblings[0].clone()
+        } else if k == arity - 1 {
+            siblings[arity - 2].clone()
+        } else {
+            let after = selectors[k + 1..]
+                .iter()
+                .fold(LinearCombination::<E>::zero(), |lc, selector| lc + selector);
+            let shifted = AllocatedNum::alloc(cs.namespace(|| format!("shifted {}", k)), || {
+                let p = position.ok_or(SynthesisError::AssignmentMissing)?;
+                let sibling = if p > k {
+                    &siblings[k]
+                } else {
+                    &siblings[k - 1]
+                };
+                value(sibling).ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            cs.enforce(
+                || format!("shift {}", k),
+                |_| after,
+                |lc| lc + siblings[k].get_variable() - siblings[k - 1].get_variable(),
+                |lc| lc + shifted.get_variable() - siblings[k - 1].get_variable(),
+            );
+            shifted
+        };
+
+        let element = AllocatedNum::alloc(cs.namespace(|| format!("element {}", k)), || {
+            let p = position.ok_or(SynthesisError::AssignmentMissing)?;
+            let selected = if p == k { node } else { &shifted };
+            value(selected).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        cs.enforce(
+            || format!("select {}", k),
+            |_| selectors[k].clone(),
+            |lc| lc + node.get_variable() - shifted.get_variable(),
+            |lc| lc + element.get_variable() - shifted.get_variable(),
+        );
+        preimage.push(element);
+    }
+
+    Ok(preimage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    use crate::test_trees::build_tree;
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use generic_array::typenum::{U2, U3, U4, U5, U8};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn random_leaves(n: usize) -> Vec<Fr> {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        (0..n).map(|_| Fr::random(&mut rng)).collect()
+    }
+
+    #[test]
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    fn test_merkle_path() {
+        let constants = MerkleConstants::<U4>::new();
+        let leaves = random_leaves(64);
+        let tree = build_tree::<U4>(&leaves);
+        let root = *tree.last().unwrap();
+
+        for &index in [0, 5, 37, 63].iter() {
+            let path = MerklePath::from_tree::<U4>(&tree, 64, index).unwrap();
+            assert_eq!(3, path.levels.len());
+            assert_eq!(index, path.index());
+            assert!(path.verify(&leaves[index], &root, &constants));
+            assert!(!path.verify(&leaves[index ^ 1], &root, &constants));
+        }
+
+        let mut path = MerklePath::from_tree::<U4>(&tree, 64, 9).unwrap();
+        path.levels[1].siblings.pop();
+        assert!(matches!(
+            path.root(&leaves[9], &constants),
+            Err(Error::InvalidMerklePath { level: 1 })
+        ));
+
+        assert!(matches!(
+            MerklePath::from_tree::<U4>(&tree, 64, 64),
+            Err(Error::IndexOutOfBounds)
+        ));
+        assert!(matches!(
+            MerklePath::from_tree::<U4>(&tree[1..], 64, 0),
+            Err(Error::InvalidTreeLength { .. })
+        ));
+        assert!(matches!(
+            MerklePath::from_tree::<U4>(&tree, 32, 0),
+            Err(Error::InvalidLeafCount { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    fn test_compound_merkle_path() {
+        let constants = MerkleConstants::<U8, U4, U2>::new();
+        let leaves = random_leaves(8 * 8);
+        let trees = leaves.chunks(8).map(build_tree::<U8>).collect::<Vec<_>>();
+        let tree_refs = trees.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let sub_roots = trees
+            .chunks(4)
+            .map(|trees| {
+                let roots = trees.iter().map(|tree| tree[8]).collect::<Vec<_>>();
+                constants.hash(Level::Sub, &roots)
+            })
+            .collect::<Vec<_>>();
+        let root = constants.hash(Level::Top, &sub_roots);
+
+        for &index in [0, 13, 42, 63].iter() {
+            let path = MerklePath::from_compound_trees(&tree_refs, 8, index, &constants).unwrap();
+            assert_eq!(3, path.levels.len());
+            assert_eq!(index, path.index());
+            assert!(path.verify(&leaves[index], &root, &constants));
+        }
+
+        assert!(matches!(
+            MerklePath::from_compound_trees(&tree_refs[1..], 8, 0, &constants),
+            Err(Error::InvalidTreeLength { .. })
+        ));
+    }
+
+    #[test]
+    fn test_insert_circuit() {
+        for &arity in [2usize, 4, 8].iter() {
+            let siblings = random_leaves(arity - 1);
+            for position in 0..arity {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let node = AllocatedNum::alloc(cs.namespace(|| "node"), || Ok(Fr::one())).unwrap();
+                let allocated_siblings = siblings
+                    .iter()
+                    .enumerate()
+                    .map(|(i, sibling)| {
+                        AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || {
+                            Ok(*sibling)
+                        })
+                        .unwrap()
+                    })
+                    .collect::<Vec<_>>();
+                let bits = (0..arity.trailing_zeros() as usize)
+                    .map(|bit| {
+                        Boolean::from(
+                            AllocatedBit::alloc(
+                                cs.namespace(|| format!("bit {}", bit)),
+                                Some((position >> bit) & 1 == 1),
+                            )
+                            .unwrap(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let bit_constraints = cs.num_constraints();
+
+                let preimage =
+                    insert_circuit(cs.namespace(|| "insert"), &node, &allocated_siblings, &bits)
+                        .unwrap();
+
+                let mut expected = siblings.clone();
+                expected.insert(position, Fr::one());
+                assert!(cs.is_satisfied(), "constraints not satisfied");
+                assert_eq!(
+                    expected,
+                    preimage
+                        .iter()
+                        .map(|element| element.get_value().unwrap())
+                        .collect::<Vec<_>>()
+                );
+                assert_eq!(
+                    3 * arity - bits.len() - 3,
+                    cs.num_constraints() - bit_constraints
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_circuit_with_selectors() {
+        for &arity in [3usize, 5].iter() {
+            let siblings = random_leaves(arity - 1);
+            for position in 0..arity {
+                let mut cs = TestConstraintSystem::<Bls12>::new();
+                let node = AllocatedNum::alloc(cs.namespace(|| "node"), || Ok(Fr::one())).unwrap();
+                let allocated_siblings = siblings
+                    .iter()
+                    .enumerate()
+                    .map(|(i, sibling)| {
+                        AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || {
+                            Ok(*sibling)
+                        })
+                        .unwrap()
+                    })
+                    .collect::<Vec<_>>();
+                let selectors = (0..arity)
+                    .map(|k| {
+                        Boolean::from(
+                            AllocatedBit::alloc(
+                                cs.namespace(|| format!("selector {}", k)),
+                                Some(k == position),
+                            )
+                            .unwrap(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let selector_constraints = cs.num_constraints();
+
+                let preimage = insert_circuit_with_selectors(
+                    cs.namespace(|| "insert"),
+                    &node,
+                    &allocated_siblings,
+                    &selectors,
+                )
+                .unwrap();
+
+                let mut expected = siblings.clone();
+                expected.insert(position, Fr::one());
+                assert!(cs.is_satisfied(), "constraints not satisfied");
+                assert_eq!(
+                    expected,
+                    preimage
+                        .iter()
+                        .map(|element| element.get_value().unwrap())
+                        .collect::<Vec<_>>()
+                );
+                assert_eq!(2 * arity - 1, cs.num_constraints() - selector_constraints);
+            }
+
+            // No selector set.
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let node = AllocatedNum::alloc(cs.namespace(|| "node"), || Ok(Fr::one())).unwrap();
+            let allocated_siblings = siblings
+                .iter()
+                .enumerate()
+                .map(|(i, sibling)| {
+                    AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || Ok(*sibling))
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            let selectors = vec![Boolean::Constant(false); arity];
+            assert!(insert_circuit_with_selectors(
+                cs.namespace(|| "insert"),
+                &node,
+                &allocated_siblings,
+                &selectors,
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    fn test_root_circuit() {
+        let constants = MerkleConstants::<U8, U2>::new();
+        let leaves = random_leaves(16);
+        let trees = leaves.chunks(8).map(build_tree::<U8>).collect::<Vec<_>>();
+        let tree_refs = trees.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let path = MerklePath::from_compound_trees(&tree_refs, 8, 11, &constants).unwrap();
+        let root = path.root(&leaves[11], &constants).unwrap();
+
+        for (leaf, valid) in [(leaves[11], true), (leaves[10], false)].iter() {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let leaf = AllocatedNum::alloc(cs.namespace(|| "leaf"), || Ok(*leaf)).unwrap();
+            let allocated_path =
+                AllocatedMerklePath::alloc(cs.namespace(|| "path"), Some(&path), 2, &constants)
+                    .unwrap();
+            assert_eq!(4, allocated_path.index_bits.len());
+
+            let computed =
+                root_circuit(cs.namespace(|| "root"), &leaf, &allocated_path, &constants).unwrap();
+            assert!(cs.is_satisfied(), "constraints not satisfied");
+            assert_eq!(*valid, computed.get_value().unwrap() == root);
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    fn test_root_circuit_any_arity() {
+        let constants = MerkleConstants::<U3>::new();
+        let leaves = random_leaves(27);
+        let tree = build_tree::<U3>(&leaves);
+        let root = *tree.last().unwrap();
+        let path = MerklePath::from_tree::<U3>(&tree, 27, 16).unwrap();
+
+        for (leaf, valid) in [(leaves[16], true), (leaves[15], false)].iter() {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let leaf = AllocatedNum::alloc(cs.namespace(|| "leaf"), || Ok(*leaf)).unwrap();
+            let allocated_path =
+                AllocatedMerklePath::alloc(cs.namespace(|| "path"), Some(&path), 3, &constants)
+                    .unwrap();
+            assert_eq!(9, allocated_path.index_bits.len());
+
+            let computed =
+                root_circuit(cs.namespace(|| "root"), &leaf, &allocated_path, &constants).unwrap();
+            assert!(cs.is_satisfied(), "constraints not satisfied");
+            assert_eq!(*valid, computed.get_value().unwrap() == root);
+        }
+
+        // Index bits for a path one level shorter.
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let leaf = AllocatedNum::alloc(cs.namespace(|| "leaf"), || Ok(leaves[16])).unwrap();
+        let mut allocated_path =
+            AllocatedMerklePath::alloc(cs.namespace(|| "path"), Some(&path), 3, &constants)
+                .unwrap();
+        allocated_path.index_bits.truncate(6);
+        assert!(matches!(
+            root_circuit(cs.namespace(|| "root"), &leaf, &allocated_path, &constants),
+            Err(SynthesisError::Unsatisfiable)
+        ));
+    }
+}
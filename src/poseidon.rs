@@ -1,14 +1,17 @@
+use crate::fingerprint::Fingerprint;
 use crate::hash_type::HashType;
 use crate::matrix::Matrix;
 use crate::mds::{create_mds_matrices, factor_to_sparse_matrixes, MDSMatrices, SparseMatrix};
 use crate::poseidon_alt::{hash_correct, hash_optimized_dynamic};
 use crate::preprocessing::compress_round_constants;
-use crate::{matrix, quintic_s_box, BatchHasher, Strength, DEFAULT_STRENGTH};
+use crate::sponge::Sponge;
+use crate::trace::{Trace, TraceStep};
+use crate::{matrix, quintic_s_box, BatchHasher, Fingerprinted, Strength, DEFAULT_STRENGTH};
 use crate::{round_constants, round_numbers, scalar_from_u64, Error};
 use bellperson::bls::{Bls12, Fr};
+use core::marker::PhantomData;
 use ff::{Field, PrimeField, ScalarEngine};
 use generic_array::{sequence::GenericSequence, typenum, ArrayLength, GenericArray};
-use std::marker::PhantomData;
 use typenum::marker_traits::Unsigned;
 use typenum::*;
 
@@ -82,7 +85,7 @@ impl_arity!(
 );
 
 /// The `Poseidon` structure will accept a number of inputs equal to the arity.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Poseidon<'a, E, A = U2>
 where
     E: ScalarEngine,
@@ -94,9 +97,25 @@ where
     pub elements: GenericArray<E::Fr, A::ConstantsSize>,
     pos: usize,
     pub(crate) constants: &'a PoseidonConstants<E, A>,
+    pub(crate) trace: Option<Trace<E::Fr>>,
     _e: PhantomData<E>,
 }
 
+/// Hashers are equal if their states are, whether or not either records a trace.
+impl<'a, E, A> PartialEq for Poseidon<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.constants_offset == other.constants_offset
+            && self.current_round == other.current_round
+            && self.elements == other.elements
+            && self.pos == other.pos
+            && self.constants == other.constants
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct PoseidonConstants<E, A>
 where
@@ -116,6 +135,7 @@ where
     pub half_full_rounds: usize,
     pub partial_rounds: usize,
     pub hash_type: HashType<E::Fr, A>,
+    fingerprint: Fingerprint,
     _a: PhantomData<A>,
 }
 
@@ -146,27 +166,40 @@ where
     /// `new_constant_length` creates constants for hashing a constant-sized preimage which is <= the max
     /// supported by the permutation width.
     pub fn new_constant_length(length: usize) -> Self {
-        let arity = A::to_usize();
-        assert!(length <= arity);
-        Self::new_with_strength_and_type(DEFAULT_STRENGTH, HashType::ConstantLength(length))
+        Self::try_new_constant_length(length).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `new_constant_length`, but returns an error if `length` is not in `1..=arity`.
+    pub fn try_new_constant_length(length: usize) -> Result<Self, Error> {
+        Self::try_new_with_strength_and_type(DEFAULT_STRENGTH, HashType::ConstantLength(length))
     }
 
     pub fn with_length(&self, length: usize) -> Self {
-        let arity = A::to_usize();
-        assert!(length <= arity);
+        self.try_with_length(length)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
 
+    /// Like `with_length`, but returns an error if `length` is not in `1..=arity` or the hash type is not
+    /// `ConstantLength`.
+    pub fn try_with_length(&self, length: usize) -> Result<Self, Error> {
         let hash_type = match self.hash_type {
             HashType::ConstantLength(_) => HashType::ConstantLength(length),
-            _ => panic!("cannot set constant length of hash without type ConstantLength."),
+            _ => {
+                return Err(Error::InvalidHashType(format!(
+                    "cannot set constant length of hash type {}",
+                    self.hash_type.name()
+                )))
+            }
         };
 
-        let domain_tag = hash_type.domain_tag(&self.strength);
+        let domain_tag = hash_type.try_domain_tag(&self.strength)?;
 
-        Self {
+        Ok(Self {
             hash_type,
             domain_tag,
             ..self.clone()
         }
+        .with_fingerprint())
     }
 
     pub fn new_with_strength(strength: Strength) -> Self {
@@ -174,7 +207,19 @@ where
     }
 
     pub fn new_with_strength_and_type(strength: Strength, hash_type: HashType<E::Fr, A>) -> Self {
-        assert!(hash_type.is_supported());
+        Self::try_new_with_strength_and_type(strength, hash_type)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `new_with_strength_and_type`, but returns an error if `hash_type` is unsupported or invalid.
+    pub fn try_new_with_strength_and_type(
+        strength: Strength,
+        hash_type: HashType<E::Fr, A>,
+    ) -> Result<Self, Error> {
+        if !hash_type.is_supported() {
+            return Err(Error::UnsupportedHashType(hash_type.name()));
+        }
+        let domain_tag = hash_type.try_domain_tag(&strength)?;
         let arity = A::to_usize();
         let width = arity + 1;
 
@@ -206,20 +251,27 @@ where
             compressed_round_constants.len()
         );
 
-        Self {
+        Ok(Self {
             mds_matrices,
             round_constants,
             compressed_round_constants,
             pre_sparse_matrix,
             sparse_matrixes,
             strength,
-            domain_tag: hash_type.domain_tag(&strength),
+            domain_tag,
             full_rounds,
             half_full_rounds,
             partial_rounds,
             hash_type,
+            fingerprint: Fingerprint::UNSET,
             _a: PhantomData::<A>,
         }
+        .with_fingerprint())
+    }
+
+    fn with_fingerprint(mut self) -> Self {
+        self.fingerprint = Fingerprint::of(&self);
+        self
     }
 
     /// Returns the width.
@@ -233,6 +285,12 @@ where
     pub fn width(&self) -> usize {
         A::ConstantsSize::to_usize()
     }
+
+    /// Fingerprint of these constants. See `Fingerprint`. It is computed once, on construction: use `Fingerprint::of`
+    /// for constants modified since.
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
 }
 
 impl<'a, E, A> Poseidon<'a, E, A>
@@ -254,48 +312,65 @@ where
             elements,
             pos: 1,
             constants,
+            trace: None,
             _e: PhantomData::<E>,
         }
     }
 
     pub fn new_with_preimage(preimage: &[E::Fr], constants: &'a PoseidonConstants<E, A>) -> Self {
-        let elements = match constants.hash_type {
-            HashType::ConstantLength(constant_len) => {
-                assert_eq!(constant_len, preimage.len(), "Invalid preimage size");
-
-                GenericArray::generate(|i| {
-                    if i == 0 {
-                        constants.domain_tag
-                    } else if i > preimage.len() {
-                        E::Fr::zero()
-                    } else {
-                        preimage[i - 1]
-                    }
-                })
-            }
-            HashType::VariableLength => panic!("variable-length hashes are not yet supported."),
-            _ => {
-                assert_eq!(preimage.len(), A::to_usize(), "Invalid preimage size");
+        Self::try_new_with_preimage(preimage, constants).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-                GenericArray::generate(|i| {
-                    if i == 0 {
-                        constants.domain_tag
-                    } else {
-                        preimage[i - 1]
-                    }
-                })
-            }
+    /// Like `new_with_preimage`, but returns an error if the preimage length does not match the hash type. A
+    /// `VariableLength` preimage may have up to `A` elements.
+    pub fn try_new_with_preimage(
+        preimage: &[E::Fr],
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Self, Error> {
+        let expected = match constants.hash_type {
+            HashType::ConstantLength(constant_len) => constant_len,
+            HashType::VariableLength => preimage.len().min(A::to_usize()),
+            _ => A::to_usize(),
         };
-        let width = preimage.len();
+        if preimage.len() != expected {
+            return Err(Error::InvalidPreimageLength {
+                expected,
+                actual: preimage.len(),
+            });
+        }
 
-        Poseidon {
+        let elements = match constants.hash_type {
+            HashType::ConstantLength(_) | HashType::VariableLength => GenericArray::generate(|i| {
+                if i == 0 {
+                    constants.domain_tag
+                } else if i > preimage.len() {
+                    E::Fr::zero()
+                } else {
+                    preimage[i - 1]
+                }
+            }),
+            _ => GenericArray::generate(|i| {
+                if i == 0 {
+                    constants.domain_tag
+                } else {
+                    preimage[i - 1]
+                }
+            }),
+        };
+        let width = match constants.hash_type {
+            HashType::VariableLength => preimage.len() + 1,
+            _ => preimage.len(),
+        };
+
+        Ok(Poseidon {
             constants_offset: 0,
             current_round: 0,
             elements,
             pos: width,
             constants,
+            trace: None,
             _e: PhantomData::<E>,
-        }
+        })
     }
 
     /// Replace the elements with the provided optional items.
@@ -318,6 +393,53 @@ where
             .for_each(|l| *l = scalar_from_u64::<E::Fr>(0u64));
         self.elements[0] = self.constants.domain_tag;
         self.pos = 1;
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
+    }
+
+    /// Record a `Trace` of the intermediate states of subsequent permutations.
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(Trace::new(
+            self.constants.half_full_rounds,
+            self.constants.partial_rounds,
+        ));
+    }
+
+    /// Take the recorded trace, if tracing is enabled. Tracing remains enabled, with an empty trace.
+    pub fn take_trace(&mut self) -> Option<Trace<E::Fr>> {
+        let trace = self.trace.take();
+        if trace.is_some() {
+            self.enable_trace();
+        }
+        trace
+    }
+
+    /// Hash in `mode`, recording a trace of the permutation.
+    pub fn hash_in_mode_traced(&mut self, mode: HashMode) -> (E::Fr, Trace<E::Fr>) {
+        self.enable_trace();
+        let digest = self.hash_in_mode(mode);
+        let trace = self.trace.take().expect("tracing is enabled");
+
+        (digest, trace)
+    }
+
+    pub(crate) fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Record the current state, if tracing.
+    pub(crate) fn record(&mut self, step: TraceStep) {
+        if let Some(trace) = &mut self.trace {
+            trace.record(step, &self.elements);
+        }
+    }
+
+    /// Record `elements` as the state after `step`, if tracing.
+    pub(crate) fn record_state(&mut self, step: TraceStep, elements: &[E::Fr]) {
+        if let Some(trace) = &mut self.trace {
+            trace.record(step, elements);
+        }
     }
 
     /// The returned `usize` represents the element position (within arity) for the input operation
@@ -334,7 +456,12 @@ where
         Ok(self.pos - 1)
     }
 
+    /// Hash the elements input so far. Variable-length hashes are computed with `Sponge`, whatever the `mode`, so they
+    /// match `Sponge::hash` and the circuit.
     pub fn hash_in_mode(&mut self, mode: HashMode) -> E::Fr {
+        if let HashType::VariableLength = self.constants.hash_type {
+            return Sponge::hash(&self.elements[1..self.pos], self.constants);
+        }
         self.apply_padding();
         match mode {
             Correct => hash_correct(self),
@@ -357,11 +484,18 @@ where
                 // There is nothing to do here, but only because the state elements were
                 // initialized to zero, and that is what we need to pad with.
             }
-            HashType::VariableLength => todo!(),
             _ => (),
         }
     }
 
+    /// Apply the permutation to the current state, without padding. All elements of the state are permuted, so the
+    /// permutation may be applied again, as a sponge does between blocks.
+    pub(crate) fn permute(&mut self) {
+        self.constants_offset = 0;
+        self.current_round = 0;
+        self.hash_optimized_static();
+    }
+
     pub fn hash_optimized_static(&mut self) -> E::Fr {
         // The first full round should use the initial constants.
         self.add_round_constants();
@@ -429,6 +563,7 @@ where
         } else {
             self.constants_offset += self.elements.len();
         }
+        self.record(TraceStep::SBox);
         self.round_product_mds();
     }
 
@@ -439,6 +574,7 @@ where
         // Apply the quintic S-Box to the first element
         quintic_s_box::<E>(&mut self.elements[0], None, Some(&post_round_key));
         self.constants_offset += 1;
+        self.record(TraceStep::SBox);
 
         self.round_product_mds();
     }
@@ -453,6 +589,7 @@ where
             element.add_assign(round_constant);
         }
         self.constants_offset += self.elements.len();
+        self.record(TraceStep::AddRoundKeys);
     }
 
     /// Set the provided elements with the result of the product between the elements and the appropriate
@@ -460,8 +597,9 @@ where
     fn round_product_mds(&mut self) {
         let full_half = self.constants.half_full_rounds;
         let sparse_offset = full_half - 1;
-        if self.current_round == sparse_offset {
+        let step = if self.current_round == sparse_offset {
             self.product_mds_with_matrix(&self.constants.pre_sparse_matrix);
+            TraceStep::PreSparseMatrix
         } else {
             if (self.current_round > sparse_offset)
                 && (self.current_round < full_half + self.constants.partial_rounds)
@@ -470,10 +608,13 @@ where
                 let sparse_matrix = &self.constants.sparse_matrixes[index];
 
                 self.product_mds_with_sparse_matrix(&sparse_matrix);
+                TraceStep::SparseMatrix
             } else {
                 self.product_mds();
+                TraceStep::Mds
             }
         };
+        self.record(step);
 
         self.current_round += 1;
     }
@@ -498,7 +639,7 @@ where
             }
         }
 
-        let _ = std::mem::replace(&mut self.elements, result);
+        let _ = core::mem::replace(&mut self.elements, result);
     }
 
     // Sparse matrix in this context means one of the form, M''.
@@ -522,9 +663,10 @@ where
             val.add_assign(&tmp);
         }
 
-        let _ = std::mem::replace(&mut self.elements, result);
+        let _ = core::mem::replace(&mut self.elements, result);
     }
 
+    #[cfg(feature = "std")]
     fn debug(&self, msg: &str) {
         dbg!(msg, &self.constants_offset, &self.elements);
     }
@@ -573,6 +715,15 @@ where
     }
 }
 
+impl<A> Fingerprinted for SimplePoseidonBatchHasher<A>
+where
+    A: Arity<Fr>,
+{
+    fn fingerprint(&self) -> Fingerprint {
+        self.constants.fingerprint()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -815,4 +966,86 @@ mod tests {
             default_constants.partial_rounds
         );
     }
+
+    #[test]
+    fn try_apis() {
+        match PoseidonConstants::<Bls12, U4>::try_new_constant_length(5) {
+            Err(Error::InvalidConstantLength {
+                length: 5,
+                arity: 4,
+            }) => (),
+            _ => panic!("constant length greater than arity was accepted"),
+        }
+        assert!(PoseidonConstants::<Bls12, U4>::try_new_constant_length(0).is_err());
+        assert!(
+            PoseidonConstants::<Bls12, U4>::try_new_with_strength_and_type(
+                Strength::Standard,
+                HashType::MerkleTreeSparse(1)
+            )
+            .is_err()
+        );
+
+        let merkle = PoseidonConstants::<Bls12, U4>::new();
+        assert!(merkle.try_with_length(2).is_err());
+
+        let constant = PoseidonConstants::<Bls12, U4>::try_new_constant_length(3).unwrap();
+        assert_eq!(
+            PoseidonConstants::<Bls12, U4>::new_constant_length(2),
+            constant.try_with_length(2).unwrap()
+        );
+        assert!(constant.try_with_length(5).is_err());
+
+        let preimage = vec![Scalar::one(); 3];
+        match Poseidon::try_new_with_preimage(&preimage[..2], &constant) {
+            Err(Error::InvalidPreimageLength {
+                expected: 3,
+                actual: 2,
+            }) => (),
+            _ => panic!("preimage of the wrong length was accepted"),
+        }
+        assert!(Poseidon::try_new_with_preimage(&preimage, &merkle).is_err());
+        assert_eq!(
+            Poseidon::new_with_preimage(&preimage, &constant).hash(),
+            Poseidon::try_new_with_preimage(&preimage, &constant)
+                .unwrap()
+                .hash()
+        );
+    }
+
+    #[test]
+    fn hash_variable_length() {
+        let constants = PoseidonConstants::<Bls12, U4>::new_with_strength_and_type(
+            Strength::Standard,
+            HashType::VariableLength,
+        );
+        let preimage = (0..5).map(|n| scalar_from_u64::<Fr>(n)).collect::<Vec<_>>();
+
+        for length in 0..=4 {
+            let expected = crate::sponge::Sponge::hash(&preimage[..length], &constants);
+            let mut p = Poseidon::new_with_preimage(&preimage[..length], &constants);
+            assert_eq!(expected, p.hash_in_mode(Correct));
+
+            let mut p = Poseidon::new(&constants);
+            for element in &preimage[..length] {
+                p.input(*element).unwrap();
+            }
+            assert_eq!(expected, p.hash());
+        }
+        assert!(Poseidon::try_new_with_preimage(&preimage, &constants).is_err());
+    }
+
+    #[test]
+    fn eq_ignores_trace() {
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let preimage = vec![Scalar::one(); 2];
+        let mut traced = Poseidon::new_with_preimage(&preimage, &constants);
+        let mut untraced = traced.clone();
+        traced.enable_trace();
+        assert_eq!(traced, untraced);
+
+        traced.hash();
+        assert_ne!(traced, untraced);
+        untraced.hash();
+        assert_eq!(traced, untraced);
+    }
 }
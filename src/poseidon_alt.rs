@@ -2,6 +2,7 @@
 //! These are tested (in `poseidon::test`) to be equivalent to the 'static optimized' version
 //! used for actual hashing by the neptune library.
 use crate::poseidon::{Arity, Poseidon};
+use crate::trace::TraceStep;
 use crate::{matrix, quintic_s_box};
 use ff::{Field, ScalarEngine};
 
@@ -46,6 +47,15 @@ where
     // Round keys are added in the S-box to match circuits (where the addition is free)
     // and in preparation for the shift to adding round keys after (rather than before) applying the S-box.
 
+    if p.is_tracing() {
+        let mut state = p.elements.clone();
+        state
+            .iter_mut()
+            .zip(p.constants.round_constants.iter().skip(p.constants_offset))
+            .for_each(|(l, key)| l.add_assign(key));
+        p.record_state(TraceStep::AddRoundKeys, &state);
+    }
+
     let pre_round_keys = p
         .constants
         .round_constants
@@ -61,10 +71,12 @@ where
         });
 
     p.constants_offset += p.elements.len();
+    p.record(TraceStep::SBox);
 
     // M(B)
     // Multiply the elements by the constant MDS matrix
     p.product_mds();
+    p.record(TraceStep::Mds);
 }
 
 /// The partial round is the same as the full round, with the difference that we apply the S-Box only to the first bitflags poseidon leaf.
@@ -75,12 +87,15 @@ where
 {
     // Every element of the hash buffer is incremented by the round constants
     add_round_constants(p);
+    p.record(TraceStep::AddRoundKeys);
 
     // Apply the quintic S-Box to the first element
     quintic_s_box::<E>(&mut p.elements[0], None, None);
+    p.record(TraceStep::SBox);
 
     // Multiply the elements by the constant MDS matrix
     p.product_mds();
+    p.record(TraceStep::Mds);
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -199,6 +214,7 @@ pub fn full_round_dynamic<'a, E, A>(
         consumed += p.elements.len()
     };
     p.constants_offset += consumed;
+    p.record(TraceStep::SBox);
 
     // If absorb_next_round_keys
     //   M(B + M^-1(S)
@@ -206,6 +222,7 @@ pub fn full_round_dynamic<'a, E, A>(
     //   M(B)
     // Multiply the elements by the constant MDS matrix
     p.product_mds();
+    p.record(TraceStep::Mds);
 }
 
 pub fn partial_round_dynamic<'a, E, A>(p: &mut Poseidon<'a, E, A>)
@@ -215,9 +232,11 @@ where
 {
     // Apply the quintic S-Box to the first element
     quintic_s_box::<E>(&mut p.elements[0], None, None);
+    p.record(TraceStep::SBox);
 
     // Multiply the elements by the constant MDS matrix
     p.product_mds();
+    p.record(TraceStep::Mds);
 }
 
 /// For every leaf, add the round constants with index defined by the constants offset, and increment the
@@ -0,0 +1,186 @@
+//! Named, versioned parameter profiles.
+//!
+//! A `Profile` names a complete choice of Poseidon parameters, so that two parties can agree on a name rather than on
+//! a list of options. Profile names have the form `<family>-v<version>-arity<arity>-<strength>`, for example
+//! `filecoin-v1-arity8-standard`. The parameters of a published profile never change: a different choice of
+//! parameters gets a new version, and so a new name.
+//!
+//! All current profiles use the `MerkleTree` hash type, as Filecoin does for its trees and columns.
+//!
+//! ```ignore
+//! use neptune::profile::profile;
+//!
+//! let profile = profile("filecoin-v1-arity8-standard")?;
+//! let constants = profile.constants::<U8>()?;
+//! // Later, e.g. before using constants received from elsewhere:
+//! profile.check(&constants)?;
+//! ```
+use crate::fingerprint::Fingerprint;
+use crate::hash_type::HashType;
+use crate::poseidon::{Arity, PoseidonConstants};
+use crate::{Error, Strength};
+use bellperson::bls::{Bls12, Fr};
+
+/// A named set of Poseidon parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Profile {
+    pub name: &'static str,
+    pub version: u32,
+    pub arity: usize,
+    pub strength: Strength,
+}
+
+/// Every known profile.
+pub const PROFILES: &[Profile] = &[
+    Profile {
+        name: "filecoin-v1-arity2-standard",
+        version: 1,
+        arity: 2,
+        strength: Strength::Standard,
+    },
+    Profile {
+        name: "filecoin-v1-arity4-standard",
+        version: 1,
+        arity: 4,
+        strength: Strength::Standard,
+    },
+    Profile {
+        name: "filecoin-v1-arity8-standard",
+        version: 1,
+        arity: 8,
+        strength: Strength::Standard,
+    },
+    Profile {
+        name: "filecoin-v1-arity11-standard",
+        version: 1,
+        arity: 11,
+        strength: Strength::Standard,
+    },
+    Profile {
+        name: "filecoin-v1-arity2-strengthened",
+        version: 1,
+        arity: 2,
+        strength: Strength::Strengthened,
+    },
+    Profile {
+        name: "filecoin-v1-arity4-strengthened",
+        version: 1,
+        arity: 4,
+        strength: Strength::Strengthened,
+    },
+    Profile {
+        name: "filecoin-v1-arity8-strengthened",
+        version: 1,
+        arity: 8,
+        strength: Strength::Strengthened,
+    },
+    Profile {
+        name: "filecoin-v1-arity11-strengthened",
+        version: 1,
+        arity: 11,
+        strength: Strength::Strengthened,
+    },
+];
+
+/// Look up a profile by name.
+pub fn profile(name: &str) -> Result<&'static Profile, Error> {
+    PROFILES
+        .iter()
+        .find(|profile| profile.name == name)
+        .ok_or_else(|| Error::UnknownProfile(name.to_string()))
+}
+
+impl Profile {
+    /// Build the constants of this profile. `A` must be the profile's arity.
+    pub fn constants<A: Arity<Fr>>(&self) -> Result<PoseidonConstants<Bls12, A>, Error> {
+        if A::to_usize() != self.arity {
+            return Err(Error::ProfileArityMismatch {
+                profile: self.name.to_string(),
+                arity: A::to_usize(),
+            });
+        }
+
+        PoseidonConstants::try_new_with_strength_and_type(self.strength, HashType::MerkleTree)
+    }
+
+    /// Fingerprint of the constants of this profile. `A` must be the profile's arity.
+    pub fn fingerprint<A: Arity<Fr>>(&self) -> Result<Fingerprint, Error> {
+        Ok(self.constants::<A>()?.fingerprint())
+    }
+
+    /// Returns an error unless `constants` are exactly those of this profile.
+    pub fn check<A: Arity<Fr>>(
+        &self,
+        constants: &PoseidonConstants<Bls12, A>,
+    ) -> Result<(), Error> {
+        self.fingerprint::<A>()?.check(&constants.fingerprint())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generic_array::typenum::{U11, U2, U4, U8};
+
+    #[test]
+    fn test_profiles() {
+        for (i, a) in PROFILES.iter().enumerate() {
+            let strength = match a.strength {
+                Strength::Standard => "standard",
+                Strength::Strengthened => "strengthened",
+            };
+            assert_eq!(
+                format!("filecoin-v{}-arity{}-{}", a.version, a.arity, strength),
+                a.name
+            );
+            assert_eq!(a, profile(a.name).unwrap());
+            for b in PROFILES.iter().skip(i + 1) {
+                assert_ne!(a.name, b.name);
+            }
+        }
+
+        match profile("filecoin-v0-arity8-standard") {
+            Err(Error::UnknownProfile(name)) => assert_eq!("filecoin-v0-arity8-standard", name),
+            _ => panic!("unknown profile was found"),
+        }
+    }
+
+    #[test]
+    fn test_profile_constants() {
+        let standard = profile("filecoin-v1-arity8-standard").unwrap();
+        let strengthened = profile("filecoin-v1-arity8-strengthened").unwrap();
+
+        // The standard profiles are the default constants.
+        let constants = PoseidonConstants::<Bls12, U8>::new();
+        assert_eq!(constants, standard.constants::<U8>().unwrap());
+        assert!(standard.check(&constants).is_ok());
+        assert!(strengthened.check(&constants).is_err());
+        assert!(standard
+            .check(&PoseidonConstants::<Bls12, U8>::new_constant_length(8))
+            .is_err());
+
+        match standard.constants::<U4>() {
+            Err(Error::ProfileArityMismatch { profile, arity }) => {
+                assert_eq!((standard.name, 4), (profile.as_str(), arity))
+            }
+            _ => panic!("constants built with the wrong arity"),
+        }
+
+        // Every profile has distinct constants.
+        let mut fingerprints = Vec::new();
+        for profile in PROFILES {
+            fingerprints.push(match profile.arity {
+                2 => profile.fingerprint::<U2>().unwrap(),
+                4 => profile.fingerprint::<U4>().unwrap(),
+                8 => profile.fingerprint::<U8>().unwrap(),
+                11 => profile.fingerprint::<U11>().unwrap(),
+                arity => panic!("untested arity {}", arity),
+            });
+        }
+        for (i, a) in fingerprints.iter().enumerate() {
+            for b in fingerprints.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+    }
+}
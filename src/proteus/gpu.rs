@@ -1,8 +1,9 @@
 use super::sources::generate_program;
 use crate::error::{ClError, Error};
+use crate::fingerprint::Fingerprint;
 use crate::hash_type::HashType;
 use crate::poseidon::PoseidonConstants;
-use crate::{Arity, BatchHasher, Strength, DEFAULT_STRENGTH};
+use crate::{Arity, BatchHasher, Fingerprinted, Strength, DEFAULT_STRENGTH};
 use bellperson::bls::{Bls12, Fr, FrRepr};
 use ff::{Field, PrimeField, PrimeFieldDecodingError};
 use generic_array::{typenum, ArrayLength, GenericArray};
@@ -25,6 +26,8 @@ where
     device: opencl::Device,
     constants: GPUConstants<A>,
     constants_buffer: opencl::Buffer<Fr>,
+    /// Fingerprint of the constants in `constants_buffer`, read back from the device.
+    fingerprint: Fingerprint,
     max_batch_size: usize,
     program: opencl::Program,
 }
@@ -176,6 +179,21 @@ where
 
         Ok(buffer)
     }
+
+    /// Fingerprint of the constants the kernel reads from `buffer`, as written by `to_buffer`.
+    fn buffer_fingerprint(&self, buffer: &opencl::Buffer<Fr>) -> Result<Fingerprint, Error> {
+        let mut uploaded = vec![<Fr as Field>::zero(); self.derived_constants().constants_elements];
+        buffer
+            .read_into(0, &mut uploaded)
+            .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
+
+        Ok(Fingerprint::of_kernel_constants(
+            self.0.width(),
+            self.full_rounds(),
+            self.partial_rounds(),
+            &uploaded,
+        ))
+    }
 }
 
 pub fn get_device(selector: &GPUSelector) -> Result<&'static opencl::Device, Error> {
@@ -207,10 +225,12 @@ where
         let program = opencl::Program::from_opencl(device.clone(), &src)
             .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
         let constants_buffer = constants.to_buffer(&program)?;
+        let fingerprint = constants.buffer_fingerprint(&constants_buffer)?;
         Ok(Self {
             device: device.clone(),
             constants,
             constants_buffer,
+            fingerprint,
             max_batch_size,
             program,
         })
@@ -229,7 +249,12 @@ where
         let local_work_size = LOCAL_WORK_SIZE;
         let max_batch_size = self.max_batch_size;
         let batch_size = preimages.len();
-        assert!(batch_size <= max_batch_size);
+        if batch_size > max_batch_size {
+            return Err(Error::BatchTooLarge {
+                batch_size,
+                max_batch_size,
+            });
+        }
 
         // Set `global_work_size` to smallest multiple of `local_work_size` >= `batch-size`.
         let global_work_size = ((batch_size / local_work_size)
@@ -278,7 +303,12 @@ where
         let local_work_size = LOCAL_WORK_SIZE;
         let max_batch_size = self.max_batch_size;
         let batch_size = preimages.len();
-        assert!(batch_size <= max_batch_size);
+        if batch_size > max_batch_size {
+            return Err(Error::BatchTooLarge {
+                batch_size,
+                max_batch_size,
+            });
+        }
 
         // Set `global_work_size` to smallest multiple of `local_work_size` >= `batch-size`.
         let global_work_size = ((batch_size / local_work_size)
@@ -320,6 +350,15 @@ where
     }
 }
 
+impl<A> Fingerprinted for CLBatchHasher<A>
+where
+    A: Arity<Fr>,
+{
+    fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+}
+
 #[cfg(test)]
 #[cfg(all(feature = "opencl", not(target_os = "macos")))]
 mod test {
@@ -342,6 +381,7 @@ mod test {
         let mut simple_hasher =
             SimplePoseidonBatchHasher::<U2>::new_with_strength(Strength::Standard, batch_size)
                 .unwrap();
+        assert_eq!(simple_hasher.fingerprint(), cl_hasher.fingerprint());
 
         let preimages = (0..batch_size)
             .map(|_| GenericArray::<Fr, U2>::generate(|_| Fr::random(&mut rng)))
@@ -0,0 +1,10 @@
+//! Hash-based signatures over Poseidon, whose verification is cheap in R1CS.
+//!
+//! `wots` provides Winternitz one-time signatures of a single field element. `xmss` authenticates many WOTS public keys
+//! with a Merkle tree built by `TreeBuilder`, for a fixed number of signatures per key. Security rests only on the
+//! Poseidon hash, so both are believed to resist quantum attacks. Each has a verification gadget built on
+//! `circuit::poseidon_hash`; that of `xmss` checks the path of the signing key with `merkle::root_circuit`.
+pub mod wots;
+
+#[cfg(any(feature = "gpu", feature = "opencl"))]
+pub mod xmss;
@@ -0,0 +1,575 @@
+//! Winternitz one-time signatures (WOTS) of a field element.
+//!
+//! The message is split into `MESSAGE_DIGITS` base-16 digits, followed by `CHECKSUM_DIGITS` digits of the checksum
+//! `Σ (15 - digit)`. Each digit has a hash chain of length `CHAIN_LENGTH`, which starts at a secret derived from the
+//! secret seed. The public key is a digest of the ends of the chains, and a signature reveals, for each digit `d`, the
+//! element `d` steps down its chain. Advancing a chain to sign a larger digit lowers the checksum, whose chains cannot
+//! be walked backwards.
+//!
+//! Every hash is keyed by a public seed and by the position in the chain, so that the chains of different keys and
+//! positions are independent. A secret key must sign only one message: `WotsSecretKey::sign` consumes it.
+use crate::circuit::{poseidon_hash_elts, Elt};
+use crate::hash_to_field::bits_lc;
+use crate::hash_type::{CType, HashType};
+use crate::poseidon::{Poseidon, PoseidonConstants};
+use crate::{scalar_from_u64, Error, Strength};
+use bellperson::bls::{Bls12, Fr};
+use bellperson::gadgets::boolean::{AllocatedBit, Boolean};
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::{ConstraintSystem, SynthesisError};
+use ff::{Field, PrimeField};
+use generic_array::typenum::{U3, U8};
+
+/// Number of message bits per digit.
+pub const DIGIT_BITS: usize = 4;
+/// Number of hash steps in each chain, which is also the largest digit.
+pub const CHAIN_LENGTH: usize = (1 << DIGIT_BITS) - 1;
+/// Number of digits of the message, enough for the 255 bits of a field element.
+pub const MESSAGE_DIGITS: usize = 64;
+/// Number of digits of the checksum, whose maximum is `MESSAGE_DIGITS * CHAIN_LENGTH = 960`.
+pub const CHECKSUM_DIGITS: usize = 3;
+/// Number of chains, and of elements in a signature.
+pub const CHAINS: usize = MESSAGE_DIGITS + CHECKSUM_DIGITS;
+
+/// Number of chain ends absorbed by each hash of the public key compression.
+const PUBLIC_KEY_CHUNK: usize = 7;
+/// Tweaks of chain steps are below `CHAINS * (CHAIN_LENGTH + 1)`; those used to derive secrets start here.
+pub(crate) const SECRET_TWEAK: u64 = 1 << 32;
+
+/// Constants for WOTS: an arity-3 hash for chain steps and an arity-8 hash compressing the public key, each with its
+/// own custom domain tag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WotsConstants {
+    pub chain: PoseidonConstants<Bls12, U3>,
+    pub public_key: PoseidonConstants<Bls12, U8>,
+}
+
+impl WotsConstants {
+    pub fn new() -> Self {
+        Self {
+            chain: PoseidonConstants::new_with_strength_and_type(
+                Strength::Standard,
+                HashType::Custom(CType::from_domain("neptune.wots.chain")),
+            ),
+            public_key: PoseidonConstants::new_with_strength_and_type(
+                Strength::Standard,
+                HashType::Custom(CType::from_domain("neptune.wots.public-key")),
+            ),
+        }
+    }
+}
+
+impl Default for WotsConstants {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A one-time secret key. It is deterministically derived from its seeds; the secret seed must be uniformly random.
+pub struct WotsSecretKey {
+    secret_seed: Fr,
+    public_seed: Fr,
+}
+
+/// A one-time public key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WotsPublicKey {
+    pub public_seed: Fr,
+    pub digest: Fr,
+}
+
+/// A one-time signature: one element of each chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WotsSignature {
+    pub chains: Vec<Fr>,
+}
+
+impl WotsSecretKey {
+    pub fn new(secret_seed: Fr, public_seed: Fr) -> Self {
+        Self {
+            secret_seed,
+            public_seed,
+        }
+    }
+
+    pub fn public_key(&self, constants: &WotsConstants) -> WotsPublicKey {
+        let ends = (0..CHAINS)
+            .map(|chain| {
+                advance(
+                    self.chain_start(chain, constants),
+                    chain,
+                    0,
+                    CHAIN_LENGTH,
+                    &self.public_seed,
+                    constants,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        WotsPublicKey {
+            public_seed: self.public_seed,
+            digest: compress_public_key(&ends, &self.public_seed, constants),
+        }
+    }
+
+    /// Sign `message`, consuming the key.
+    pub fn sign(self, message: &Fr, constants: &WotsConstants) -> WotsSignature {
+        let chains = digits(message)
+            .iter()
+            .enumerate()
+            .map(|(chain, &digit)| {
+                advance(
+                    self.chain_start(chain, constants),
+                    chain,
+                    0,
+                    digit,
+                    &self.public_seed,
+                    constants,
+                )
+            })
+            .collect();
+
+        WotsSignature { chains }
+    }
+
+    fn chain_start(&self, chain: usize, constants: &WotsConstants) -> Fr {
+        tweak_hash(
+            &self.public_seed,
+            SECRET_TWEAK + chain as u64,
+            &self.secret_seed,
+            constants,
+        )
+    }
+}
+
+impl WotsPublicKey {
+    pub fn verify(
+        &self,
+        message: &Fr,
+        signature: &WotsSignature,
+        constants: &WotsConstants,
+    ) -> bool {
+        signature
+            .recover_public_key(message, &self.public_seed, constants)
+            .map_or(false, |public_key| public_key == *self)
+    }
+}
+
+impl WotsSignature {
+    /// The public key under which this is a signature of `message`. A signature is valid if it recovers the expected
+    /// public key.
+    pub fn recover_public_key(
+        &self,
+        message: &Fr,
+        public_seed: &Fr,
+        constants: &WotsConstants,
+    ) -> Result<WotsPublicKey, Error> {
+        if self.chains.len() != CHAINS {
+            return Err(Error::InvalidSignatureLength {
+                expected: CHAINS,
+                actual: self.chains.len(),
+            });
+        }
+
+        let ends = digits(message)
+            .iter()
+            .zip(self.chains.iter())
+            .enumerate()
+            .map(|(chain, (&digit, element))| {
+                advance(
+                    *element,
+                    chain,
+                    digit,
+                    CHAIN_LENGTH - digit,
+                    public_seed,
+                    constants,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Ok(WotsPublicKey {
+            public_seed: *public_seed,
+            digest: compress_public_key(&ends, public_seed, constants),
+        })
+    }
+}
+
+/// `H(public_seed, tweak, x)`.
+pub(crate) fn tweak_hash(public_seed: &Fr, tweak: u64, x: &Fr, constants: &WotsConstants) -> Fr {
+    Poseidon::new_with_preimage(
+        &[*public_seed, scalar_from_u64(tweak), *x],
+        &constants.chain,
+    )
+    .hash()
+}
+
+fn chain_tweak(chain: usize, step: usize) -> u64 {
+    (chain * (CHAIN_LENGTH + 1) + step) as u64
+}
+
+/// Advance `x`, which is at position `start` of `chain`, by `steps` steps.
+fn advance(
+    mut x: Fr,
+    chain: usize,
+    start: usize,
+    steps: usize,
+    public_seed: &Fr,
+    constants: &WotsConstants,
+) -> Fr {
+    for step in start..start + steps {
+        x = tweak_hash(public_seed, chain_tweak(chain, step), &x, constants);
+    }
+    x
+}
+
+/// The message digits, least significant first, followed by the checksum digits.
+fn digits(message: &Fr) -> [usize; CHAINS] {
+    let repr = message.into_repr();
+    let limbs = repr.as_ref();
+    let digits_per_limb = 64 / DIGIT_BITS;
+
+    let mut digits = [0; CHAINS];
+    for (i, digit) in digits.iter_mut().take(MESSAGE_DIGITS).enumerate() {
+        let limb = limbs[i / digits_per_limb];
+        *digit = ((limb >> (DIGIT_BITS * (i % digits_per_limb))) as usize) & CHAIN_LENGTH;
+    }
+    let checksum = checksum(&digits[..MESSAGE_DIGITS]);
+    for (i, digit) in digits[MESSAGE_DIGITS..].iter_mut().enumerate() {
+        *digit = (checksum >> (DIGIT_BITS * i)) & CHAIN_LENGTH;
+    }
+
+    digits
+}
+
+fn checksum(message_digits: &[usize]) -> usize {
+    message_digits
+        .iter()
+        .map(|digit| CHAIN_LENGTH - digit)
+        .sum()
+}
+
+/// Absorb the chain ends, `PUBLIC_KEY_CHUNK` at a time, into a state starting at the public seed.
+fn compress_public_key(ends: &[Fr], public_seed: &Fr, constants: &WotsConstants) -> Fr {
+    let mut state = *public_seed;
+    for chunk in ends.chunks(PUBLIC_KEY_CHUNK) {
+        let mut preimage = [Fr::zero(); PUBLIC_KEY_CHUNK + 1];
+        preimage[0] = state;
+        preimage[1..=chunk.len()].copy_from_slice(chunk);
+        state = Poseidon::new_with_preimage(&preimage, &constants.public_key).hash();
+    }
+    state
+}
+
+/// Allocate the elements of `signature`, or unassigned elements if it is `None`.
+pub fn alloc_signature<CS>(
+    mut cs: CS,
+    signature: Option<&WotsSignature>,
+) -> Result<Vec<AllocatedNum<Bls12>>, SynthesisError>
+where
+    CS: ConstraintSystem<Bls12>,
+{
+    (0..CHAINS)
+        .map(|i| {
+            AllocatedNum::alloc(cs.namespace(|| format!("chain {}", i)), || {
+                signature
+                    .and_then(|signature| signature.chains.get(i).copied())
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })
+        })
+        .collect()
+}
+
+/// Circuit computing the digest of the public key which `signature` recovers for `message`.
+///
+/// # Panics
+///
+/// Panics if `signature` does not have `CHAINS` elements.
+pub fn recover_public_key_circuit<CS>(
+    mut cs: CS,
+    message: &AllocatedNum<Bls12>,
+    signature: &[AllocatedNum<Bls12>],
+    public_seed: &AllocatedNum<Bls12>,
+    constants: &WotsConstants,
+) -> Result<AllocatedNum<Bls12>, SynthesisError>
+where
+    CS: ConstraintSystem<Bls12>,
+{
+    assert_eq!(CHAINS, signature.len(), "signature has the wrong length");
+
+    let message_bits = message.to_bits_le_strict(cs.namespace(|| "message bits"))?;
+    let mut digits = message_bits
+        .chunks(DIGIT_BITS)
+        .map(|bits| bits.to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(MESSAGE_DIGITS, digits.len());
+
+    // The checksum is small, so its bits are unique, and `Σ digit + checksum = 960` cannot wrap around.
+    let checksum_value = message
+        .get_value()
+        .map(|message| checksum(&self::digits(&message)[..MESSAGE_DIGITS]));
+    let checksum_bits = (0..CHECKSUM_DIGITS * DIGIT_BITS)
+        .map(|i| {
+            AllocatedBit::alloc(
+                cs.namespace(|| format!("checksum bit {}", i)),
+                checksum_value.map(|checksum| (checksum >> i) & 1 == 1),
+            )
+            .map(Boolean::from)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let maximum: Fr = scalar_from_u64((MESSAGE_DIGITS * CHAIN_LENGTH) as u64);
+    cs.enforce(
+        || "checksum",
+        |lc| {
+            digits.iter().fold(lc, |lc, digit| {
+                lc + &bits_lc::<Bls12>(digit, CS::one(), Fr::one())
+            }) + &bits_lc::<Bls12>(&checksum_bits, CS::one(), Fr::one())
+        },
+        |lc| lc + CS::one(),
+        |lc| lc + (maximum, CS::one()),
+    );
+    digits.extend(checksum_bits.chunks(DIGIT_BITS).map(|bits| bits.to_vec()));
+
+    let ends = digits
+        .iter()
+        .zip(signature)
+        .enumerate()
+        .map(|(chain, (digit, element))| {
+            chain_circuit(
+                cs.namespace(|| format!("chain {}", chain)),
+                element,
+                digit,
+                chain,
+                public_seed,
+                constants,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut state = public_seed.clone();
+    for (i, chunk) in ends.chunks(PUBLIC_KEY_CHUNK).enumerate() {
+        let mut cs = cs.namespace(|| format!("public key {}", i));
+        let mut preimage = Vec::with_capacity(PUBLIC_KEY_CHUNK + 1);
+        preimage.push(Elt::Allocated(state));
+        preimage.extend(chunk.iter().cloned().map(Elt::Allocated));
+        for _ in chunk.len()..PUBLIC_KEY_CHUNK {
+            preimage.push(Elt::num_from_fr::<CS>(Fr::zero()));
+        }
+        state = poseidon_hash_elts(cs.namespace(|| "hash"), preimage, &constants.public_key)?;
+    }
+
+    Ok(state)
+}
+
+/// Circuit enforcing that `signature` is a signature of `message` under the public key with the given digest and seed.
+pub fn verify_circuit<CS>(
+    mut cs: CS,
+    public_key: &AllocatedNum<Bls12>,
+    public_seed: &AllocatedNum<Bls12>,
+    message: &AllocatedNum<Bls12>,
+    signature: &[AllocatedNum<Bls12>],
+    constants: &WotsConstants,
+) -> Result<(), SynthesisError>
+where
+    CS: ConstraintSystem<Bls12>,
+{
+    let recovered = recover_public_key_circuit(
+        cs.namespace(|| "recover"),
+        message,
+        signature,
+        public_seed,
+        constants,
+    )?;
+    cs.enforce(
+        || "public key",
+        |lc| lc + recovered.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + public_key.get_variable(),
+    );
+
+    Ok(())
+}
+
+/// Advance `element` from position `digit` to the end of `chain`. Every step is hashed, and the result is kept only
+/// from step `digit` on.
+fn chain_circuit<CS>(
+    mut cs: CS,
+    element: &AllocatedNum<Bls12>,
+    digit: &[Boolean],
+    chain: usize,
+    public_seed: &AllocatedNum<Bls12>,
+    constants: &WotsConstants,
+) -> Result<AllocatedNum<Bls12>, SynthesisError>
+where
+    CS: ConstraintSystem<Bls12>,
+{
+    let digit_value = digit
+        .iter()
+        .enumerate()
+        .map(|(i, bit)| bit.get_value().map(|bit| (bit as usize) << i))
+        .sum::<Option<usize>>();
+
+    // `skip[j]` is set iff `digit > j`, i.e. iff step `j` is before the signed position. The bits are decreasing and
+    // sum to the digit, which fixes them.
+    let mut skip: Vec<AllocatedBit> = Vec::with_capacity(CHAIN_LENGTH);
+    for j in 0..CHAIN_LENGTH {
+        let bit = AllocatedBit::alloc(
+            cs.namespace(|| format!("skip {}", j)),
+            digit_value.map(|digit| digit > j),
+        )?;
+        if let Some(previous) = skip.last() {
+            cs.enforce(
+                || format!("skip order {}", j),
+                |lc| lc + bit.get_variable(),
+                |lc| lc + CS::one() - previous.get_variable(),
+                |lc| lc,
+            );
+        }
+        skip.push(bit);
+    }
+    cs.enforce(
+        || "skip sum",
+        |lc| skip.iter().fold(lc, |lc, bit| lc + bit.get_variable()),
+        |lc| lc + CS::one(),
+        |_| bits_lc::<Bls12>(digit, CS::one(), Fr::one()),
+    );
+
+    let mut x = element.clone();
+    for (j, skip) in skip.iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("step {}", j));
+        let tweak = Elt::num_from_fr::<CS>(scalar_from_u64(chain_tweak(chain, j)));
+        let hashed = poseidon_hash_elts(
+            cs.namespace(|| "hash"),
+            vec![
+                Elt::Allocated(public_seed.clone()),
+                tweak,
+                Elt::Allocated(x.clone()),
+            ],
+            &constants.chain,
+        )?;
+        let next = AllocatedNum::alloc(cs.namespace(|| "next"), || {
+            if skip.get_value().ok_or(SynthesisError::AssignmentMissing)? {
+                x.get_value()
+            } else {
+                hashed.get_value()
+            }
+            .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        // skip * (x - hashed) = next - hashed
+        cs.enforce(
+            || "select",
+            |lc| lc + skip.get_variable(),
+            |lc| lc + x.get_variable() - hashed.get_variable(),
+            |lc| lc + next.get_variable() - hashed.get_variable(),
+        );
+        x = next;
+    }
+
+    Ok(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn key(rng: &mut XorShiftRng) -> WotsSecretKey {
+        WotsSecretKey::new(Fr::random(&mut *rng), Fr::random(&mut *rng))
+    }
+
+    #[test]
+    fn test_wots() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = WotsConstants::new();
+        let message = Fr::random(&mut rng);
+
+        let secret_key = key(&mut rng);
+        let public_key = secret_key.public_key(&constants);
+        let signature = secret_key.sign(&message, &constants);
+        assert_eq!(CHAINS, signature.chains.len());
+        assert!(public_key.verify(&message, &signature, &constants));
+
+        let other_message = Fr::random(&mut rng);
+        assert!(!public_key.verify(&other_message, &signature, &constants));
+        let other_key = key(&mut rng).public_key(&constants);
+        assert!(!other_key.verify(&message, &signature, &constants));
+
+        let mut tampered = signature.clone();
+        tampered.chains[CHAINS - 1].add_assign(&Fr::one());
+        assert!(!public_key.verify(&message, &tampered, &constants));
+
+        let mut truncated = signature;
+        truncated.chains.pop();
+        assert!(!public_key.verify(&message, &truncated, &constants));
+        match truncated.recover_public_key(&message, &public_key.public_seed, &constants) {
+            Err(Error::InvalidSignatureLength { expected, actual }) => {
+                assert_eq!((CHAINS, CHAINS - 1), (expected, actual))
+            }
+            _ => panic!("truncated signature was accepted"),
+        }
+    }
+
+    #[test]
+    fn test_wots_checksum() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = WotsConstants::new();
+
+        // The lowest digit of the message is 0. Advancing its chain one step gives the chain element of a message
+        // with lowest digit 1, but the checksum digits no longer match.
+        let message = scalar_from_u64::<Fr>(0x1230);
+        let mut forged_message = message;
+        forged_message.add_assign(&Fr::one());
+        assert_eq!(1, digits(&forged_message)[0]);
+        assert_eq!(
+            checksum(&digits(&message)[..MESSAGE_DIGITS]) - 1,
+            checksum(&digits(&forged_message)[..MESSAGE_DIGITS])
+        );
+
+        let secret_key = key(&mut rng);
+        let public_key = secret_key.public_key(&constants);
+        let mut forged = secret_key.sign(&message, &constants);
+        forged.chains[0] = advance(
+            forged.chains[0],
+            0,
+            0,
+            1,
+            &public_key.public_seed,
+            &constants,
+        );
+        assert!(!public_key.verify(&forged_message, &forged, &constants));
+    }
+
+    #[test]
+    fn test_wots_circuit() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = WotsConstants::new();
+        let message = Fr::random(&mut rng);
+        let secret_key = key(&mut rng);
+        let public_key = secret_key.public_key(&constants);
+        let signature = secret_key.sign(&message, &constants);
+
+        for (signed, valid) in [(message, true), (Fr::random(&mut rng), false)].iter() {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let alloc = |cs: &mut TestConstraintSystem<Bls12>, name: &'static str, value: Fr| {
+                AllocatedNum::alloc(cs.namespace(|| name), || Ok(value)).unwrap()
+            };
+            let digest = alloc(&mut cs, "public key", public_key.digest);
+            let public_seed = alloc(&mut cs, "public seed", public_key.public_seed);
+            let message = alloc(&mut cs, "message", *signed);
+            let signature =
+                alloc_signature(cs.namespace(|| "signature"), Some(&signature)).unwrap();
+
+            verify_circuit(
+                cs.namespace(|| "verify"),
+                &digest,
+                &public_seed,
+                &message,
+                &signature,
+                &constants,
+            )
+            .unwrap();
+            assert_eq!(*valid, cs.is_satisfied());
+        }
+    }
+}
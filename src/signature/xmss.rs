@@ -0,0 +1,360 @@
+//! XMSS-style few-time signatures: a Merkle tree authenticating `leaf_count` WOTS public keys.
+//!
+//! Leaf `i` of the tree is the digest of the `i`-th WOTS public key, whose secret seed is derived from the XMSS secret
+//! seed and `i`. All leaves share the XMSS public seed. The tree is built with a `TreeBuilder`, so that large keys can
+//! be generated on the GPU, and the public key is its root. A signature is the index of the WOTS key used, its WOTS
+//! signature and the authentication path from its leaf to the root.
+//!
+//! `XmssSecretKey` is stateful: each signature uses the next unused leaf. Callers which persist a key must persist its
+//! state before releasing a signature, or a leaf may be used twice.
+use super::wots::{self, tweak_hash, WotsConstants, WotsSecretKey, WotsSignature, SECRET_TWEAK};
+use crate::batch_hasher::BatcherType;
+use crate::merkle::{self, AllocatedMerklePath, MerkleConstants, MerklePath, PathLevel};
+use crate::poseidon::{Arity, Poseidon};
+use crate::tree_builder::{TreeBuilder, TreeBuilderTrait};
+use crate::Error;
+use bellperson::bls::{Bls12, Fr};
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::{ConstraintSystem, SynthesisError};
+use ff::Field;
+use std::marker::PhantomData;
+
+/// Tweaks deriving the WOTS secret seeds of the leaves, distinct from those of the WOTS chains and secrets.
+const LEAF_TWEAK: u64 = SECRET_TWEAK << 1;
+
+/// Constants for XMSS: those of WOTS, and those `TreeBuilder` hashes the tree with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XmssConstants<A: Arity<Fr>> {
+    pub wots: WotsConstants,
+    pub tree: MerkleConstants<A>,
+}
+
+impl<A: Arity<Fr>> XmssConstants<A> {
+    pub fn new() -> Self {
+        Self {
+            wots: WotsConstants::new(),
+            tree: MerkleConstants::new(),
+        }
+    }
+}
+
+impl<A: Arity<Fr>> Default for XmssConstants<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A secret key for `leaf_count` signatures.
+pub struct XmssSecretKey<A: Arity<Fr>> {
+    secret_seed: Fr,
+    public_seed: Fr,
+    leaf_count: usize,
+    /// The leaves followed by the rows above them, ending with the root.
+    nodes: Vec<Fr>,
+    next_index: usize,
+    _a: PhantomData<A>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XmssPublicKey {
+    pub public_seed: Fr,
+    pub root: Fr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct XmssSignature {
+    /// Index of the leaf whose WOTS key signed.
+    pub index: usize,
+    pub wots: WotsSignature,
+    /// For each row from the leaves up, the `arity - 1` siblings of the node on the path, in order.
+    pub path: Vec<Vec<Fr>>,
+}
+
+impl<A: Arity<Fr>> XmssSecretKey<A> {
+    /// Generate a key for `leaf_count` signatures, which must be a power of the arity. The tree is hashed by a batcher
+    /// of type `batcher`, or on the CPU if it is `None`.
+    pub fn new(
+        secret_seed: Fr,
+        public_seed: Fr,
+        leaf_count: usize,
+        batcher: Option<BatcherType>,
+        constants: &XmssConstants<A>,
+    ) -> Result<Self, Error> {
+        let mut builder = TreeBuilder::<A>::new(batcher, leaf_count, leaf_count, 0, None)?;
+        constants
+            .tree
+            .base
+            .fingerprint()
+            .check(&builder.fingerprint())?;
+
+        let mut key = Self {
+            secret_seed,
+            public_seed,
+            leaf_count,
+            nodes: vec![Fr::zero(); leaf_count + builder.try_tree_size(0)?],
+            next_index: 0,
+            _a: PhantomData,
+        };
+        for index in 0..leaf_count {
+            key.nodes[index] = key
+                .leaf_key(index, &constants.wots)
+                .public_key(&constants.wots)
+                .digest;
+        }
+        let (_, rows) = builder.add_final_leaves2(&mut key.nodes, leaf_count)?;
+        key.nodes.truncate(leaf_count);
+        key.nodes.extend(rows);
+
+        Ok(key)
+    }
+
+    pub fn public_key(&self) -> XmssPublicKey {
+        XmssPublicKey {
+            public_seed: self.public_seed,
+            root: *self.nodes.last().expect("tree has a root"),
+        }
+    }
+
+    /// Number of signatures left.
+    pub fn remaining(&self) -> usize {
+        self.leaf_count - self.next_index
+    }
+
+    /// Sign `message` with the next unused leaf.
+    pub fn sign(
+        &mut self,
+        message: &Fr,
+        constants: &XmssConstants<A>,
+    ) -> Result<XmssSignature, Error> {
+        if self.next_index >= self.leaf_count {
+            return Err(Error::SigningKeyExhausted(self.leaf_count));
+        }
+        let index = self.next_index;
+        self.next_index += 1;
+
+        Ok(XmssSignature {
+            index,
+            wots: self
+                .leaf_key(index, &constants.wots)
+                .sign(message, &constants.wots),
+            path: self.path(index),
+        })
+    }
+
+    fn leaf_key(&self, index: usize, constants: &WotsConstants) -> WotsSecretKey {
+        WotsSecretKey::new(
+            tweak_hash(
+                &self.public_seed,
+                LEAF_TWEAK + index as u64,
+                &self.secret_seed,
+                constants,
+            ),
+            self.public_seed,
+        )
+    }
+
+    fn path(&self, index: usize) -> Vec<Vec<Fr>> {
+        MerklePath::from_tree::<A>(&self.nodes, self.leaf_count, index)
+            .expect("the tree has every node of the path")
+            .levels
+            .into_iter()
+            .map(|level| level.siblings)
+            .collect()
+    }
+}
+
+impl XmssPublicKey {
+    pub fn verify<A: Arity<Fr>>(
+        &self,
+        message: &Fr,
+        signature: &XmssSignature,
+        constants: &XmssConstants<A>,
+    ) -> bool {
+        let recovered =
+            signature
+                .wots
+                .recover_public_key(message, &self.public_seed, &constants.wots);
+        let mut node = match recovered {
+            Ok(public_key) => public_key.digest,
+            Err(_) => return false,
+        };
+
+        let arity = A::to_usize();
+        let mut i = signature.index;
+        for siblings in signature.path.iter() {
+            if siblings.len() != arity - 1 {
+                return false;
+            }
+            let mut preimage = siblings.clone();
+            preimage.insert(i % arity, node);
+            node = Poseidon::new_with_preimage(&preimage, &constants.tree.base).hash();
+            i /= arity;
+        }
+
+        // An index beyond the leaves of the path would make several indices share a leaf.
+        i == 0 && node == self.root
+    }
+}
+
+/// Circuit enforcing that `signature` is a signature of `message` under the public key with the given root and seed,
+/// for a tree of height `height`, i.e. with `arity^height` leaves. The index of the signing leaf stays private.
+pub fn verify_circuit<CS, A>(
+    mut cs: CS,
+    root: &AllocatedNum<Bls12>,
+    public_seed: &AllocatedNum<Bls12>,
+    message: &AllocatedNum<Bls12>,
+    signature: Option<&XmssSignature>,
+    height: usize,
+    constants: &XmssConstants<A>,
+) -> Result<(), SynthesisError>
+where
+    CS: ConstraintSystem<Bls12>,
+    A: Arity<Fr>,
+{
+    let arity = A::to_usize();
+    let wots_signature = wots::alloc_signature(
+        cs.namespace(|| "wots signature"),
+        signature.map(|signature| &signature.wots),
+    )?;
+    let node = wots::recover_public_key_circuit(
+        cs.namespace(|| "wots"),
+        message,
+        &wots_signature,
+        public_seed,
+        &constants.wots,
+    )?;
+
+    let path = signature.map(|signature| MerklePath {
+        levels: signature
+            .path
+            .iter()
+            .enumerate()
+            .map(|(level, siblings)| PathLevel {
+                siblings: siblings.clone(),
+                position: signature.index / arity.pow(level as u32) % arity,
+            })
+            .collect(),
+    });
+    let path = AllocatedMerklePath::alloc(
+        cs.namespace(|| "path"),
+        path.as_ref(),
+        height,
+        &constants.tree,
+    )?;
+    let node = merkle::root_circuit(cs.namespace(|| "tree"), &node, &path, &constants.tree)?;
+
+    cs.enforce(
+        || "root",
+        |lc| lc + node.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + root.get_variable(),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalar_from_u64;
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use generic_array::typenum::{U2, U4};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_xmss() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = XmssConstants::<U2>::new();
+        let mut secret_key = XmssSecretKey::new(
+            Fr::random(&mut rng),
+            Fr::random(&mut rng),
+            4,
+            None,
+            &constants,
+        )
+        .unwrap();
+        let public_key = secret_key.public_key();
+
+        let mut signatures = Vec::new();
+        for i in 0..4 {
+            let message = scalar_from_u64::<Fr>(i);
+            let signature = secret_key.sign(&message, &constants).unwrap();
+            assert_eq!(i as usize, signature.index);
+            assert_eq!(2, signature.path.len());
+            assert!(public_key.verify(&message, &signature, &constants));
+            signatures.push((message, signature));
+        }
+        assert_eq!(0, secret_key.remaining());
+        match secret_key.sign(&Fr::one(), &constants) {
+            Err(Error::SigningKeyExhausted(4)) => (),
+            _ => panic!("exhausted key signed"),
+        }
+
+        let (message, signature) = &signatures[2];
+        assert!(!public_key.verify(&scalar_from_u64(3), signature, &constants));
+
+        let mut wrong_index = signature.clone();
+        wrong_index.index = 3;
+        assert!(!public_key.verify(message, &wrong_index, &constants));
+        wrong_index.index = 6;
+        assert!(!public_key.verify(message, &wrong_index, &constants));
+
+        let mut tampered = signature.clone();
+        tampered.path[1][0].add_assign(&Fr::one());
+        assert!(!public_key.verify(message, &tampered, &constants));
+
+        let mut truncated = signature.clone();
+        truncated.path.pop();
+        assert!(!public_key.verify(message, &truncated, &constants));
+
+        // Leaf counts must be powers of the arity.
+        assert!(
+            XmssSecretKey::new(Fr::one(), Fr::one(), 8, None, &XmssConstants::<U4>::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_xmss_circuit() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = XmssConstants::<U4>::new();
+        let mut secret_key = XmssSecretKey::new(
+            Fr::random(&mut rng),
+            Fr::random(&mut rng),
+            16,
+            None,
+            &constants,
+        )
+        .unwrap();
+        let public_key = secret_key.public_key();
+        let message = Fr::random(&mut rng);
+        secret_key.sign(&Fr::zero(), &constants).unwrap();
+        let signature = secret_key.sign(&message, &constants).unwrap();
+        assert_eq!(1, signature.index);
+
+        for (signed, valid) in [(message, true), (Fr::random(&mut rng), false)].iter() {
+            let mut cs = TestConstraintSystem::<Bls12>::new();
+            let root =
+                AllocatedNum::alloc(cs.namespace(|| "root"), || Ok(public_key.root)).unwrap();
+            let public_seed =
+                AllocatedNum::alloc(
+                    cs.namespace(|| "public seed"),
+                    || Ok(public_key.public_seed),
+                )
+                .unwrap();
+            let message = AllocatedNum::alloc(cs.namespace(|| "message"), || Ok(*signed)).unwrap();
+
+            verify_circuit(
+                cs.namespace(|| "verify"),
+                &root,
+                &public_seed,
+                &message,
+                Some(&signature),
+                2,
+                &constants,
+            )
+            .unwrap();
+            assert_eq!(*valid, cs.is_satisfied());
+        }
+    }
+}
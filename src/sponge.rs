@@ -0,0 +1,365 @@
+//! `Sponge` hashes preimages of any length by absorbing them into the rate portion of the Poseidon state.
+//!
+//! The first element of the state is the capacity and holds the domain tag, the remaining `A` elements are the rate.
+//! Elements are added into the rate one at a time, and the state is permuted whenever a full block has been absorbed
+//! and another element arrives. Before squeezing, the input is padded with a single `1` followed by as many zeros as
+//! needed to fill the block, and the state is permuted. Outputs are then read from the rate in order, permuting again
+//! each time it is exhausted.
+//!
+//! Messages which share a common prefix can be hashed by absorbing the prefix once, taking a `SpongeState` snapshot
+//! and resuming a sponge from it for each suffix. Snapshots record the fingerprint of the constants they were taken
+//! with, and cannot be resumed with any others.
+use crate::fingerprint::Fingerprint;
+use crate::hash_type::HashType;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::Error;
+use ff::{Field, ScalarEngine};
+use generic_array::GenericArray;
+
+/// Returns `Error::UnsupportedHashType` unless `constants` are of type `HashType::VariableLength` or
+/// `HashType::Custom`.
+pub(crate) fn check_constants<E, A>(constants: &PoseidonConstants<E, A>) -> Result<(), Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    match constants.hash_type {
+        HashType::VariableLength | HashType::Custom(_) => Ok(()),
+        _ => Err(Error::UnsupportedHashType(constants.hash_type.name())),
+    }
+}
+
+/// Snapshot of a `Sponge` which has absorbed a prefix. It owns its state, so it is cheap to clone and can be sent to
+/// other threads. It can only be resumed with the constants of the sponge it was taken from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpongeState<E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    elements: GenericArray<E::Fr, A::ConstantsSize>,
+    absorbed: usize,
+    fingerprint: Fingerprint,
+}
+
+impl<E, A> SpongeState<E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    /// Fingerprint of the constants the snapshot was taken with.
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+}
+
+/// Sponge over the Poseidon permutation of width `A + 1`. Its rate is the `A` elements after the capacity element, which
+/// starts as the domain tag of `constants`. Absorbed input is padded with a `1` and as many zeros as fill the last
+/// block, so inputs of different lengths never collide. Only constants of type `HashType::VariableLength`, or
+/// `HashType::Custom` for a caller-defined domain, are accepted.
+#[derive(Clone, Debug)]
+pub struct Sponge<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    state: Poseidon<'a, E, A>,
+    /// Number of elements absorbed into the current block.
+    absorbed: usize,
+    /// Position of the next element to squeeze from the rate, once squeezing has started.
+    squeezed: Option<usize>,
+}
+
+impl<'a, E, A> Sponge<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    /// Create a new sponge. `constants` must be of type `HashType::VariableLength` or `HashType::Custom`.
+    pub fn new(constants: &'a PoseidonConstants<E, A>) -> Self {
+        Self::try_new(constants).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `new`, but returns `Error::UnsupportedHashType` for constants of any other hash type.
+    pub fn try_new(constants: &'a PoseidonConstants<E, A>) -> Result<Self, Error> {
+        check_constants(constants)?;
+
+        Ok(Self {
+            state: Poseidon::new(constants),
+            absorbed: 0,
+            squeezed: None,
+        })
+    }
+
+    /// Hash `preimage` and return a single element.
+    pub fn hash(preimage: &[E::Fr], constants: &'a PoseidonConstants<E, A>) -> E::Fr {
+        Self::try_hash(preimage, constants).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `hash`, but fails like `try_new`.
+    pub fn try_hash(
+        preimage: &[E::Fr],
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<E::Fr, Error> {
+        let mut sponge = Self::try_new(constants)?;
+        for element in preimage {
+            sponge
+                .absorb(element)
+                .expect("a fresh sponge accepts any number of elements");
+        }
+        Ok(sponge.squeeze())
+    }
+
+    /// Resume a sponge from `state`. Returns `Error::FingerprintMismatch` unless `state` was taken from a sponge
+    /// using `constants`.
+    pub fn resume(
+        state: &SpongeState<E, A>,
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Self, Error> {
+        state.fingerprint.check(&constants.fingerprint())?;
+        Ok(Self::resume_unchecked(state, constants))
+    }
+
+    fn resume_unchecked(state: &SpongeState<E, A>, constants: &'a PoseidonConstants<E, A>) -> Self {
+        let mut sponge = Self::new(constants);
+        sponge.state.elements = state.elements.clone();
+        sponge.absorbed = state.absorbed;
+        sponge
+    }
+
+    /// Hash each of `suffixes` after the prefix absorbed into `prefix`. The prefix is only absorbed once, so every
+    /// whole block it fills saves one permutation per suffix. Fails like `resume`.
+    pub fn hash_with_prefix<S: AsRef<[E::Fr]>>(
+        prefix: &SpongeState<E, A>,
+        suffixes: &[S],
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Vec<E::Fr>, Error> {
+        prefix.fingerprint.check(&constants.fingerprint())?;
+
+        Ok(suffixes
+            .iter()
+            .map(|suffix| {
+                let mut sponge = Self::resume_unchecked(prefix, constants);
+                sponge
+                    .absorb_elements(suffix.as_ref())
+                    .expect("a resumed sponge accepts any number of elements");
+                sponge.squeeze()
+            })
+            .collect())
+    }
+
+    /// Take a snapshot of the absorbed prefix. If the prefix filled a whole block, the pending permutation is applied
+    /// first, so that sponges resumed from the snapshot do not each repeat it. Returns an error once squeezing has
+    /// started.
+    pub fn snapshot(&mut self) -> Result<SpongeState<E, A>, Error> {
+        if self.squeezed.is_some() {
+            return Err(Error::Other(
+                "cannot snapshot a sponge after squeezing".to_string(),
+            ));
+        }
+
+        if self.absorbed == A::to_usize() {
+            self.state.permute();
+            self.absorbed = 0;
+        }
+
+        Ok(SpongeState {
+            elements: self.state.elements.clone(),
+            absorbed: self.absorbed,
+            fingerprint: self.state.constants.fingerprint(),
+        })
+    }
+
+    /// Absorb a single element. Returns an error once squeezing has started.
+    pub fn absorb(&mut self, element: &E::Fr) -> Result<(), Error> {
+        if self.squeezed.is_some() {
+            return Err(Error::Other(
+                "cannot absorb into a sponge after squeezing".to_string(),
+            ));
+        }
+
+        if self.absorbed == A::to_usize() {
+            self.state.permute();
+            self.absorbed = 0;
+        }
+        self.state.elements[1 + self.absorbed].add_assign(element);
+        self.absorbed += 1;
+
+        Ok(())
+    }
+
+    /// Absorb all `elements`, in order.
+    pub fn absorb_elements(&mut self, elements: &[E::Fr]) -> Result<(), Error> {
+        elements.iter().try_for_each(|element| self.absorb(element))
+    }
+
+    /// Squeeze a single element. The first call pads the absorbed input, after which no more elements may be absorbed.
+    pub fn squeeze(&mut self) -> E::Fr {
+        let position = match self.squeezed {
+            None => {
+                self.pad();
+                0
+            }
+            Some(position) if position == A::to_usize() => {
+                self.state.permute();
+                0
+            }
+            Some(position) => position,
+        };
+        self.squeezed = Some(position + 1);
+
+        self.state.elements[1 + position]
+    }
+
+    /// Squeeze `n` elements.
+    pub fn squeeze_elements(&mut self, n: usize) -> Vec<E::Fr> {
+        (0..n).map(|_| self.squeeze()).collect()
+    }
+
+    fn pad(&mut self) {
+        if self.absorbed == A::to_usize() {
+            self.state.permute();
+            self.absorbed = 0;
+        }
+        self.state.elements[1 + self.absorbed].add_assign(&E::Fr::one());
+        self.state.permute();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Strength;
+    use bellperson::bls::{Bls12, Fr};
+    use generic_array::typenum::{U2, U4};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn variable_length_constants<A: Arity<Fr>>() -> PoseidonConstants<Bls12, A> {
+        PoseidonConstants::new_with_strength_and_type(Strength::Standard, HashType::VariableLength)
+    }
+
+    #[test]
+    fn test_sponge_lengths() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = variable_length_constants::<U4>();
+        let preimage = (0..13).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        // Every prefix, including the empty one and those filling whole blocks, has a distinct digest.
+        let digests = (0..=preimage.len())
+            .map(|n| Sponge::hash(&preimage[..n], &constants))
+            .collect::<Vec<_>>();
+        for (i, a) in digests.iter().enumerate() {
+            for b in digests.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+
+        // Padding is not ambiguous with an explicitly absorbed one.
+        let mut padded = preimage[..3].to_vec();
+        padded.push(Fr::one());
+        assert_ne!(
+            Sponge::hash(&preimage[..3], &constants),
+            Sponge::hash(&padded, &constants)
+        );
+    }
+
+    #[test]
+    fn test_sponge_squeeze() {
+        let constants = variable_length_constants::<U2>();
+        let preimage = vec![Fr::one(); 5];
+
+        let mut sponge = Sponge::new(&constants);
+        sponge.absorb_elements(&preimage).unwrap();
+        let squeezed = sponge.squeeze_elements(7);
+
+        assert_eq!(Sponge::hash(&preimage, &constants), squeezed[0]);
+        for (i, a) in squeezed.iter().enumerate() {
+            for b in squeezed.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+
+        assert!(sponge.absorb(&Fr::one()).is_err());
+        assert!(sponge.snapshot().is_err());
+    }
+
+    #[test]
+    fn test_sponge_prefix_snapshot() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = variable_length_constants::<U4>();
+        let suffixes = (0..6)
+            .map(|n| (0..n).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        // Prefixes which fill no, part of, exactly one and more than one block.
+        for prefix_length in 0..10 {
+            let prefix = (0..prefix_length)
+                .map(|_| Fr::random(&mut rng))
+                .collect::<Vec<_>>();
+
+            let mut sponge = Sponge::new(&constants);
+            sponge.absorb_elements(&prefix).unwrap();
+            let snapshot = sponge.snapshot().unwrap();
+            assert_send(&snapshot);
+
+            let digests = Sponge::hash_with_prefix(&snapshot, &suffixes, &constants).unwrap();
+
+            for (suffix, digest) in suffixes.iter().zip(digests.iter()) {
+                let mut preimage = prefix.clone();
+                preimage.extend(suffix);
+                assert_eq!(Sponge::hash(&preimage, &constants), *digest);
+            }
+
+            // Taking the snapshot does not change the digest of the sponge it was taken from.
+            let mut preimage = prefix.clone();
+            preimage.extend(&suffixes[3]);
+            sponge.absorb_elements(&suffixes[3]).unwrap();
+            assert_eq!(Sponge::hash(&preimage, &constants), sponge.squeeze());
+
+            let mut resumed = Sponge::resume(&snapshot, &constants).unwrap();
+            resumed.absorb_elements(&suffixes[3]).unwrap();
+            assert_eq!(Sponge::hash(&preimage, &constants), resumed.squeeze());
+        }
+    }
+
+    #[test]
+    fn test_sponge_unsupported_hash_type() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+
+        assert!(matches!(
+            Sponge::try_new(&constants),
+            Err(Error::UnsupportedHashType(_))
+        ));
+        assert!(matches!(
+            Sponge::try_hash(&[Fr::one()], &constants),
+            Err(Error::UnsupportedHashType(_))
+        ));
+    }
+
+    #[test]
+    fn test_sponge_snapshot_fingerprint() {
+        let constants = variable_length_constants::<U4>();
+        let strengthened = PoseidonConstants::<Bls12, U4>::new_with_strength_and_type(
+            Strength::Strengthened,
+            HashType::VariableLength,
+        );
+
+        let mut sponge = Sponge::new(&constants);
+        sponge.absorb_elements(&[Fr::one(); 3]).unwrap();
+        let snapshot = sponge.snapshot().unwrap();
+        assert_eq!(constants.fingerprint(), snapshot.fingerprint());
+
+        // A snapshot cannot be resumed with other constants.
+        match Sponge::resume(&snapshot, &strengthened) {
+            Err(Error::FingerprintMismatch { expected, actual }) => {
+                assert_eq!(constants.fingerprint(), expected);
+                assert_eq!(strengthened.fingerprint(), actual);
+            }
+            _ => panic!("snapshot resumed with other constants"),
+        }
+        assert!(Sponge::hash_with_prefix(&snapshot, &[[Fr::one()]], &strengthened).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Ready-made circuits for common statements about Poseidon hashes, to prove with any bellperson prover.
+//!
+//! Each circuit holds the constants it is synthesized with, and a witness which is absent when generating parameters.
+//! Circuits synthesize the same constraints with and without their witness, so parameters generated from a blank
+//! circuit prove every instance of the same shape. Their public inputs are documented with each circuit, and are
+//! what a verifier passes along with a proof, such as to `groth16::verify`.
+use crate::circuit::poseidon_hash;
+use crate::column_commitment::{
+    column_commitment_circuit, AllocatedColumnOpening, ColumnConstants, ColumnOpening,
+};
+use crate::merkle::{root_circuit, AllocatedMerklePath, MerkleConstants, MerklePath};
+use crate::poseidon::{Arity, PoseidonConstants};
+use bellperson::bls::{Bls12, Fr};
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::{Circuit, ConstraintSystem, SynthesisError};
+
+/// Knowledge of a preimage of a Poseidon digest. The public input is the digest.
+#[derive(Clone)]
+pub struct PreimageCircuit<'a, A: Arity<Fr>> {
+    pub constants: &'a PoseidonConstants<Bls12, A>,
+    /// Each element of the preimage. Its length must be one the hash type of `constants` accepts.
+    pub preimage: Vec<Option<Fr>>,
+}
+
+impl<'a, A: Arity<Fr>> Circuit<Bls12> for PreimageCircuit<'a, A> {
+    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let preimage = self
+            .preimage
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("preimage {}", i)), || {
+                    value.ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let digest = poseidon_hash(cs.namespace(|| "hash"), preimage, self.constants)?;
+        digest.inputize(cs.namespace(|| "digest"))
+    }
+}
+
+/// Membership of a leaf in a Merkle tree of height `height`. The public input is the root.
+#[derive(Clone)]
+pub struct MerkleMembershipCircuit<'a, B, S, T>
+where
+    B: Arity<Fr>,
+    S: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    pub constants: &'a MerkleConstants<B, S, T>,
+    pub height: usize,
+    pub leaf: Option<Fr>,
+    pub path: Option<MerklePath>,
+}
+
+impl<'a, B, S, T> Circuit<Bls12> for MerkleMembershipCircuit<'a, B, S, T>
+where
+    B: Arity<Fr>,
+    S: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let leaf = AllocatedNum::alloc(cs.namespace(|| "leaf"), || {
+            self.leaf.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = AllocatedMerklePath::alloc(
+            cs.namespace(|| "path"),
+            self.path.as_ref(),
+            self.height,
+            self.constants,
+        )?;
+
+        let root = root_circuit(cs.namespace(|| "root"), &leaf, &path, self.constants)?;
+        root.inputize(cs.namespace(|| "public root"))
+    }
+}
+
+/// Opening of a column committed to by `ColumnTreeBuilder`, in a column tree of height `height`. The public input is
+/// the commitment `comm_c`.
+#[derive(Clone)]
+pub struct ColumnCommitmentCircuit<'a, C, T>
+where
+    C: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    pub constants: &'a ColumnConstants<C, T>,
+    pub height: usize,
+    pub opening: Option<ColumnOpening<C>>,
+}
+
+impl<'a, C, T> Circuit<Bls12> for ColumnCommitmentCircuit<'a, C, T>
+where
+    C: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let constants = self.constants;
+        let comm_c = AllocatedNum::alloc_input(cs.namespace(|| "comm_c"), || {
+            self.opening
+                .as_ref()
+                .and_then(|opening| opening.comm_c(constants).ok())
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let opening = AllocatedColumnOpening::alloc(
+            cs.namespace(|| "opening"),
+            self.opening.as_ref(),
+            self.height,
+            constants,
+        )?;
+
+        column_commitment_circuit(
+            cs.namespace(|| "column commitment"),
+            &opening,
+            &comm_c,
+            constants,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circom::R1csRecorder;
+    use crate::hash_type::HashType;
+    use crate::poseidon::Poseidon;
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    use crate::test_trees::{build_column_tree, build_tree};
+    use crate::Strength;
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use ff::Field;
+    use generic_array::sequence::GenericSequence;
+    use generic_array::typenum::{U11, U2, U4, U8};
+    use generic_array::GenericArray;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    /// Check that `circuit` is satisfied with `public_inputs`, and has the shape of `blank`.
+    fn check_circuit<C: Circuit<Bls12> + Clone>(circuit: C, blank: C, public_inputs: &[Fr]) {
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        circuit.clone().synthesize(&mut cs).unwrap();
+        assert!(cs.is_satisfied(), "constraints not satisfied");
+        assert_eq!(1 + public_inputs.len(), cs.num_inputs());
+
+        let mut recorder = R1csRecorder::<Bls12>::new();
+        circuit.synthesize(&mut recorder).unwrap();
+        assert_eq!(
+            public_inputs.to_vec(),
+            recorder.witness().unwrap()[1..=public_inputs.len()].to_vec()
+        );
+
+        let mut blank_recorder = R1csRecorder::<Bls12>::new();
+        blank.synthesize(&mut blank_recorder).unwrap();
+        assert_eq!(cs.num_constraints(), blank_recorder.num_constraints());
+        assert_eq!(recorder.num_wires(), blank_recorder.num_wires());
+    }
+
+    #[test]
+    fn test_preimage_circuit() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let merkle = PoseidonConstants::<Bls12, U2>::new();
+        let constant_length = PoseidonConstants::<Bls12, U4>::new_with_strength_and_type(
+            Strength::Standard,
+            HashType::ConstantLength(3),
+        );
+
+        let preimage = (0..2).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        check_circuit(
+            PreimageCircuit {
+                constants: &merkle,
+                preimage: preimage.iter().cloned().map(Some).collect(),
+            },
+            PreimageCircuit {
+                constants: &merkle,
+                preimage: vec![None; 2],
+            },
+            &[Poseidon::new_with_preimage(&preimage, &merkle).hash()],
+        );
+
+        let preimage = (0..3).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        check_circuit(
+            PreimageCircuit {
+                constants: &constant_length,
+                preimage: preimage.iter().cloned().map(Some).collect(),
+            },
+            PreimageCircuit {
+                constants: &constant_length,
+                preimage: vec![None; 3],
+            },
+            &[Poseidon::new_with_preimage(&preimage, &constant_length).hash()],
+        );
+    }
+
+    #[test]
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    fn test_merkle_membership_circuit() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = MerkleConstants::<U4>::new();
+        let leaves = (0..64).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let tree = build_tree::<U4>(&leaves);
+        let root = *tree.last().unwrap();
+        let path = MerklePath::from_tree::<U4>(&tree, 64, 27).unwrap();
+
+        check_circuit(
+            MerkleMembershipCircuit {
+                constants: &constants,
+                height: 3,
+                leaf: Some(leaves[27]),
+                path: Some(path),
+            },
+            MerkleMembershipCircuit {
+                constants: &constants,
+                height: 3,
+                leaf: None,
+                path: None,
+            },
+            &[root],
+        );
+    }
+
+    #[test]
+    #[cfg(any(feature = "gpu", feature = "opencl"))]
+    fn test_column_commitment_circuit() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = ColumnConstants::<U11, U8>::new();
+        let columns = (0..8)
+            .map(|_| GenericArray::<Fr, U11>::generate(|_| Fr::random(&mut rng)))
+            .collect::<Vec<_>>();
+        let (base, tree) = build_column_tree::<U11, U8>(&columns);
+        let opening =
+            ColumnOpening::from_column_tree(columns[5], &base, &tree, 5, &constants).unwrap();
+
+        check_circuit(
+            ColumnCommitmentCircuit {
+                constants: &constants,
+                height: 1,
+                opening: Some(opening),
+            },
+            ColumnCommitmentCircuit {
+                constants: &constants,
+                height: 1,
+                opening: None,
+            },
+            &[*tree.last().unwrap()],
+        );
+    }
+}
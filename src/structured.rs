@@ -0,0 +1,528 @@
+//! Deterministic encoding of structured data as field elements, for hashing with Poseidon.
+//!
+//! `ToFieldElements` maps a value to a sequence of `Fr`s:
+//! - `bool` and unsigned integers are one element each. `usize` is encoded as a `u64`.
+//! - `Fr` is itself.
+//! - `[u8; N]` is packed into `ceil(N / 31)` elements of up to 31 little-endian bytes each.
+//! - `Vec<T>` is its length followed by the elements of each item, so it has no constant length.
+//! - Structs deriving `PoseidonHash` (with the `derive` feature) are the concatenation of their fields, in declaration
+//!   order.
+//!
+//! Types whose encoding always has the same number of elements, at most the arity, are hashed as
+//! `HashType::ConstantLength` preimages, all others with `Sponge` and `HashType::VariableLength`. See `PoseidonHash`.
+//!
+//! With `std`, `AllocateFieldElements` allocates the encoding in a circuit, constraining integers and bytes to their
+//! ranges, and `poseidon_hash_circuit` hashes it.
+//!
+//! ```ignore
+//! #[derive(PoseidonHash)]
+//! struct Sector {
+//!     id: u64,
+//!     comm_r: [u8; 32],
+//! }
+//!
+//! let constants = Sector::poseidon_constants::<U4>()?;
+//! let digest = sector.poseidon_hash(&constants)?;
+//! let allocated_digest = poseidon_hash_circuit(cs, Some(&sector), &constants)?;
+//! ```
+use crate::hash_type::HashType;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::sponge::Sponge;
+use crate::{scalar_from_u64, scalar_from_u64s, Error, DEFAULT_STRENGTH};
+use bellperson::bls::{Bls12, Fr};
+
+#[cfg(feature = "derive")]
+pub use neptune_derive::PoseidonHash;
+
+#[cfg(feature = "std")]
+pub use gadgets::{poseidon_hash_circuit, AllocateFieldElements};
+
+/// Paths used by the code generated by `#[derive(PoseidonHash)]`.
+#[doc(hidden)]
+pub mod __private {
+    pub use bellperson::bls::{Bls12, Fr};
+    #[cfg(feature = "std")]
+    pub use bellperson::gadgets::num::AllocatedNum;
+    #[cfg(feature = "std")]
+    pub use bellperson::{ConstraintSystem, SynthesisError};
+    pub use std::vec::Vec;
+}
+
+/// Number of bytes packed into each element. Any 31 bytes are less than the modulus.
+pub const BYTES_PER_ELEMENT: usize = 31;
+
+/// Encoding of values as field elements. See the module documentation.
+pub trait ToFieldElements {
+    /// Number of elements of every value, or `None` if it depends on the value.
+    const LENGTH: Option<usize>;
+
+    /// Append the elements of `self` to `elements`.
+    fn append_elements(&self, elements: &mut Vec<Fr>);
+
+    fn to_elements(&self) -> Vec<Fr> {
+        let mut elements = Vec::with_capacity(Self::LENGTH.unwrap_or(0));
+        self.append_elements(&mut elements);
+        elements
+    }
+}
+
+/// Sum of `lengths`, or `None` if any of them is `None`. Used for the `LENGTH` of derived implementations.
+pub const fn sum_lengths(lengths: &[Option<usize>]) -> Option<usize> {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < lengths.len() {
+        match lengths[i] {
+            Some(length) => sum += length,
+            None => return None,
+        }
+        i += 1;
+    }
+    Some(sum)
+}
+
+macro_rules! impl_to_field_elements_for_integer {
+    ($($t:ty),*) => {
+        $(
+            impl ToFieldElements for $t {
+                const LENGTH: Option<usize> = Some(1);
+
+                fn append_elements(&self, elements: &mut Vec<Fr>) {
+                    elements.push(scalar_from_u64(*self as u64));
+                }
+            }
+        )*
+    };
+}
+
+impl_to_field_elements_for_integer!(bool, u8, u16, u32, u64, usize);
+
+impl ToFieldElements for u128 {
+    const LENGTH: Option<usize> = Some(1);
+
+    fn append_elements(&self, elements: &mut Vec<Fr>) {
+        elements.push(scalar_from_u64s([*self as u64, (*self >> 64) as u64, 0, 0]));
+    }
+}
+
+impl ToFieldElements for Fr {
+    const LENGTH: Option<usize> = Some(1);
+
+    fn append_elements(&self, elements: &mut Vec<Fr>) {
+        elements.push(*self);
+    }
+}
+
+impl<const N: usize> ToFieldElements for [u8; N] {
+    const LENGTH: Option<usize> = Some((N + BYTES_PER_ELEMENT - 1) / BYTES_PER_ELEMENT);
+
+    fn append_elements(&self, elements: &mut Vec<Fr>) {
+        elements.extend(self.chunks(BYTES_PER_ELEMENT).map(bytes_to_element));
+    }
+}
+
+impl<T: ToFieldElements> ToFieldElements for Vec<T> {
+    const LENGTH: Option<usize> = None;
+
+    fn append_elements(&self, elements: &mut Vec<Fr>) {
+        elements.push(scalar_from_u64(self.len() as u64));
+        for item in self {
+            item.append_elements(elements);
+        }
+    }
+}
+
+/// Little-endian `bytes`, of which there are at most `BYTES_PER_ELEMENT`.
+fn bytes_to_element(bytes: &[u8]) -> Fr {
+    let mut limbs = [0u64; 4];
+    for (i, byte) in bytes.iter().enumerate() {
+        limbs[i / 8] |= (*byte as u64) << (8 * (i % 8));
+    }
+    scalar_from_u64s(limbs)
+}
+
+/// Poseidon hashing of the encoding of a value. Implemented for every `ToFieldElements` type.
+pub trait PoseidonHash: ToFieldElements {
+    /// `ConstantLength(LENGTH)` for constant-length types of between 1 and `A` elements, `VariableLength` for all
+    /// others.
+    fn hash_type<A: Arity<Fr>>() -> HashType<Fr, A> {
+        match Self::LENGTH {
+            Some(length) if length > 0 && length <= A::to_usize() => {
+                HashType::ConstantLength(length)
+            }
+            _ => HashType::VariableLength,
+        }
+    }
+
+    /// Constants for hashing values of this type with arity `A`.
+    fn poseidon_constants<A: Arity<Fr>>() -> Result<PoseidonConstants<Bls12, A>, Error> {
+        PoseidonConstants::try_new_with_strength_and_type(DEFAULT_STRENGTH, Self::hash_type())
+    }
+
+    /// Hash `self` with `constants`, which must be of the hash type `hash_type()`.
+    fn poseidon_hash<A: Arity<Fr>>(
+        &self,
+        constants: &PoseidonConstants<Bls12, A>,
+    ) -> Result<Fr, Error> {
+        check_hash_type::<Self, A>(constants)?;
+
+        let elements = self.to_elements();
+        match constants.hash_type {
+            HashType::ConstantLength(_) => {
+                Ok(Poseidon::try_new_with_preimage(&elements, constants)?.hash())
+            }
+            _ => Ok(Sponge::hash(&elements, constants)),
+        }
+    }
+}
+
+/// Returns an error unless `constants` are of the hash type `T::hash_type()`.
+fn check_hash_type<T, A>(constants: &PoseidonConstants<Bls12, A>) -> Result<(), Error>
+where
+    T: PoseidonHash + ?Sized,
+    A: Arity<Fr>,
+{
+    let expected = T::hash_type::<A>();
+    if constants.hash_type == expected {
+        Ok(())
+    } else {
+        Err(Error::InvalidHashType(format!(
+            "{} for values hashed as {}",
+            constants.hash_type.name(),
+            expected.name()
+        )))
+    }
+}
+
+impl<T: ToFieldElements> PoseidonHash for T {}
+
+#[cfg(feature = "std")]
+mod gadgets {
+    use super::*;
+    use bellperson::gadgets::boolean::AllocatedBit;
+    use bellperson::gadgets::num::AllocatedNum;
+    use bellperson::{ConstraintSystem, LinearCombination, SynthesisError};
+    use ff::{Field, PrimeField};
+
+    /// Allocation of the encoding of values in a circuit.
+    pub trait AllocateFieldElements: ToFieldElements {
+        /// Allocate the elements of `value`, or unassigned elements if `value` is `None` and the type has a constant
+        /// `LENGTH`.
+        fn alloc_elements<CS: ConstraintSystem<Bls12>>(
+            cs: CS,
+            value: Option<&Self>,
+        ) -> Result<Vec<AllocatedNum<Bls12>>, SynthesisError>;
+    }
+
+    /// Allocate `value` and constrain it to be less than `2^bits`.
+    fn alloc_range_checked<CS: ConstraintSystem<Bls12>>(
+        mut cs: CS,
+        value: Option<Fr>,
+        bits: usize,
+    ) -> Result<AllocatedNum<Bls12>, SynthesisError> {
+        let num = AllocatedNum::alloc(cs.namespace(|| "value"), || {
+            value.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let repr = value.map(|v| v.into_repr());
+        let mut lc = LinearCombination::zero();
+        let mut coeff = Fr::one();
+        for i in 0..bits {
+            let bit = AllocatedBit::alloc(
+                cs.namespace(|| format!("bit {}", i)),
+                repr.as_ref()
+                    .map(|r| (r.as_ref()[i / 64] >> (i % 64)) & 1 == 1),
+            )?;
+            lc = lc + (coeff, bit.get_variable());
+            coeff.double();
+        }
+
+        cs.enforce(
+            || "bits",
+            |_| lc,
+            |lc| lc + CS::one(),
+            |lc| lc + num.get_variable(),
+        );
+
+        Ok(num)
+    }
+
+    macro_rules! impl_allocate_field_elements_for_integer {
+        ($($t:ty => $bits:expr),*) => {
+            $(
+                impl AllocateFieldElements for $t {
+                    fn alloc_elements<CS: ConstraintSystem<Bls12>>(
+                        cs: CS,
+                        value: Option<&Self>,
+                    ) -> Result<Vec<AllocatedNum<Bls12>>, SynthesisError> {
+                        let value = value.map(|v| v.to_elements()[0]);
+                        Ok(vec![alloc_range_checked(cs, value, $bits)?])
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_allocate_field_elements_for_integer!(
+        bool => 1, u8 => 8, u16 => 16, u32 => 32, u64 => 64, usize => 64, u128 => 128
+    );
+
+    impl AllocateFieldElements for Fr {
+        fn alloc_elements<CS: ConstraintSystem<Bls12>>(
+            cs: CS,
+            value: Option<&Self>,
+        ) -> Result<Vec<AllocatedNum<Bls12>>, SynthesisError> {
+            Ok(vec![AllocatedNum::alloc(cs, || {
+                value.copied().ok_or(SynthesisError::AssignmentMissing)
+            })?])
+        }
+    }
+
+    impl<const N: usize> AllocateFieldElements for [u8; N] {
+        fn alloc_elements<CS: ConstraintSystem<Bls12>>(
+            mut cs: CS,
+            value: Option<&Self>,
+        ) -> Result<Vec<AllocatedNum<Bls12>>, SynthesisError> {
+            let elements = value.map(|v| v.to_elements());
+            (0..Self::LENGTH.expect("byte arrays have a constant length"))
+                .map(|i| {
+                    let bytes = usize::min(BYTES_PER_ELEMENT, N - i * BYTES_PER_ELEMENT);
+                    alloc_range_checked(
+                        cs.namespace(|| format!("bytes {}", i)),
+                        elements.as_ref().map(|e| e[i]),
+                        8 * bytes,
+                    )
+                })
+                .collect()
+        }
+    }
+
+    /// The number of items of a vector is part of the shape of the circuit, so vectors can only be allocated with a
+    /// value, also when generating parameters. The length prefix is allocated and constrained to that number.
+    impl<T: AllocateFieldElements> AllocateFieldElements for Vec<T> {
+        fn alloc_elements<CS: ConstraintSystem<Bls12>>(
+            mut cs: CS,
+            value: Option<&Self>,
+        ) -> Result<Vec<AllocatedNum<Bls12>>, SynthesisError> {
+            let value = value.ok_or(SynthesisError::AssignmentMissing)?;
+            let length = scalar_from_u64::<Fr>(value.len() as u64);
+
+            let allocated_length = AllocatedNum::alloc(cs.namespace(|| "length"), || Ok(length))?;
+            cs.enforce(
+                || "length is constant",
+                |lc| lc + allocated_length.get_variable(),
+                |lc| lc + CS::one(),
+                |lc| lc + (length, CS::one()),
+            );
+
+            let mut elements = vec![allocated_length];
+            for (i, item) in value.iter().enumerate() {
+                elements.extend(T::alloc_elements(
+                    cs.namespace(|| format!("item {}", i)),
+                    Some(item),
+                )?);
+            }
+            Ok(elements)
+        }
+    }
+
+    /// Allocate `value` and hash its elements with `constants`, which must be of the hash type
+    /// `T::hash_type()`, e.g. from `T::poseidon_constants()`. Returns `SynthesisError::Unsatisfiable` otherwise.
+    pub fn poseidon_hash_circuit<T, CS, A>(
+        mut cs: CS,
+        value: Option<&T>,
+        constants: &PoseidonConstants<Bls12, A>,
+    ) -> Result<AllocatedNum<Bls12>, SynthesisError>
+    where
+        T: AllocateFieldElements,
+        CS: ConstraintSystem<Bls12>,
+        A: Arity<Fr>,
+    {
+        check_hash_type::<T, A>(constants).map_err(|_| SynthesisError::Unsatisfiable)?;
+
+        let elements = T::alloc_elements(cs.namespace(|| "elements"), value)?;
+        crate::circuit::poseidon_hash(cs.namespace(|| "hash"), elements, constants)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Strength;
+    use bellperson::gadgets::num::AllocatedNum;
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use bellperson::{ConstraintSystem, SynthesisError};
+    use ff::Field;
+    use generic_array::typenum::{U2, U4, U8};
+
+    /// What `#[derive(PoseidonHash)]` generates for a struct with an integer and a byte array.
+    struct Sector {
+        id: u64,
+        comm: [u8; 40],
+    }
+
+    impl ToFieldElements for Sector {
+        const LENGTH: Option<usize> = sum_lengths(&[
+            <u64 as ToFieldElements>::LENGTH,
+            <[u8; 40] as ToFieldElements>::LENGTH,
+        ]);
+
+        fn append_elements(&self, elements: &mut Vec<Fr>) {
+            ToFieldElements::append_elements(&self.id, elements);
+            ToFieldElements::append_elements(&self.comm, elements);
+        }
+    }
+
+    impl AllocateFieldElements for Sector {
+        fn alloc_elements<CS: ConstraintSystem<Bls12>>(
+            mut cs: CS,
+            value: Option<&Self>,
+        ) -> Result<Vec<AllocatedNum<Bls12>>, SynthesisError> {
+            let mut elements = Vec::new();
+            elements.extend(<u64 as AllocateFieldElements>::alloc_elements(
+                cs.namespace(|| "id"),
+                value.map(|value| &value.id),
+            )?);
+            elements.extend(<[u8; 40] as AllocateFieldElements>::alloc_elements(
+                cs.namespace(|| "comm"),
+                value.map(|value| &value.comm),
+            )?);
+            Ok(elements)
+        }
+    }
+
+    fn sector() -> Sector {
+        let mut comm = [0u8; 40];
+        for (i, byte) in comm.iter_mut().enumerate() {
+            *byte = 255 - i as u8;
+        }
+        Sector { id: 1234, comm }
+    }
+
+    #[test]
+    fn test_to_field_elements() {
+        assert_eq!(Some(3), Sector::LENGTH);
+        assert_eq!(None, sum_lengths(&[Some(1), <Vec<u8>>::LENGTH, Some(2)]));
+
+        let sector = sector();
+        let elements = sector.to_elements();
+        assert_eq!(3, elements.len());
+        assert_eq!(scalar_from_u64::<Fr>(1234), elements[0]);
+        // Bytes are little-endian.
+        assert_eq!(
+            scalar_from_u64s([0xf8f9_fafb_fcfd_feff, 0xf0f1_f2f3_f4f5_f6f7, 0, 0]),
+            [
+                0xffu8, 0xfe, 0xfd, 0xfc, 0xfb, 0xfa, 0xf9, 0xf8, 0xf7, 0xf6, 0xf5, 0xf4, 0xf3,
+                0xf2, 0xf1, 0xf0
+            ]
+            .to_elements()[0]
+        );
+        assert_eq!(bytes_to_element(&sector.comm[31..]), elements[2]);
+
+        assert_eq!(
+            scalar_from_u64s([u64::MAX, 1, 0, 0]),
+            ((1u128 << 64) + u64::MAX as u128).to_elements()[0]
+        );
+
+        // Vectors are length-prefixed, so splitting items differently changes the encoding.
+        let nested = vec![vec![1u8, 2], vec![3]];
+        let split = vec![vec![1u8], vec![2, 3]];
+        assert_eq!(6, nested.to_elements().len());
+        assert_ne!(nested.to_elements(), split.to_elements());
+    }
+
+    #[test]
+    fn test_poseidon_hash() {
+        let sector = sector();
+        let constants = Sector::poseidon_constants::<U4>().unwrap();
+
+        assert_eq!(
+            Poseidon::new_with_preimage(&sector.to_elements(), &constants).hash(),
+            sector.poseidon_hash(&constants).unwrap()
+        );
+        assert!(sector
+            .poseidon_hash(&PoseidonConstants::<Bls12, U4>::new())
+            .is_err());
+
+        // Values longer than the arity are hashed with the sponge.
+        let constants = Sector::poseidon_constants::<U2>().unwrap();
+        assert_eq!(HashType::VariableLength, constants.hash_type);
+        assert_eq!(
+            Sponge::hash(&sector.to_elements(), &constants),
+            sector.poseidon_hash(&constants).unwrap()
+        );
+        assert!(sector
+            .poseidon_hash(&PoseidonConstants::<Bls12, U2>::new_constant_length(2))
+            .is_err());
+
+        let ids = vec![1u64, 2, 3];
+        let constants = <Vec<u64>>::poseidon_constants::<U8>().unwrap();
+        assert_eq!(
+            Sponge::hash(&ids.to_elements(), &constants),
+            ids.poseidon_hash(&constants).unwrap()
+        );
+        assert!(ids
+            .poseidon_hash(&PoseidonConstants::<Bls12, U8>::new_with_strength_and_type(
+                Strength::Standard,
+                HashType::ConstantLength(4)
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_circuit() {
+        let sector = sector();
+        let constants = Sector::poseidon_constants::<U4>().unwrap();
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let digest =
+            poseidon_hash_circuit(cs.namespace(|| "sector"), Some(&sector), &constants).unwrap();
+        assert!(cs.is_satisfied());
+        assert_eq!(
+            sector.poseidon_hash(&constants).unwrap(),
+            digest.get_value().unwrap()
+        );
+
+        // Values longer than the arity, and vectors, are hashed with the sponge.
+        let constants = Sector::poseidon_constants::<U2>().unwrap();
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let digest =
+            poseidon_hash_circuit(cs.namespace(|| "sector"), Some(&sector), &constants).unwrap();
+        assert!(cs.is_satisfied());
+        assert_eq!(
+            sector.poseidon_hash(&constants).unwrap(),
+            digest.get_value().unwrap()
+        );
+
+        let ids = vec![1u64, 2, 3];
+        let constants = <Vec<u64>>::poseidon_constants::<U2>().unwrap();
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let digest = poseidon_hash_circuit(cs.namespace(|| "ids"), Some(&ids), &constants).unwrap();
+        assert!(cs.is_satisfied());
+        assert_eq!(
+            ids.poseidon_hash(&constants).unwrap(),
+            digest.get_value().unwrap()
+        );
+        cs.set("ids/elements/length/num", scalar_from_u64(2));
+        assert!(!cs.is_satisfied());
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        assert!(<Vec<u64>>::alloc_elements(cs.namespace(|| "ids"), None).is_err());
+
+        // Constants of the wrong hash type are rejected.
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        assert!(poseidon_hash_circuit(
+            cs.namespace(|| "sector"),
+            Some(&sector),
+            &PoseidonConstants::<Bls12, U4>::new()
+        )
+        .is_err());
+
+        // Out-of-range values cannot be assigned.
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let elements = u8::alloc_elements(cs.namespace(|| "byte"), Some(&200)).unwrap();
+        assert_eq!(1, elements.len());
+        assert!(cs.is_satisfied());
+        cs.set("byte/value/num", scalar_from_u64(256));
+        assert!(!cs.is_satisfied());
+    }
+}
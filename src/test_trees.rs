@@ -0,0 +1,52 @@
+//! Trees built by `TreeBuilder` and `ColumnTreeBuilder` with the CPU batcher, as fixtures for the tests of the modules
+//! which read their output.
+use crate::batch_hasher::BatcherType;
+use crate::column_tree_builder::{ColumnTreeBuilder, ColumnTreeBuilderTrait};
+use crate::tree_builder::{TreeBuilder, TreeBuilderTrait};
+use crate::Arity;
+use bellperson::bls::Fr;
+use ff::Field;
+use generic_array::GenericArray;
+
+/// The tree over `leaves`, laid out as the leaves followed by each row above them, as `TreeBuilder` returns it when no
+/// rows are discarded.
+pub(crate) fn build_tree<A: Arity<Fr>>(leaves: &[Fr]) -> Vec<Fr> {
+    let leaf_count = leaves.len();
+    let mut data = vec![Fr::zero(); leaf_count + TreeBuilder::<A>::tree_size2(leaf_count, 0)];
+    let mut builder = TreeBuilder::<A>::new(
+        Some(BatcherType::CPU),
+        leaf_count,
+        leaf_count,
+        0,
+        Some(&mut data),
+    )
+    .expect("failed to create tree builder");
+
+    let (mut tree, rows) = builder
+        .add_final_leaves(leaves)
+        .expect("failed to build tree");
+    tree.extend(rows);
+    tree
+}
+
+/// The base row and tree `ColumnTreeBuilder` returns for `columns`.
+pub(crate) fn build_column_tree<C, T>(columns: &[GenericArray<Fr, C>]) -> (Vec<Fr>, Vec<Fr>)
+where
+    C: Arity<Fr>,
+    T: Arity<Fr>,
+{
+    let leaf_count = columns.len();
+    let mut data = vec![Fr::zero(); leaf_count + ColumnTreeBuilder::<C, T>::tree_size2(leaf_count)];
+    let mut builder = ColumnTreeBuilder::<C, T>::new(
+        Some(BatcherType::CPU),
+        leaf_count,
+        leaf_count,
+        leaf_count,
+        &mut data,
+    )
+    .expect("failed to create column tree builder");
+
+    builder
+        .add_final_columns(columns)
+        .expect("failed to build column tree")
+}
@@ -0,0 +1,359 @@
+//! Known-answer test vectors, for checking other implementations of Filecoin Poseidon against `neptune`.
+//!
+//! `generate_test_vectors` hashes deterministic inputs for each requested arity, both `Strength`s and every supported
+//! `HashType`, and `test_vectors_json` serializes the result. Each vector records its parameters, the domain tag, a
+//! fingerprint of the constants, the inputs and the digest. `check_test_vectors` recomputes every vector of a JSON
+//! file and reports the first mismatch.
+//!
+//! Field elements are encoded as `Digest`s are displayed: the 64 lowercase hex digits of the 32 little-endian bytes of
+//! their canonical integer value, without a prefix.
+//! Variable-length vectors are hashed with `Sponge`, all others with a single permutation of `Poseidon`.
+use crate::digest::Digest;
+use crate::fingerprint::Fingerprint;
+use crate::hash_type::{CType, HashType};
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::sponge::Sponge;
+use crate::{scalar_from_u64, Error, Scalar, Strength};
+use bellperson::bls::Bls12;
+use ff::Field;
+use generic_array::typenum::*;
+use serde::{Deserialize, Serialize};
+
+/// Version of the test vector format.
+pub const TEST_VECTORS_VERSION: u32 = 1;
+
+/// All arities supported by `Poseidon`.
+pub const SUPPORTED_ARITIES: [usize; 35] = [
+    2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 36,
+];
+
+/// A file of test vectors.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TestVectors {
+    pub version: u32,
+    pub vectors: Vec<TestVector>,
+}
+
+/// A single known answer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TestVector {
+    pub arity: usize,
+    /// `standard` or `strengthened`.
+    pub strength: String,
+    /// `merkle-tree`, `constant-length-<n>`, `variable-length`, `encryption` or `custom-<identifier>`.
+    pub hash_type: String,
+    pub domain_tag: String,
+    /// See `Fingerprint`.
+    pub constants_fingerprint: String,
+    pub inputs: Vec<String>,
+    pub digest: String,
+}
+
+fn strength_name(strength: Strength) -> &'static str {
+    match strength {
+        Strength::Standard => "standard",
+        Strength::Strengthened => "strengthened",
+    }
+}
+
+fn parse_strength(name: &str) -> Result<Strength, Error> {
+    match name {
+        "standard" => Ok(Strength::Standard),
+        "strengthened" => Ok(Strength::Strengthened),
+        _ => Err(Error::Other(format!("unknown strength: {}", name))),
+    }
+}
+
+fn hash_type_name<A: Arity<Scalar>>(hash_type: &HashType<Scalar, A>) -> String {
+    match hash_type {
+        HashType::MerkleTree => "merkle-tree".to_string(),
+        HashType::ConstantLength(length) => format!("constant-length-{}", length),
+        HashType::VariableLength => "variable-length".to_string(),
+        HashType::Encryption => "encryption".to_string(),
+        HashType::Custom(CType::Arbitrary(id)) => format!("custom-{}", id),
+        HashType::MerkleTreeSparse(_) | HashType::Custom(_) => {
+            unreachable!("unsupported hash types have no test vectors")
+        }
+    }
+}
+
+fn parse_hash_type<A: Arity<Scalar>>(name: &str) -> Result<HashType<Scalar, A>, Error> {
+    let hash_type = match name {
+        "merkle-tree" => HashType::MerkleTree,
+        "variable-length" => HashType::VariableLength,
+        "encryption" => HashType::Encryption,
+        _ if name.starts_with("custom-") => {
+            let id = name["custom-".len()..]
+                .parse::<u64>()
+                .map_err(|_| Error::Other(format!("unknown hash type: {}", name)))?;
+            HashType::Custom(CType::Arbitrary(id))
+        }
+        _ => {
+            let length = name
+                .strip_prefix("constant-length-")
+                .and_then(|length| length.parse::<usize>().ok())
+                .ok_or_else(|| Error::Other(format!("unknown hash type: {}", name)))?;
+            if length == 0 || length > A::to_usize() {
+                return Err(Error::Other(format!(
+                    "invalid constant length {} for arity {}",
+                    length,
+                    A::to_usize()
+                )));
+            }
+            HashType::ConstantLength(length)
+        }
+    };
+
+    Ok(hash_type)
+}
+
+/// Deterministic inputs: `1, 2, ..., length`, or their negations, which exercise the high limbs.
+fn inputs(length: usize, negate: bool) -> Vec<Scalar> {
+    (1..=length as u64)
+        .map(|i| {
+            let mut element: Scalar = scalar_from_u64(i);
+            if negate {
+                element.negate();
+            }
+            element
+        })
+        .collect()
+}
+
+fn digest<A: Arity<Scalar>>(constants: &PoseidonConstants<Bls12, A>, inputs: &[Scalar]) -> Scalar {
+    match constants.hash_type {
+        HashType::VariableLength => Sponge::hash(inputs, constants),
+        _ => Poseidon::new_with_preimage(inputs, constants).hash(),
+    }
+}
+
+fn vector<A: Arity<Scalar>>(
+    constants: &PoseidonConstants<Bls12, A>,
+    fingerprint: &Fingerprint,
+    inputs: &[Scalar],
+) -> TestVector {
+    TestVector {
+        arity: A::to_usize(),
+        strength: strength_name(constants.strength).to_string(),
+        hash_type: hash_type_name(&constants.hash_type),
+        domain_tag: Digest::new(constants.domain_tag).to_string(),
+        constants_fingerprint: fingerprint.to_string(),
+        inputs: inputs
+            .iter()
+            .map(|input| Digest::new(*input).to_string())
+            .collect(),
+        digest: Digest::new(digest(constants, inputs)).to_string(),
+    }
+}
+
+fn generate_for_arity<A: Arity<Scalar>>() -> Result<Vec<TestVector>, Error> {
+    let arity = A::to_usize();
+    let mut vectors = Vec::new();
+
+    for &strength in &[Strength::Standard, Strength::Strengthened] {
+        let mut hash_types = vec![
+            HashType::MerkleTree,
+            HashType::Encryption,
+            HashType::Custom(CType::from_domain("neptune.test-vectors")),
+        ];
+        hash_types.extend((1..=arity).map(HashType::ConstantLength));
+
+        for hash_type in hash_types {
+            let length = match hash_type {
+                HashType::ConstantLength(length) => length,
+                _ => arity,
+            };
+            let constants = PoseidonConstants::new_with_strength_and_type(strength, hash_type);
+            let fingerprint = constants.fingerprint();
+            for &negate in &[false, true] {
+                vectors.push(vector(&constants, &fingerprint, &inputs(length, negate)));
+            }
+        }
+
+        // Lengths which leave the last block empty, partial and full, over one and several blocks.
+        let constants =
+            PoseidonConstants::new_with_strength_and_type(strength, HashType::VariableLength);
+        let fingerprint = constants.fingerprint();
+        for &length in &[0, 1, arity, arity + 1, 2 * arity + 1] {
+            vectors.push(vector(&constants, &fingerprint, &inputs(length, true)));
+        }
+    }
+
+    Ok(vectors)
+}
+
+fn check_for_arity<A: Arity<Scalar>>(vector: &TestVector) -> Result<(), Error> {
+    let strength = parse_strength(&vector.strength)?;
+    let hash_type = parse_hash_type::<A>(&vector.hash_type)?;
+    let inputs = vector
+        .inputs
+        .iter()
+        .map(|input| input.parse::<Digest>().map(Scalar::from))
+        .collect::<Result<Vec<Scalar>, _>>()?;
+    let length = match hash_type {
+        HashType::ConstantLength(length) => Some(length),
+        HashType::VariableLength => None,
+        _ => Some(A::to_usize()),
+    };
+    if let Some(length) = length {
+        if length != inputs.len() {
+            return Err(Error::Other(format!(
+                "{} inputs for preimage length {}",
+                inputs.len(),
+                length
+            )));
+        }
+    }
+
+    let constants = PoseidonConstants::try_new_with_strength_and_type(strength, hash_type)?;
+    let expected = vector_with_fingerprint(&constants, &inputs);
+    let mismatch = |field: &str, expected: &str, actual: &str| -> Result<(), Error> {
+        Err(Error::Other(format!(
+            "{} mismatch: expected {}, got {}",
+            field, expected, actual
+        )))
+    };
+
+    if vector.domain_tag != expected.domain_tag {
+        return mismatch("domain tag", &vector.domain_tag, &expected.domain_tag);
+    }
+    let fingerprint: Fingerprint = vector.constants_fingerprint.parse()?;
+    fingerprint.check(&constants.fingerprint())?;
+    if vector.digest != expected.digest {
+        return mismatch("digest", &vector.digest, &expected.digest);
+    }
+
+    Ok(())
+}
+
+fn vector_with_fingerprint<A: Arity<Scalar>>(
+    constants: &PoseidonConstants<Bls12, A>,
+    inputs: &[Scalar],
+) -> TestVector {
+    vector(constants, &constants.fingerprint(), inputs)
+}
+
+/// Call `$f::<A>($args)` with the arity type `A` corresponding to `$arity`.
+macro_rules! with_arity {
+    (@dispatch $arity:expr, $f:ident($($arg:expr),*), $($n:literal => $a:ty),*) => {
+        match $arity {
+            $($n => $f::<$a>($($arg),*),)*
+            arity => Err(Error::Other(format!("unsupported arity: {}", arity))),
+        }
+    };
+    ($arity:expr, $f:ident($($arg:expr),*)) => {
+        with_arity!(@dispatch $arity, $f($($arg),*),
+            2 => U2, 3 => U3, 4 => U4, 5 => U5, 6 => U6, 7 => U7, 8 => U8, 9 => U9, 10 => U10, 11 => U11, 12 => U12,
+            13 => U13, 14 => U14, 15 => U15, 16 => U16, 17 => U17, 18 => U18, 19 => U19, 20 => U20, 21 => U21,
+            22 => U22, 23 => U23, 24 => U24, 25 => U25, 26 => U26, 27 => U27, 28 => U28, 29 => U29, 30 => U30,
+            31 => U31, 32 => U32, 33 => U33, 34 => U34, 35 => U35, 36 => U36)
+    };
+}
+
+/// Generate test vectors for each of `arities`, both strengths and every supported hash type.
+/// Pass `&SUPPORTED_ARITIES` for the full suite.
+pub fn generate_test_vectors(arities: &[usize]) -> Result<TestVectors, Error> {
+    let mut vectors = Vec::new();
+    for &arity in arities {
+        vectors.extend(with_arity!(arity, generate_for_arity())?);
+    }
+
+    Ok(TestVectors {
+        version: TEST_VECTORS_VERSION,
+        vectors,
+    })
+}
+
+/// Generate test vectors for each of `arities`, as pretty-printed JSON.
+pub fn test_vectors_json(arities: &[usize]) -> Result<String, Error> {
+    serde_json::to_string_pretty(&generate_test_vectors(arities)?)
+        .map_err(|e| Error::Other(e.to_string()))
+}
+
+/// Check every vector of `json` against this implementation, and return the number of vectors checked.
+/// The error identifies the first vector which does not match.
+pub fn check_test_vectors(json: &str) -> Result<usize, Error> {
+    let test_vectors: TestVectors =
+        serde_json::from_str(json).map_err(|e| Error::Other(e.to_string()))?;
+    if test_vectors.version != TEST_VECTORS_VERSION {
+        return Err(Error::Other(format!(
+            "unsupported test vector version: {}",
+            test_vectors.version
+        )));
+    }
+
+    for (i, vector) in test_vectors.vectors.iter().enumerate() {
+        with_arity!(vector.arity, check_for_arity(vector)).map_err(|e| {
+            Error::Other(format!(
+                "test vector {} (arity {}, {}, {}): {}",
+                i, vector.arity, vector.strength, vector.hash_type, e
+            ))
+        })?;
+    }
+
+    Ok(test_vectors.vectors.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors_round_trip() {
+        let json = test_vectors_json(&[2, 4, 8]).unwrap();
+        let test_vectors: TestVectors = serde_json::from_str(&json).unwrap();
+
+        // Per strength: two vectors each for merkle-tree, encryption, custom and every constant length, plus five
+        // variable-length vectors.
+        let expected = [2, 4, 8]
+            .iter()
+            .map(|a| 2 * (2 * (3 + a) + 5))
+            .sum::<usize>();
+        assert_eq!(expected, test_vectors.vectors.len());
+        assert_eq!(expected, check_test_vectors(&json).unwrap());
+
+        // The merkle-tree vectors agree with `Poseidon`.
+        let vector = &test_vectors.vectors[0];
+        assert_eq!(
+            (2, "merkle-tree"),
+            (vector.arity, vector.hash_type.as_str())
+        );
+        let constants = PoseidonConstants::<Bls12, U2>::new();
+        let preimage = [scalar_from_u64(1), scalar_from_u64(2)];
+        assert_eq!(
+            Digest::new(Poseidon::new_with_preimage(&preimage, &constants).hash()).to_string(),
+            vector.digest
+        );
+
+        // Elements are encoded as `Digest`s.
+        assert_eq!(format!("01{}", "00".repeat(31)), vector.inputs[0]);
+    }
+
+    #[test]
+    fn test_vectors_mismatch() {
+        let mut test_vectors = generate_test_vectors(&[2]).unwrap();
+        let mut vector = test_vectors.vectors[3].clone();
+
+        vector.digest = vector.inputs[0].clone();
+        test_vectors.vectors.push(vector);
+        let json = serde_json::to_string(&test_vectors).unwrap();
+
+        let err = check_test_vectors(&json).unwrap_err().to_string();
+        assert!(err.contains("digest mismatch"), "{}", err);
+
+        // A vector recorded with other constants is rejected, even if its digest is right.
+        let mut vector = test_vectors.vectors[0].clone();
+        vector.constants_fingerprint = test_vectors.vectors[2].constants_fingerprint.clone();
+        test_vectors.vectors = vec![vector];
+        let json = serde_json::to_string(&test_vectors).unwrap();
+        let err = check_test_vectors(&json).unwrap_err().to_string();
+        assert!(err.contains("fingerprint mismatch"), "{}", err);
+
+        let mut vector = test_vectors.vectors[0].clone();
+        vector.arity = 37;
+        test_vectors.vectors = vec![vector];
+        let json = serde_json::to_string(&test_vectors).unwrap();
+        assert!(check_test_vectors(&json).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+//! Round-by-round traces of the Poseidon permutation, for debugging and test-vector generation.
+//!
+//! A `Trace` records the state after each step of every round. `HashMode::Correct` adds the uncompressed round keys
+//! before each S-box layer, while `HashMode::OptimizedStatic` adds compressed round keys after it and replaces the
+//! partial rounds' MDS matrices with sparse factors, so intermediate states of the two modes differ. They do agree on
+//! the input to every S-box layer: the whole state for full rounds, the first element for partial rounds. `align`
+//! pairs the corresponding entries of two traces, and `first_divergence` finds the first pair which differs. A port
+//! of the permutation which records the same states can be checked against `neptune` the same way.
+use crate::poseidon::{Arity, PoseidonConstants};
+use core::ops::Range;
+use ff::ScalarEngine;
+
+/// The step of a round after which a state was recorded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceStep {
+    /// Round keys were added to the state.
+    AddRoundKeys,
+    /// The S-box layer was applied, including any round keys added inside the S-box.
+    SBox,
+    /// The state was multiplied by the MDS matrix.
+    Mds,
+    /// The state was multiplied by the matrix preceding the sparse matrices.
+    PreSparseMatrix,
+    /// The state was multiplied by a sparse matrix.
+    SparseMatrix,
+}
+
+impl TraceStep {
+    /// Whether the step ends a round.
+    pub fn is_linear_layer(&self) -> bool {
+        match self {
+            TraceStep::Mds | TraceStep::PreSparseMatrix | TraceStep::SparseMatrix => true,
+            TraceStep::AddRoundKeys | TraceStep::SBox => false,
+        }
+    }
+}
+
+/// The state after `step` of `round`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry<F> {
+    pub round: usize,
+    pub step: TraceStep,
+    pub elements: Vec<F>,
+}
+
+/// Recorded states of one or more permutations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace<F> {
+    pub entries: Vec<TraceEntry<F>>,
+    half_full_rounds: usize,
+    partial_rounds: usize,
+    round: usize,
+}
+
+impl<F: Copy + PartialEq> Trace<F> {
+    pub(crate) fn new(half_full_rounds: usize, partial_rounds: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            half_full_rounds,
+            partial_rounds,
+            round: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, step: TraceStep, elements: &[F]) {
+        self.entries.push(TraceEntry {
+            round: self.round,
+            step,
+            elements: elements.to_vec(),
+        });
+        if step.is_linear_layer() {
+            // Each permutation of a multi-permutation trace starts again at round 0.
+            self.round = (self.round + 1) % self.rounds();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.round = 0;
+    }
+
+    /// Total number of rounds of one permutation.
+    pub fn rounds(&self) -> usize {
+        2 * self.half_full_rounds + self.partial_rounds
+    }
+
+    /// Whether `round` is a partial round.
+    pub fn is_partial_round(&self, round: usize) -> bool {
+        round >= self.half_full_rounds && round < self.half_full_rounds + self.partial_rounds
+    }
+}
+
+/// Two entries, one from a `Correct` and one from an `OptimizedStatic` trace, which must hold equal states.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alignment {
+    /// The round whose S-box input the entries hold. `rounds()` denotes the output of the permutation.
+    pub round: usize,
+    /// Index of the entry in the `Correct` trace.
+    pub correct: usize,
+    /// Index of the entry in the `OptimizedStatic` trace.
+    pub optimized: usize,
+    /// Only the first element is comparable, because the round is partial.
+    pub first_element_only: bool,
+}
+
+/// Pair the entries of a trace of a single permutation recorded with `HashMode::Correct` with those of a trace of the
+/// same permutation recorded with `HashMode::OptimizedStatic`.
+pub fn align<F: Copy + PartialEq>(correct: &Trace<F>, optimized: &Trace<F>) -> Vec<Alignment> {
+    // `Correct` adds the round keys of each round before its S-box layer.
+    let correct_inputs = correct
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.step == TraceStep::AddRoundKeys)
+        .map(|(i, entry)| (entry.round, i));
+
+    // `OptimizedStatic` only adds round keys before the first S-box layer. Later S-box layers take the output of the
+    // previous round's linear layer.
+    let optimized_inputs =
+        optimized
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| match entry.step {
+                TraceStep::AddRoundKeys => Some((entry.round, i)),
+                step if step.is_linear_layer() => Some((entry.round + 1, i)),
+                _ => None,
+            });
+
+    let mut alignments = correct_inputs
+        .zip(optimized_inputs)
+        .take_while(|((correct_round, _), (optimized_round, _))| {
+            correct_round == optimized_round && *correct_round < correct.rounds()
+        })
+        .map(|((round, correct_index), (_, optimized_index))| Alignment {
+            round,
+            correct: correct_index,
+            optimized: optimized_index,
+            first_element_only: correct.is_partial_round(round),
+        })
+        .collect::<Vec<_>>();
+
+    // Both modes end with the same output.
+    if !correct.entries.is_empty() && !optimized.entries.is_empty() {
+        alignments.push(Alignment {
+            round: correct.rounds(),
+            correct: correct.entries.len() - 1,
+            optimized: optimized.entries.len() - 1,
+            first_element_only: false,
+        });
+    }
+
+    alignments
+}
+
+/// Find the first aligned pair of entries whose states differ.
+pub fn first_divergence<F: Copy + PartialEq>(
+    correct: &Trace<F>,
+    optimized: &Trace<F>,
+) -> Option<Alignment> {
+    align(correct, optimized).into_iter().find(|alignment| {
+        let a = &correct.entries[alignment.correct].elements;
+        let b = &optimized.entries[alignment.optimized].elements;
+        if alignment.first_element_only {
+            a[0] != b[0]
+        } else {
+            a != b
+        }
+    })
+}
+
+/// Ranges of the round constants consumed by `round`: first into `round_constants`, as added by `HashMode::Correct`
+/// before the round's S-box layer, then into `compressed_round_constants`, as added by `HashMode::OptimizedStatic`.
+/// Compressed round constants are added after the S-box layer, and are derived from the uncompressed constants of the
+/// following round(s). The first round additionally adds `compressed_round_constants[0..width]` before its S-box layer,
+/// which equal the uncompressed constants of that round.
+pub fn round_constant_ranges<E, A>(
+    constants: &PoseidonConstants<E, A>,
+    round: usize,
+) -> (Range<usize>, Range<usize>)
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    let width = constants.width();
+    let half = constants.half_full_rounds;
+    let partial = constants.partial_rounds;
+    let rounds = constants.full_rounds + partial;
+    assert!(round < rounds, "round out of range");
+
+    let uncompressed = round * width..(round + 1) * width;
+
+    // The compressed constants start with the pre-S-box keys of the first round.
+    let first_half_start = width;
+    let partial_start = first_half_start + half * width;
+    let second_half_start = partial_start + partial;
+
+    let compressed = if round < half {
+        let start = first_half_start + round * width;
+        start..start + width
+    } else if round < half + partial {
+        let start = partial_start + round - half;
+        start..start + 1
+    } else if round < rounds - 1 {
+        let start = second_half_start + (round - half - partial) * width;
+        start..start + width
+    } else {
+        // No round keys follow the last S-box layer.
+        second_half_start + (half - 1) * width..second_half_start + (half - 1) * width
+    };
+
+    (uncompressed, compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poseidon::{HashMode, Poseidon};
+    use crate::Strength;
+    use bellperson::bls::{Bls12, Fr};
+    use ff::Field;
+    use generic_array::typenum::{U11, U2, U4, U8};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn traces<A: Arity<Fr>>(
+        constants: &PoseidonConstants<Bls12, A>,
+        mode: HashMode,
+    ) -> (Fr, Trace<Fr>) {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let preimage = (0..A::to_usize())
+            .map(|_| Fr::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        Poseidon::new_with_preimage(&preimage, constants).hash_in_mode_traced(mode)
+    }
+
+    fn test_align_aux<A: Arity<Fr>>(strength: Strength) {
+        let constants = PoseidonConstants::<Bls12, A>::new_with_strength(strength);
+        let rounds = constants.full_rounds + constants.partial_rounds;
+
+        let (correct_digest, correct) = traces(&constants, HashMode::Correct);
+        let (optimized_digest, optimized) = traces(&constants, HashMode::OptimizedStatic);
+        let (dynamic_digest, dynamic) = traces(&constants, HashMode::OptimizedDynamic);
+        assert_eq!(correct_digest, optimized_digest);
+        assert_eq!(correct_digest, dynamic_digest);
+
+        // Every mode ends each round with one linear layer, the last of which holds the output.
+        for trace in &[&correct, &optimized, &dynamic] {
+            let linear_layers = trace
+                .entries
+                .iter()
+                .filter(|entry| entry.step.is_linear_layer())
+                .collect::<Vec<_>>();
+            assert_eq!(rounds, linear_layers.len());
+            assert_eq!(rounds - 1, linear_layers[rounds - 1].round);
+            assert_eq!(correct_digest, trace.entries.last().unwrap().elements[1]);
+        }
+
+        let alignments = align(&correct, &optimized);
+        assert_eq!(rounds + 1, alignments.len());
+        for (round, alignment) in alignments.iter().enumerate() {
+            assert_eq!(round, alignment.round);
+            assert_eq!(
+                correct.is_partial_round(round),
+                alignment.first_element_only
+            );
+        }
+        assert_eq!(None, first_divergence(&correct, &optimized));
+
+        // A fault in any round is attributed to that round.
+        for round in &[0, constants.half_full_rounds, rounds - 1] {
+            let alignment = &alignments[*round];
+            let mut faulty = optimized.clone();
+            faulty.entries[alignment.optimized].elements[0].add_assign(&Fr::one());
+
+            assert_eq!(
+                Some(alignment),
+                first_divergence(&correct, &faulty).as_ref()
+            );
+        }
+    }
+
+    #[test]
+    fn test_align() {
+        test_align_aux::<U2>(Strength::Standard);
+        test_align_aux::<U4>(Strength::Standard);
+        test_align_aux::<U8>(Strength::Standard);
+        test_align_aux::<U11>(Strength::Standard);
+        test_align_aux::<U4>(Strength::Strengthened);
+    }
+
+    #[test]
+    fn test_round_constant_ranges() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let rounds = constants.full_rounds + constants.partial_rounds;
+
+        let mut uncompressed_end = 0;
+        let mut compressed_end = constants.width();
+        for round in 0..rounds {
+            let (uncompressed, compressed) = round_constant_ranges(&constants, round);
+            assert_eq!(uncompressed_end, uncompressed.start);
+            assert_eq!(compressed_end, compressed.start);
+            uncompressed_end = uncompressed.end;
+            compressed_end = compressed.end;
+        }
+        assert_eq!(constants.round_constants.len(), uncompressed_end);
+        assert_eq!(constants.compressed_round_constants.len(), compressed_end);
+
+        // The first round's pre-S-box keys are not compressed.
+        assert_eq!(
+            constants.round_constants[..constants.width()],
+            constants.compressed_round_constants[..constants.width()]
+        );
+    }
+}
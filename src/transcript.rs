@@ -0,0 +1,471 @@
+//! `Transcript` implements Fiat-Shamir challenge derivation on top of the Poseidon permutation.
+//!
+//! Every item absorbed into the transcript is preceded by a label element which encodes the kind of operation
+//! (protocol, append, challenge), the label's length and the label bytes themselves. Labels are therefore never zero,
+//! which makes the zero padding added before each challenge unambiguous. The transcript state is a single field
+//! element which is chained through every permutation, as the first element of its preimage.
+//!
+//! `TranscriptCircuit` performs exactly the same operations in-circuit, so a prover using `Transcript` and a verifier
+//! circuit using `TranscriptCircuit` derive identical challenges.
+use crate::circuit::{poseidon_hash_elts, Elt};
+use crate::hash_type::HashType;
+use crate::poseidon::{Arity, Poseidon, PoseidonConstants};
+use crate::{scalar_from_u64, Error};
+use bellperson::gadgets::boolean::Boolean;
+use bellperson::gadgets::num::AllocatedNum;
+use bellperson::{ConstraintSystem, SynthesisError};
+use ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
+
+/// Maximum length in bytes of a transcript label.
+pub const MAX_LABEL_LENGTH: usize = 30;
+
+/// The operation a label element introduces. Its discriminant occupies the top byte of the label element.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LabelOp {
+    Protocol = 1,
+    Append = 2,
+    AppendMany = 3,
+    Challenge = 4,
+}
+
+/// Encode `label` as a single field element: bytes `0..30` hold the label, byte 30 its length and byte 31 the
+/// operation. The result is always non-zero.
+///
+/// # Panics
+///
+/// Panics if `label` is longer than `MAX_LABEL_LENGTH` bytes.
+fn label_element<Fr: PrimeField>(op: LabelOp, label: &[u8]) -> Fr {
+    assert!(
+        label.len() <= MAX_LABEL_LENGTH,
+        "transcript label must be at most {} bytes",
+        MAX_LABEL_LENGTH
+    );
+
+    let mut repr = Fr::Repr::default();
+    let mut bytes = vec![0u8; repr.as_ref().len() * 8];
+    bytes[..label.len()].copy_from_slice(label);
+    bytes[MAX_LABEL_LENGTH] = label.len() as u8;
+    bytes[MAX_LABEL_LENGTH + 1] = op as u8;
+
+    repr.read_le(bytes.as_slice())
+        .expect("label buffer has the size of a field element");
+    Fr::from_repr(repr).expect("label element is smaller than the modulus")
+}
+
+/// Returns `Error::UnsupportedHashType` unless `constants` hash a full preimage.
+fn check_constants<E, A>(constants: &PoseidonConstants<E, A>) -> Result<(), Error>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    assert!(
+        A::to_usize() >= 2,
+        "transcript requires an arity of at least 2"
+    );
+    match constants.hash_type {
+        HashType::ConstantLength(_) | HashType::VariableLength => {
+            Err(Error::UnsupportedHashType(constants.hash_type.name()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Native Fiat-Shamir transcript.
+///
+/// The `constants` determine the domain tag of every permutation. They must hash a full preimage of `A` elements
+/// (e.g. `HashType::MerkleTree`), and should be dedicated to the transcript.
+#[derive(Clone, Debug)]
+pub struct Transcript<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    state: E::Fr,
+    pending: Vec<E::Fr>,
+    constants: &'a PoseidonConstants<E, A>,
+}
+
+impl<'a, E, A> Transcript<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    /// Create a new transcript for the protocol identified by `protocol_label`.
+    pub fn new(protocol_label: &[u8], constants: &'a PoseidonConstants<E, A>) -> Self {
+        Self::try_new(protocol_label, constants).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `new`, but returns `Error::UnsupportedHashType` for constants which do not hash a full preimage.
+    pub fn try_new(
+        protocol_label: &[u8],
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Self, Error> {
+        check_constants(constants)?;
+
+        let mut transcript = Self {
+            state: E::Fr::zero(),
+            pending: Vec::with_capacity(A::to_usize() - 1),
+            constants,
+        };
+        transcript.absorb(label_element(LabelOp::Protocol, protocol_label));
+        Ok(transcript)
+    }
+
+    /// Append a single labelled scalar.
+    pub fn append_scalar(&mut self, label: &[u8], scalar: &E::Fr) {
+        self.absorb(label_element(LabelOp::Append, label));
+        self.absorb(*scalar);
+    }
+
+    /// Append a labelled sequence of scalars. The length of the sequence is absorbed too.
+    pub fn append_scalars(&mut self, label: &[u8], scalars: &[E::Fr]) {
+        self.absorb(label_element(LabelOp::AppendMany, label));
+        self.absorb(scalar_from_u64(scalars.len() as u64));
+        for scalar in scalars {
+            self.absorb(*scalar);
+        }
+    }
+
+    /// Squeeze a labelled challenge scalar.
+    pub fn challenge_scalar(&mut self, label: &[u8]) -> E::Fr {
+        self.absorb(label_element(LabelOp::Challenge, label));
+        self.flush();
+        self.state
+    }
+
+    /// Squeeze a labelled challenge and return its `n` least-significant bits, in little-endian order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is greater than the capacity of the field.
+    pub fn challenge_bits(&mut self, label: &[u8], n: usize) -> Vec<bool> {
+        assert!(
+            n <= E::Fr::CAPACITY as usize,
+            "cannot squeeze more challenge bits than the field capacity"
+        );
+        let challenge = self.challenge_scalar(label).into_repr();
+        let limbs = challenge.as_ref();
+
+        (0..n)
+            .map(|i| (limbs[i / 64] >> (i % 64)) & 1 == 1)
+            .collect()
+    }
+
+    fn absorb(&mut self, element: E::Fr) {
+        self.pending.push(element);
+        if self.pending.len() == A::to_usize() - 1 {
+            self.permute();
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            self.pending.resize(A::to_usize() - 1, E::Fr::zero());
+            self.permute();
+        }
+    }
+
+    fn permute(&mut self) {
+        let mut preimage = Vec::with_capacity(A::to_usize());
+        preimage.push(self.state);
+        preimage.extend(self.pending.drain(..));
+
+        self.state = Poseidon::new_with_preimage(&preimage, self.constants).hash();
+    }
+}
+
+/// In-circuit counterpart of `Transcript`.
+pub struct TranscriptCircuit<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    /// Constant until the first permutation.
+    state: Elt<E>,
+    /// Labels and lengths are constants, so they cost no constraints in the first round of their permutation.
+    pending: Vec<Elt<E>>,
+    permutations: usize,
+    constants: &'a PoseidonConstants<E, A>,
+}
+
+impl<'a, E, A> TranscriptCircuit<'a, E, A>
+where
+    E: ScalarEngine,
+    A: Arity<E::Fr>,
+{
+    /// Create a new transcript circuit for the protocol identified by `protocol_label`.
+    pub fn new<CS: ConstraintSystem<E>>(
+        cs: CS,
+        protocol_label: &[u8],
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Self, SynthesisError> {
+        check_constants(constants).unwrap_or_else(|e| panic!("{}", e));
+        Self::new_unchecked(cs, protocol_label, constants)
+    }
+
+    /// Like `new`, but returns `Error::UnsupportedHashType` for constants which do not hash a full preimage, and
+    /// `Error::Other` if synthesis fails.
+    pub fn try_new<CS: ConstraintSystem<E>>(
+        cs: CS,
+        protocol_label: &[u8],
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Self, Error> {
+        check_constants(constants)?;
+        Self::new_unchecked(cs, protocol_label, constants).map_err(|e| Error::Other(e.to_string()))
+    }
+
+    fn new_unchecked<CS: ConstraintSystem<E>>(
+        mut cs: CS,
+        protocol_label: &[u8],
+        constants: &'a PoseidonConstants<E, A>,
+    ) -> Result<Self, SynthesisError> {
+        let mut transcript = Self {
+            state: Elt::num_from_fr::<CS>(E::Fr::zero()),
+            pending: Vec::with_capacity(A::to_usize() - 1),
+            permutations: 0,
+            constants,
+        };
+        transcript.absorb_label(
+            cs.namespace(|| "protocol"),
+            LabelOp::Protocol,
+            protocol_label,
+        )?;
+        Ok(transcript)
+    }
+
+    /// Append a single labelled scalar.
+    pub fn append_scalar<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        label: &[u8],
+        scalar: &AllocatedNum<E>,
+    ) -> Result<(), SynthesisError> {
+        self.absorb_label(cs.namespace(|| "label"), LabelOp::Append, label)?;
+        self.absorb(cs.namespace(|| "scalar"), Elt::Allocated(scalar.clone()))
+    }
+
+    /// Append a labelled sequence of scalars. The length of the sequence is absorbed too.
+    pub fn append_scalars<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        label: &[u8],
+        scalars: &[AllocatedNum<E>],
+    ) -> Result<(), SynthesisError> {
+        self.absorb_label(cs.namespace(|| "label"), LabelOp::AppendMany, label)?;
+        let length = Elt::num_from_fr::<CS>(scalar_from_u64(scalars.len() as u64));
+        self.absorb(cs.namespace(|| "absorb length"), length)?;
+        for (i, scalar) in scalars.iter().enumerate() {
+            self.absorb(
+                cs.namespace(|| format!("scalar {}", i)),
+                Elt::Allocated(scalar.clone()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Squeeze a labelled challenge scalar.
+    pub fn challenge_scalar<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        label: &[u8],
+    ) -> Result<AllocatedNum<E>, SynthesisError> {
+        self.absorb_label(cs.namespace(|| "label"), LabelOp::Challenge, label)?;
+        self.flush(cs.namespace(|| "flush"))?;
+        self.state
+            .ensure_allocated(&mut cs.namespace(|| "challenge"), true)
+    }
+
+    /// Squeeze a labelled challenge and return its `n` least-significant bits, in little-endian order.
+    /// The challenge is decomposed strictly, so the bits are unique.
+    pub fn challenge_bits<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        label: &[u8],
+        n: usize,
+    ) -> Result<Vec<Boolean>, SynthesisError> {
+        assert!(
+            n <= E::Fr::CAPACITY as usize,
+            "cannot squeeze more challenge bits than the field capacity"
+        );
+        let challenge = self.challenge_scalar(cs.namespace(|| "challenge"), label)?;
+        let mut bits = challenge.to_bits_le_strict(cs.namespace(|| "challenge bits"))?;
+        bits.truncate(n);
+        Ok(bits)
+    }
+
+    fn absorb_label<CS: ConstraintSystem<E>>(
+        &mut self,
+        mut cs: CS,
+        op: LabelOp,
+        label: &[u8],
+    ) -> Result<(), SynthesisError> {
+        let element = Elt::num_from_fr::<CS>(label_element(op, label));
+        self.absorb(cs.namespace(|| "absorb label"), element)
+    }
+
+    fn absorb<CS: ConstraintSystem<E>>(
+        &mut self,
+        cs: CS,
+        element: Elt<E>,
+    ) -> Result<(), SynthesisError> {
+        self.pending.push(element);
+        if self.pending.len() == A::to_usize() - 1 {
+            self.permute(cs)?;
+        }
+        Ok(())
+    }
+
+    fn flush<CS: ConstraintSystem<E>>(&mut self, mut cs: CS) -> Result<(), SynthesisError> {
+        if !self.pending.is_empty() {
+            for _ in self.pending.len()..A::to_usize() - 1 {
+                self.pending.push(Elt::num_from_fr::<CS>(E::Fr::zero()));
+            }
+            self.permute(cs.namespace(|| "permute"))?;
+        }
+        Ok(())
+    }
+
+    fn permute<CS: ConstraintSystem<E>>(&mut self, mut cs: CS) -> Result<(), SynthesisError> {
+        let mut preimage = Vec::with_capacity(A::to_usize());
+        preimage.push(self.state.clone());
+        preimage.extend(self.pending.drain(..));
+
+        self.state = Elt::Allocated(poseidon_hash_elts(
+            cs.namespace(|| format!("permutation {}", self.permutations)),
+            preimage,
+            self.constants,
+        )?);
+        self.permutations += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Strength;
+    use bellperson::bls::{Bls12, Fr};
+    use bellperson::util_cs::test_cs::TestConstraintSystem;
+    use generic_array::typenum::{U2, U4, U8};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_transcript_circuit_matches_native() {
+        test_transcript_circuit_matches_native_aux::<U2>();
+        test_transcript_circuit_matches_native_aux::<U4>();
+        test_transcript_circuit_matches_native_aux::<U8>();
+    }
+
+    fn test_transcript_circuit_matches_native_aux<A: Arity<Fr>>() {
+        let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
+        let constants = PoseidonConstants::<Bls12, A>::new();
+        let scalars = (0..5).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+
+        let mut transcript = Transcript::new(b"neptune-test", &constants);
+        transcript.append_scalar(b"commitment", &scalars[0]);
+        let c1 = transcript.challenge_scalar(b"alpha");
+        transcript.append_scalars(b"openings", &scalars[1..]);
+        let c2 = transcript.challenge_scalar(b"beta");
+        let bits = transcript.challenge_bits(b"index", 20);
+
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+        let allocated = scalars
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("scalar {}", i)), || Ok(*s)).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut circuit =
+            TranscriptCircuit::new(cs.namespace(|| "transcript"), b"neptune-test", &constants)
+                .unwrap();
+        circuit
+            .append_scalar(cs.namespace(|| "commitment"), b"commitment", &allocated[0])
+            .unwrap();
+        let cc1 = circuit
+            .challenge_scalar(cs.namespace(|| "alpha"), b"alpha")
+            .unwrap();
+        circuit
+            .append_scalars(cs.namespace(|| "openings"), b"openings", &allocated[1..])
+            .unwrap();
+        let cc2 = circuit
+            .challenge_scalar(cs.namespace(|| "beta"), b"beta")
+            .unwrap();
+        let circuit_bits = circuit
+            .challenge_bits(cs.namespace(|| "index"), b"index", 20)
+            .unwrap();
+
+        assert!(cs.is_satisfied(), "constraints not satisfied");
+        assert_eq!(c1, cc1.get_value().unwrap());
+        assert_eq!(c2, cc2.get_value().unwrap());
+        assert_eq!(
+            bits,
+            circuit_bits
+                .iter()
+                .map(|b| b.get_value().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_transcript_domain_separation() {
+        let constants = PoseidonConstants::<Bls12, U4>::new();
+        let one = Fr::one();
+
+        let challenge = |protocol: &[u8], label: &[u8], challenge_label: &[u8]| {
+            let mut t = Transcript::new(protocol, &constants);
+            t.append_scalar(label, &one);
+            t.challenge_scalar(challenge_label)
+        };
+
+        let base = challenge(b"protocol", b"x", b"c");
+        assert_ne!(base, challenge(b"protocol2", b"x", b"c"));
+        assert_ne!(base, challenge(b"protocol", b"y", b"c"));
+        assert_ne!(base, challenge(b"protocol", b"x", b"d"));
+        assert_ne!(base, challenge(b"protocol", b"x\0", b"c"));
+
+        // Appending a single scalar differs from appending a sequence of one.
+        let mut t = Transcript::new(b"protocol", &constants);
+        t.append_scalars(b"x", &[one]);
+        assert_ne!(base, t.challenge_scalar(b"c"));
+
+        // Successive challenges differ, even with the same label.
+        let mut t = Transcript::new(b"protocol", &constants);
+        let c1 = t.challenge_scalar(b"c");
+        let c2 = t.challenge_scalar(b"c");
+        assert_ne!(c1, c2);
+    }
+
+    #[test]
+    fn test_transcript_unsupported_hash_type() {
+        let constants = PoseidonConstants::<Bls12, U4>::new_with_strength_and_type(
+            Strength::Standard,
+            HashType::ConstantLength(4),
+        );
+        let mut cs = TestConstraintSystem::<Bls12>::new();
+
+        assert!(matches!(
+            Transcript::try_new(b"protocol", &constants),
+            Err(Error::UnsupportedHashType(_))
+        ));
+        assert!(matches!(
+            TranscriptCircuit::try_new(&mut cs, b"protocol", &constants),
+            Err(Error::UnsupportedHashType(_))
+        ));
+        assert_eq!(0, cs.num_constraints());
+    }
+
+    #[test]
+    fn test_label_element() {
+        let a = label_element::<Fr>(LabelOp::Append, b"");
+        let b = label_element::<Fr>(LabelOp::Challenge, b"");
+        assert!(!a.is_zero());
+        assert_ne!(a, b);
+        assert_ne!(
+            label_element::<Fr>(LabelOp::Append, b"a"),
+            label_element::<Fr>(LabelOp::Append, b"a\0")
+        );
+    }
+}
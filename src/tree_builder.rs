@@ -1,10 +1,11 @@
 use crate::batch_hasher::{Batcher, BatcherType};
+use crate::codec;
 use crate::error::Error;
+use crate::fingerprint::Fingerprint;
 use crate::poseidon::{Poseidon, PoseidonConstants};
-use crate::{Arity, BatchHasher};
+use crate::{Arity, BatchHasher, Fingerprinted};
 use bellperson::bls::{Bls12, Fr};
 use ff::Field;
-use generic_array::GenericArray;
 use log::{error, info};
 #[cfg(all(feature = "gpu", not(target_os = "macos")))]
 use rust_gpu_tools::opencl::GPUSelector;
@@ -86,24 +87,6 @@ where
     }
 }
 
-fn as_generic_arrays<'a, A: Arity<Fr>>(vec: &'a [Fr]) -> &'a [GenericArray<Fr, A>] {
-    // It is a programmer error to call `as_generic_arrays` on a vector whose underlying data cannot be divided
-    // into an even number of `GenericArray<Fr, Arity>`.
-    assert_eq!(
-        0,
-        (vec.len() * std::mem::size_of::<Fr>()) % std::mem::size_of::<GenericArray<Fr, A>>()
-    );
-
-    // This block does not affect the underlying `Fr`s. It just groups them into `GenericArray`s of length `Arity`.
-    // We know by the assertion above that `vec` can be evenly divided into these units.
-    unsafe {
-        std::slice::from_raw_parts(
-            vec.as_ptr() as *const () as *const GenericArray<Fr, A>,
-            vec.len() / A::to_usize(),
-        )
-    }
-}
-
 impl<'a, TreeArity> TreeBuilder<'a, TreeArity>
 where
     TreeArity: Arity<Fr>,
@@ -129,12 +112,21 @@ where
             rows_to_discard,
         };
 
+        // Rows hashed by the batcher and by `tree_constants` must agree.
+        if let Some(batcher) = &builder.tree_batcher {
+            builder.fingerprint().check(&batcher.fingerprint())?;
+        }
+
         // Cannot discard the base row or the root.
-        assert!(rows_to_discard < builder.tree_height());
+        let tree_height = builder.try_tree_height()?;
+        if rows_to_discard >= tree_height {
+            return Err(Error::InvalidRowsToDiscard {
+                rows_to_discard,
+                tree_height,
+            });
+        }
 
-        // This will panic if leaf_count is not compatible with tree arity.
-        // That is the desired behavior so such a programmer error is caught at development time.
-        let c = builder.tree_size(rows_to_discard);
+        let c = builder.try_tree_size(rows_to_discard)?;
         info!("TreeBuilder new, tree size:{}, rows_to_discard:{}, leaf_count:{}", c, rows_to_discard, leaf_count);
         Ok(builder)
     }
@@ -180,7 +172,7 @@ where
                         };
 
                         let preimages =
-                            as_generic_arrays::<TreeArity>(&tree_data[batch_start..batch_end]);
+                            codec::as_generic_arrays::<TreeArity>(&tree_data[batch_start..batch_end])?;
 
                         batcher.hash2(&preimages, out)?;
 
@@ -256,7 +248,7 @@ where
                         };
 
                         let preimages =
-                            as_generic_arrays::<TreeArity>(&data[batch_start..batch_end]);
+                            codec::as_generic_arrays::<TreeArity>(&data[batch_start..batch_end])?;
 
                         batcher.hash2(&preimages, out)?;
 
@@ -293,39 +285,33 @@ where
         Ok((base_row, tree_to_keep))
     }
 
+    /// Fingerprint of the constants the tree is hashed with.
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.tree_constants.fingerprint()
+    }
+
     /// `tree_size` returns the number of nodes in the tree to cache.
     /// This excludes the base row and the following `rows_to_discard` rows.
+    /// # Panics
+    ///
+    /// Panics if the leaf count is not a power of the arity. See `try_tree_size`.
     pub fn tree_size(&self, rows_to_discard: usize) -> usize {
-        let arity = TreeArity::to_usize();
-
-        let mut tree_size = 0;
-        let mut current_row_size = self.leaf_count;
-
-        // Exclude the base row, along with the rows to be discarded.
-        let mut remaining_rows_to_exclude = rows_to_discard + 1;
-
-        while current_row_size >= 1 {
-            if remaining_rows_to_exclude > 0 {
-                remaining_rows_to_exclude -= 1;
-            } else {
-                tree_size += current_row_size;
-            }
-            if current_row_size != 1 {
-                assert_eq!(
-                    0,
-                    current_row_size % arity,
-                    "Tree leaf count {} is not a power of arity {}.",
-                    self.leaf_count,
-                    arity
-                )
-            }
-            current_row_size /= arity;
-        }
+        self.try_tree_size(rows_to_discard)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        tree_size
+    pub fn try_tree_size(&self, rows_to_discard: usize) -> Result<usize, Error> {
+        Self::try_tree_size2(self.leaf_count, rows_to_discard)
     }
 
+    /// # Panics
+    ///
+    /// Panics if `leaf_count` is not a power of the arity. See `try_tree_size2`.
     pub fn tree_size2(leaf_count:usize, rows_to_discard: usize) -> usize {
+        Self::try_tree_size2(leaf_count, rows_to_discard).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_tree_size2(leaf_count: usize, rows_to_discard: usize) -> Result<usize, Error> {
         let arity = TreeArity::to_usize();
 
         let mut tree_size = 0;
@@ -340,22 +326,23 @@ where
             } else {
                 tree_size += current_row_size;
             }
-            if current_row_size != 1 {
-                assert_eq!(
-                    0,
-                    current_row_size % arity,
-                    "Tree leaf count {} is not a power of arity {}.",
-                    leaf_count,
-                    arity
-                )
+            if current_row_size != 1 && current_row_size % arity != 0 {
+                return Err(Error::InvalidLeafCount { leaf_count, arity });
             }
             current_row_size /= arity;
         }
 
-        tree_size
+        Ok(tree_size)
     }
 
+    /// # Panics
+    ///
+    /// Panics if the leaf count is not a power of the arity. See `try_tree_height`.
     pub fn tree_height(&self) -> usize {
+        self.try_tree_height().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_tree_height(&self) -> Result<usize, Error> {
         let arity = TreeArity::to_usize();
 
         let mut tree_height = 0;
@@ -365,17 +352,16 @@ where
         while current_row_size >= 1 {
             if current_row_size != 1 {
                 tree_height += 1;
-                assert_eq!(
-                    0,
-                    current_row_size % arity,
-                    "Tree leaf count {} is not a power of arity {}.",
-                    self.leaf_count,
-                    arity
-                );
+                if current_row_size % arity != 0 {
+                    return Err(Error::InvalidLeafCount {
+                        leaf_count: self.leaf_count,
+                        arity,
+                    });
+                }
             }
             current_row_size /= arity;
         }
-        tree_height
+        Ok(tree_height)
     }
 
     // Compute root of tree composed of all identical columns. For use in checking correctness of GPU tree-building
@@ -402,6 +388,25 @@ mod tests {
     use ff::Field;
     use generic_array::typenum::U8;
 
+    #[test]
+    fn test_tree_builder_invalid() {
+        match TreeBuilder::<U8>::new(None, 100, 32, 0, None) {
+            Err(Error::InvalidLeafCount {
+                leaf_count: 100,
+                arity: 8,
+            }) => (),
+            _ => panic!("leaf count which is not a power of the arity was accepted"),
+        }
+        match TreeBuilder::<U8>::new(None, 512, 32, 3, None) {
+            Err(Error::InvalidRowsToDiscard {
+                rows_to_discard: 3,
+                tree_height: 3,
+            }) => (),
+            _ => panic!("rows to discard including the root were accepted"),
+        }
+        assert!(TreeBuilder::<U8>::try_tree_size2(64, 0).is_ok());
+    }
+
     #[test]
     fn test_tree_builder() {
         // 16KiB tree has 512 leaves.
@@ -434,6 +439,10 @@ mod tests {
                 &mut db
             )
             .unwrap();
+            assert_eq!(
+                PoseidonConstants::<Bls12, U8>::new().fingerprint(),
+                builder.fingerprint()
+            );
 
             // Simplify computing the expected root.
             let constant_element = Fr::zero();
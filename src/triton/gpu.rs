@@ -1,8 +1,10 @@
 use super::cl;
+use crate::codec;
 use crate::error::Error;
+use crate::fingerprint::Fingerprint;
 use crate::hash_type::HashType;
 use crate::poseidon::PoseidonConstants;
-use crate::{Arity, BatchHasher, Strength, DEFAULT_STRENGTH};
+use crate::{Arity, BatchHasher, Fingerprinted, Strength, DEFAULT_STRENGTH};
 use bellperson::bls::{Bls12, Fr, FrRepr};
 use ff::{PrimeField, PrimeFieldDecodingError};
 use generic_array::{typenum, ArrayLength, GenericArray};
@@ -48,13 +50,14 @@ enum BatcherState {
 impl BatcherState {
     /// Create a new state for use in batch hashing preimages of `Arity` elements.
     /// State is an opaque pointer supplied to the corresponding GPU entry point when processing a batch.
-    fn new<A: Arity<Fr>>(ctx: Arc<Mutex<FutharkContext>>) -> Result<Self, Error> {
+    /// The fingerprint of the constants the state was initialized with is returned alongside it.
+    fn new<A: Arity<Fr>>(ctx: Arc<Mutex<FutharkContext>>) -> Result<(Self, Fingerprint), Error> {
         Self::new_with_strength::<A>(ctx, DEFAULT_STRENGTH)
     }
     fn new_with_strength<A: Arity<Fr>>(
         ctx: Arc<Mutex<FutharkContext>>,
         strength: Strength,
-    ) -> Result<Self, Error> {
+    ) -> Result<(Self, Fingerprint), Error> {
         let mut ctx = ctx.lock().unwrap();
         Ok(match A::to_usize() {
             size if size == 2 => init_hash2(&mut ctx, strength)?,
@@ -109,6 +112,8 @@ pub struct GPUBatchHasher<A> {
     /// If `tree_builder_state` is provided, use it to build the final 64MiB tree on the GPU with one call.
     tree_builder_state: Option<T864MState>,
     max_batch_size: usize,
+    /// Fingerprint of the constants the GPU state was initialized from.
+    fingerprint: Fingerprint,
     _a: PhantomData<A>,
 }
 
@@ -130,11 +135,14 @@ where
         strength: Strength,
         max_batch_size: usize,
     ) -> Result<Self, Error> {
+        let (state, fingerprint) =
+            BatcherState::new_with_strength::<A>(Arc::clone(&ctx), strength)?;
         let new = Self {
             ctx: Arc::clone(&ctx),
-            state: BatcherState::new_with_strength::<A>(Arc::clone(&ctx), strength)?,
+            state,
             tree_builder_state: None,
             max_batch_size,
+            fingerprint,
             _a: PhantomData::<A>,
         };
 
@@ -191,6 +199,15 @@ where
     }
 }
 
+impl<A> Fingerprinted for GPUBatchHasher<A>
+where
+    A: Arity<Fr>,
+{
+    fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+}
+
 #[derive(Debug)]
 struct GPUConstants<A>(PoseidonConstants<Bls12, A>)
 where
@@ -223,18 +240,31 @@ where
     }
 
     fn sparse_matrixes(&self, ctx: &FutharkContext) -> Result<Array_u64_3d, Error> {
-        let sparse_matrixes = &self.0.sparse_matrixes;
+        array_u64_3d_from_frs_2d(ctx, &self.sparse_rows())
+    }
 
-        let frs_2d: Vec<Vec<Fr>> = sparse_matrixes
+    fn sparse_rows(&self) -> Vec<Vec<Fr>> {
+        self.0
+            .sparse_matrixes
             .iter()
             .map(|m| {
                 let mut x = m.w_hat.clone();
                 x.extend(m.v_rest.clone());
                 x.into_iter().collect()
             })
-            .collect();
+            .collect()
+    }
+
+    /// Fingerprint of the limbs passed to the Futhark entry points by the methods above.
+    fn fingerprint(&self) -> Fingerprint {
+        let c = &self.0;
+        let mut limbs = frs_to_u64s(&[c.domain_tag]);
+        limbs.extend(frs_to_u64s(&c.compressed_round_constants));
+        limbs.extend(frs_2d_to_u64s(&c.mds_matrices.m));
+        limbs.extend(frs_2d_to_u64s(&c.pre_sparse_matrix));
+        limbs.extend(frs_2d_to_u64s(&self.sparse_rows()));
 
-        array_u64_3d_from_frs_2d(ctx, &frs_2d)
+        Fingerprint::of_kernel_limbs::<Fr>(c.width(), c.full_rounds, c.partial_rounds, &limbs)
     }
 }
 
@@ -321,54 +351,6 @@ fn unpack_fr_array(vec_shape: (Vec<u64>, &[i64])) -> Result<Vec<Fr>, Error> {
         .map_err(|_| Error::DecodingError)
 }
 
-fn unpack_fr_array_from_monts<'a>(monts: &'a [u64]) -> Result<&'a [Fr], Error> {
-    let fr_size = 4;
-    let fr_count = monts.len() / fr_size;
-    assert_eq!(
-        std::mem::size_of::<Fr>(),
-        fr_size * std::mem::size_of::<u64>()
-    );
-
-    if monts.len() % fr_size != 0 {
-        return Err(Error::Other(
-            "wrong size monts to convert to Frs".to_string(),
-        ));
-    }
-
-    let frs = unsafe { std::slice::from_raw_parts(monts.as_ptr() as *const () as _, fr_count) };
-
-    Ok(frs)
-}
-
-fn as_mont_u64s<'a, U: ArrayLength<Fr>>(vec: &'a [GenericArray<Fr, U>]) -> &'a [u64] {
-    let fr_size = 4; // Number of limbs in Fr.
-    assert_eq!(
-        fr_size * std::mem::size_of::<u64>(),
-        std::mem::size_of::<Fr>(),
-        "fr size changed"
-    );
-
-    unsafe {
-        std::slice::from_raw_parts(
-            vec.as_ptr() as *const () as *const u64,
-            vec.len() * fr_size * U::to_usize(),
-        )
-    }
-}
-
-fn frs_as_mont_u64s<'a>(vec: &'a [Fr]) -> &'a [u64] {
-    let fr_size = 4; // Number of limbs in Fr.
-    assert_eq!(
-        fr_size * std::mem::size_of::<u64>(),
-        std::mem::size_of::<Fr>(),
-        "fr size changed"
-    );
-
-    unsafe {
-        std::slice::from_raw_parts(vec.as_ptr() as *const () as *const u64, vec.len() * fr_size)
-    }
-}
-
 fn as_u64s<U: ArrayLength<Fr>>(vec: &[GenericArray<Fr, U>]) -> Vec<u64> {
     if vec.len() == 0 {
         return Vec::new();
@@ -385,7 +367,11 @@ fn as_u64s<U: ArrayLength<Fr>>(vec: &[GenericArray<Fr, U>]) -> Vec<u64> {
     safely
 }
 
-fn init_hash2(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherState, Error> {
+/// Returns the state, and the fingerprint of the constants it was initialized with.
+fn init_hash2(
+    ctx: &mut FutharkContext,
+    strength: Strength,
+) -> Result<(BatcherState, Fingerprint), Error> {
     let constants = GPUConstants(PoseidonConstants::<Bls12, U2>::new_with_strength(strength));
     match strength {
         Strength::Standard => {
@@ -398,7 +384,7 @@ fn init_hash2(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSta
                     constants.sparse_matrixes(&ctx)?,
                 )
                 .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
-            Ok(BatcherState::Arity2(state))
+            Ok((BatcherState::Arity2(state), constants.fingerprint()))
         }
         Strength::Strengthened => {
             let state = ctx
@@ -410,12 +396,16 @@ fn init_hash2(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSta
                     constants.sparse_matrixes(&ctx)?,
                 )
                 .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
-            Ok(BatcherState::Arity2s(state))
+            Ok((BatcherState::Arity2s(state), constants.fingerprint()))
         }
     }
 }
 
-fn init_hash8(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherState, Error> {
+/// Returns the state, and the fingerprint of the constants it was initialized with.
+fn init_hash8(
+    ctx: &mut FutharkContext,
+    strength: Strength,
+) -> Result<(BatcherState, Fingerprint), Error> {
     let constants = GPUConstants(PoseidonConstants::<Bls12, U8>::new_with_strength(strength));
     match strength {
         Strength::Standard => {
@@ -429,7 +419,7 @@ fn init_hash8(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSta
                 )
                 .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
-            Ok(BatcherState::Arity8(state))
+            Ok((BatcherState::Arity8(state), constants.fingerprint()))
         }
         Strength::Strengthened => {
             let state = ctx
@@ -442,12 +432,16 @@ fn init_hash8(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSta
                 )
                 .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
-            Ok(BatcherState::Arity8s(state))
+            Ok((BatcherState::Arity8s(state), constants.fingerprint()))
         }
     }
 }
 
-fn init_hash11(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherState, Error> {
+/// Returns the state, and the fingerprint of the constants it was initialized with.
+fn init_hash11(
+    ctx: &mut FutharkContext,
+    strength: Strength,
+) -> Result<(BatcherState, Fingerprint), Error> {
     let constants = GPUConstants(PoseidonConstants::<Bls12, U11>::new_with_strength(strength));
 
     match strength {
@@ -462,7 +456,7 @@ fn init_hash11(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSt
                 )
                 .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
-            Ok(BatcherState::Arity11(state))
+            Ok((BatcherState::Arity11(state), constants.fingerprint()))
         }
         Strength::Strengthened => {
             let state = ctx
@@ -475,7 +469,7 @@ fn init_hash11(ctx: &mut FutharkContext, strength: Strength) -> Result<BatcherSt
                 )
                 .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
-            Ok(BatcherState::Arity11s(state))
+            Ok((BatcherState::Arity11s(state), constants.fingerprint()))
         }
     }
 }
@@ -489,7 +483,7 @@ where
     A: Arity<Fr>,
 {
     assert_eq!(2, A::to_usize());
-    let flat_preimages = as_mont_u64s(preimages);
+    let flat_preimages = codec::frs_as_mont_u64s(codec::generic_arrays_as_frs(preimages));
     let input = Array_u64_1d::from_vec(*ctx, &flat_preimages, &[flat_preimages.len() as i64, 1])
         .map_err(|_| Error::Other("could not convert".to_string()))?;
 
@@ -498,7 +492,7 @@ where
         .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
     let (vec, _shape) = res.to_vec()?;
-    let frs = unpack_fr_array_from_monts(vec.as_slice())?;
+    let frs = codec::mont_u64s_as_frs(vec.as_slice())?;
 
     Ok((frs.to_vec(), state))
 }
@@ -512,7 +506,7 @@ where
     A: Arity<Fr>,
 {
     assert_eq!(8, A::to_usize());
-    let flat_preimages = as_mont_u64s(preimages);
+    let flat_preimages = codec::frs_as_mont_u64s(codec::generic_arrays_as_frs(preimages));
     let input = Array_u64_1d::from_vec(*ctx, &flat_preimages, &[flat_preimages.len() as i64, 1])
         .map_err(|_| Error::Other("could not convert".to_string()))?;
 
@@ -521,7 +515,7 @@ where
         .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
     let (vec, _shape) = res.to_vec()?;
-    let frs = unpack_fr_array_from_monts(vec.as_slice())?;
+    let frs = codec::mont_u64s_as_frs(vec.as_slice())?;
 
     Ok((frs.to_vec(), state))
 }
@@ -535,7 +529,7 @@ where
     A: Arity<Fr>,
 {
     assert_eq!(11, A::to_usize());
-    let flat_preimages = as_mont_u64s(preimages);
+    let flat_preimages = codec::frs_as_mont_u64s(codec::generic_arrays_as_frs(preimages));
     let input = Array_u64_1d::from_vec(*ctx, &flat_preimages, &[flat_preimages.len() as i64, 1])
         .map_err(|_| Error::Other("could not convert".to_string()))?;
 
@@ -544,7 +538,7 @@ where
         .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
     let (vec, _shape) = res.to_vec()?;
-    let frs = unpack_fr_array_from_monts(vec.as_slice())?;
+    let frs = codec::mont_u64s_as_frs(vec.as_slice())?;
 
     Ok((frs.to_vec(), state))
 }
@@ -558,7 +552,7 @@ where
     A: Arity<Fr>,
 {
     assert_eq!(2, A::to_usize());
-    let flat_preimages = as_mont_u64s(preimages);
+    let flat_preimages = codec::frs_as_mont_u64s(codec::generic_arrays_as_frs(preimages));
     let input = Array_u64_1d::from_vec(*ctx, &flat_preimages, &[flat_preimages.len() as i64, 1])
         .map_err(|_| Error::Other("could not convert".to_string()))?;
 
@@ -567,7 +561,7 @@ where
         .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
     let (vec, _shape) = res.to_vec()?;
-    let frs = unpack_fr_array_from_monts(vec.as_slice())?;
+    let frs = codec::mont_u64s_as_frs(vec.as_slice())?;
 
     Ok((frs.to_vec(), state))
 }
@@ -581,7 +575,7 @@ where
     A: Arity<Fr>,
 {
     assert_eq!(8, A::to_usize());
-    let flat_preimages = as_mont_u64s(preimages);
+    let flat_preimages = codec::frs_as_mont_u64s(codec::generic_arrays_as_frs(preimages));
     let input = Array_u64_1d::from_vec(*ctx, &flat_preimages, &[flat_preimages.len() as i64, 1])
         .map_err(|_| Error::Other("could not convert".to_string()))?;
 
@@ -590,7 +584,7 @@ where
         .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
     let (vec, _shape) = res.to_vec()?;
-    let frs = unpack_fr_array_from_monts(vec.as_slice())?;
+    let frs = codec::mont_u64s_as_frs(vec.as_slice())?;
 
     Ok((frs.to_vec(), state))
 }
@@ -604,7 +598,7 @@ where
     A: Arity<Fr>,
 {
     assert_eq!(11, A::to_usize());
-    let flat_preimages = as_mont_u64s(preimages);
+    let flat_preimages = codec::frs_as_mont_u64s(codec::generic_arrays_as_frs(preimages));
     let input = Array_u64_1d::from_vec(*ctx, &flat_preimages, &[flat_preimages.len() as i64, 1])
         .map_err(|_| Error::Other("could not convert".to_string()))?;
 
@@ -613,7 +607,7 @@ where
         .map_err(|e| Error::GPUError(format!("{:?}", e)))?;
 
     let (vec, _shape) = res.to_vec()?;
-    let frs = unpack_fr_array_from_monts(vec.as_slice())?;
+    let frs = codec::mont_u64s_as_frs(vec.as_slice())?;
 
     Ok((frs.to_vec(), state))
 }
@@ -643,7 +637,7 @@ mod tests {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
         let ctx = cl::default_futhark_context().unwrap();
         let mut state = if let BatcherState::Arity2(s) =
-            init_hash2(&mut ctx.lock().unwrap(), Strength::Standard).unwrap()
+            init_hash2(&mut ctx.lock().unwrap(), Strength::Standard).unwrap().0
         {
             s
         } else {
@@ -660,6 +654,7 @@ mod tests {
         let mut simple_hasher =
             SimplePoseidonBatchHasher::<U2>::new_with_strength(Strength::Standard, batch_size)
                 .unwrap();
+        assert_eq!(simple_hasher.fingerprint(), gpu_hasher.fingerprint());
 
         let preimages = (0..batch_size)
             .map(|_| GenericArray::<Fr, U2>::generate(|_| Fr::random(&mut rng)))
@@ -680,7 +675,7 @@ mod tests {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
         let ctx = cl::default_futhark_context().unwrap();
         let mut state = if let BatcherState::Arity2s(s) =
-            init_hash2(&mut ctx.lock().unwrap(), Strength::Strengthened).unwrap()
+            init_hash2(&mut ctx.lock().unwrap(), Strength::Strengthened).unwrap().0
         {
             s
         } else {
@@ -716,7 +711,7 @@ mod tests {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
         let ctx = cl::default_futhark_context().unwrap();
         let mut state = if let BatcherState::Arity8(s) =
-            init_hash8(&mut ctx.lock().unwrap(), Strength::Standard).unwrap()
+            init_hash8(&mut ctx.lock().unwrap(), Strength::Standard).unwrap().0
         {
             s
         } else {
@@ -752,7 +747,7 @@ mod tests {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
         let ctx = cl::default_futhark_context().unwrap();
         let mut state = if let BatcherState::Arity8s(s) =
-            init_hash8(&mut ctx.lock().unwrap(), Strength::Strengthened).unwrap()
+            init_hash8(&mut ctx.lock().unwrap(), Strength::Strengthened).unwrap().0
         {
             s
         } else {
@@ -788,7 +783,7 @@ mod tests {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
         let ctx = cl::default_futhark_context().unwrap();
         let mut state = if let BatcherState::Arity11(s) =
-            init_hash11(&mut ctx.lock().unwrap(), Strength::Standard).unwrap()
+            init_hash11(&mut ctx.lock().unwrap(), Strength::Standard).unwrap().0
         {
             s
         } else {
@@ -824,7 +819,7 @@ mod tests {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
         let ctx = cl::default_futhark_context().unwrap();
         let mut state = if let BatcherState::Arity11s(s) =
-            init_hash11(&mut ctx.lock().unwrap(), Strength::Strengthened).unwrap()
+            init_hash11(&mut ctx.lock().unwrap(), Strength::Strengthened).unwrap().0
         {
             s
         } else {
@@ -859,7 +854,7 @@ mod tests {
         let mut rng = XorShiftRng::from_seed(crate::TEST_SEED);
         let ctx = cl::default_futhark_context().unwrap();
         let mut state = if let BatcherState::Arity8(s) =
-            init_hash8(&mut ctx.lock().unwrap(), Strength::Standard).unwrap()
+            init_hash8(&mut ctx.lock().unwrap(), Strength::Standard).unwrap().0
         {
             s
         } else {